@@ -0,0 +1,459 @@
+//! Conversion of `ELBRecord`s into Apache Arrow `RecordBatch`es.
+//!
+//! Every batch has the same schema: one column per [`ELBRecordField`](../enum.ELBRecordField.html)
+//! named after the field's snake_case name, in the order the fields appear in a record.
+//!
+//! | Column | Arrow type |
+//! |--------|------------|
+//! | `timestamp` | `Timestamp(Microsecond, "UTC")` |
+//! | `elb_name`, `request_method`, `request_http_version`, `ssl_cipher`, `ssl_protocol` | `Dictionary(Int32, Utf8)` |
+//! | `client_address`, `backend_address` | `Utf8` or `FixedSizeBinary(6)`, see [`AddressEncoding`](enum.AddressEncoding.html) |
+//! | `*_processing_time` | `Float32` |
+//! | `elb_status_code`, `backend_status_code` | `UInt16` |
+//! | `received_bytes`, `sent_bytes` | `UInt64` |
+//! | `request_url`, `user_agent` | `Utf8` |
+//!
+//! Fields recorded as `-` are null.
+//!
+//! With the `geoip` feature, a builder created with [`with_geo_columns`]
+//! (struct.ELBRecordBatchBuilder.html#method.with_geo_columns) adds a nullable column per
+//! [`GeoField`](../geoip/enum.GeoField.html) after the record's columns: `client_country` and
+//! `client_as_organization` as `Dictionary(Int32, Utf8)`, `client_city` as `Utf8` and
+//! `client_asn` as `UInt32`.
+
+use std::sync::Arc;
+
+#[cfg(feature = "geoip")]
+use arrow_array::builder::UInt32Builder;
+use arrow_array::builder::{FixedSizeBinaryBuilder, Float32Builder, StringBuilder,
+                           StringDictionaryBuilder, TimestampMicrosecondBuilder, UInt16Builder,
+                           UInt64Builder};
+use arrow_array::types::Int32Type;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+
+#[cfg(feature = "geoip")]
+use geoip::{GeoField, GeoInfo, GeoRecord};
+use {ELBRecord, ELBRecordField, epoch_micros};
+
+/// How client and backend addresses are stored.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressEncoding {
+    /// `"ip:port"` strings.
+    Utf8,
+    /// Six bytes: the four octets of the IPv4 address followed by the port in network byte
+    /// order.
+    FixedBinary,
+}
+
+const ADDRESS_BYTE_WIDTH: i32 = 6;
+
+/// The schema of the batches built with `address_encoding`.
+pub fn schema(address_encoding: AddressEncoding) -> Schema {
+    let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let address = match address_encoding {
+        AddressEncoding::Utf8 => DataType::Utf8,
+        AddressEncoding::FixedBinary => DataType::FixedSizeBinary(ADDRESS_BYTE_WIDTH),
+    };
+
+    let fields: Vec<Field> = ELBRecordField::ALL.iter()
+        .map(|field| {
+            let (data_type, nullable) = match *field {
+                ELBRecordField::Timestamp => {
+                    (DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), false)
+                }
+                ELBRecordField::ELBName => (dictionary.clone(), false),
+                ELBRecordField::ClientAddress => (address.clone(), false),
+                ELBRecordField::BackendAddress => (address.clone(), true),
+                ELBRecordField::RequestProcessingTime |
+                ELBRecordField::BackendProcessingTime |
+                ELBRecordField::ResponseProcessingTime => (DataType::Float32, false),
+                ELBRecordField::ELBStatusCode => (DataType::UInt16, false),
+                ELBRecordField::BackendStatusCode => (DataType::UInt16, true),
+                ELBRecordField::ReceivedBytes |
+                ELBRecordField::SentBytes => (DataType::UInt64, false),
+                ELBRecordField::RequestMethod |
+                ELBRecordField::RequestHTTPVersion |
+                ELBRecordField::SSLCipher |
+                ELBRecordField::SSLProtocol => (dictionary.clone(), true),
+                ELBRecordField::RequestURL |
+                ELBRecordField::UserAgent => (DataType::Utf8, true),
+            };
+            Field::new(field.snake_case_name(), data_type, nullable)
+        })
+        .collect();
+
+    Schema::new(fields)
+}
+
+/// Convert `records` into a single batch.
+pub fn to_record_batch(records: &[ELBRecord],
+                       address_encoding: AddressEncoding)
+                       -> Result<RecordBatch, ArrowError> {
+    let mut builder = ELBRecordBatchBuilder::with_capacity(address_encoding, records.len());
+    for record in records {
+        builder.append(record)?;
+    }
+    builder.finish()
+}
+
+/// Accumulates records and converts them into a `RecordBatch`.
+///
+/// The builder can be reused: [`finish`](#method.finish) resets it so batches can be built from
+/// a stream of records without holding the records themselves.
+pub struct ELBRecordBatchBuilder {
+    schema: SchemaRef,
+    address_encoding: AddressEncoding,
+    len: usize,
+    timestamp: TimestampMicrosecondBuilder,
+    elb_name: StringDictionaryBuilder<Int32Type>,
+    client_address: AddressBuilder,
+    backend_address: AddressBuilder,
+    request_processing_time: Float32Builder,
+    backend_processing_time: Float32Builder,
+    response_processing_time: Float32Builder,
+    elb_status_code: UInt16Builder,
+    backend_status_code: UInt16Builder,
+    received_bytes: UInt64Builder,
+    sent_bytes: UInt64Builder,
+    request_method: StringDictionaryBuilder<Int32Type>,
+    request_url: StringBuilder,
+    request_http_version: StringDictionaryBuilder<Int32Type>,
+    user_agent: StringBuilder,
+    ssl_cipher: StringDictionaryBuilder<Int32Type>,
+    ssl_protocol: StringDictionaryBuilder<Int32Type>,
+    #[cfg(feature = "geoip")]
+    geo: Option<GeoColumnBuilders>,
+}
+
+#[cfg(feature = "geoip")]
+struct GeoColumnBuilders {
+    country: StringDictionaryBuilder<Int32Type>,
+    city: StringBuilder,
+    asn: UInt32Builder,
+    as_organization: StringDictionaryBuilder<Int32Type>,
+}
+
+#[cfg(feature = "geoip")]
+impl GeoColumnBuilders {
+    fn append(&mut self, info: &GeoInfo) {
+        self.country.append_option(info.country);
+        self.city.append_option(info.city);
+        self.asn.append_option(info.asn);
+        self.as_organization.append_option(info.as_organization);
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![Arc::new(self.country.finish()),
+             Arc::new(self.city.finish()),
+             Arc::new(self.asn.finish()),
+             Arc::new(self.as_organization.finish())]
+    }
+}
+
+enum AddressBuilder {
+    Utf8(StringBuilder),
+    FixedBinary(FixedSizeBinaryBuilder),
+}
+
+impl AddressBuilder {
+    fn new(address_encoding: AddressEncoding, capacity: usize) -> AddressBuilder {
+        match address_encoding {
+            AddressEncoding::Utf8 => {
+                AddressBuilder::Utf8(StringBuilder::with_capacity(capacity, capacity * 20))
+            }
+            AddressEncoding::FixedBinary => {
+                let builder = FixedSizeBinaryBuilder::with_capacity(capacity, ADDRESS_BYTE_WIDTH);
+                AddressBuilder::FixedBinary(builder)
+            }
+        }
+    }
+
+    fn append(&mut self, address: Option<::std::net::SocketAddrV4>) -> Result<(), ArrowError> {
+        match (self, address) {
+            (&mut AddressBuilder::Utf8(ref mut builder), Some(address)) => {
+                builder.append_value(address.to_string())
+            }
+            (&mut AddressBuilder::FixedBinary(ref mut builder), Some(address)) => {
+                let (octets, port) = (address.ip().octets(), address.port().to_be_bytes());
+                builder.append_value([octets[0], octets[1], octets[2], octets[3], port[0],
+                                      port[1]])?
+            }
+            (&mut AddressBuilder::Utf8(ref mut builder), None) => builder.append_null(),
+            (&mut AddressBuilder::FixedBinary(ref mut builder), None) => builder.append_null(),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match *self {
+            AddressBuilder::Utf8(ref mut builder) => Arc::new(builder.finish()),
+            AddressBuilder::FixedBinary(ref mut builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+impl ELBRecordBatchBuilder {
+    pub fn new(address_encoding: AddressEncoding) -> ELBRecordBatchBuilder {
+        ELBRecordBatchBuilder::with_capacity(address_encoding, 1024)
+    }
+
+    /// A builder with room for `capacity` records before it has to reallocate.
+    pub fn with_capacity(address_encoding: AddressEncoding,
+                         capacity: usize)
+                         -> ELBRecordBatchBuilder {
+        ELBRecordBatchBuilder {
+            schema: Arc::new(schema(address_encoding)),
+            address_encoding,
+            len: 0,
+            timestamp: TimestampMicrosecondBuilder::with_capacity(capacity).with_timezone("UTC"),
+            elb_name: StringDictionaryBuilder::new(),
+            client_address: AddressBuilder::new(address_encoding, capacity),
+            backend_address: AddressBuilder::new(address_encoding, capacity),
+            request_processing_time: Float32Builder::with_capacity(capacity),
+            backend_processing_time: Float32Builder::with_capacity(capacity),
+            response_processing_time: Float32Builder::with_capacity(capacity),
+            elb_status_code: UInt16Builder::with_capacity(capacity),
+            backend_status_code: UInt16Builder::with_capacity(capacity),
+            received_bytes: UInt64Builder::with_capacity(capacity),
+            sent_bytes: UInt64Builder::with_capacity(capacity),
+            request_method: StringDictionaryBuilder::new(),
+            request_url: StringBuilder::with_capacity(capacity, capacity * 64),
+            request_http_version: StringDictionaryBuilder::new(),
+            user_agent: StringBuilder::with_capacity(capacity, capacity * 64),
+            ssl_cipher: StringDictionaryBuilder::new(),
+            ssl_protocol: StringDictionaryBuilder::new(),
+            #[cfg(feature = "geoip")]
+            geo: None,
+        }
+    }
+
+    /// Add the geo columns to the batches, which are filled in by [`append_geo`]
+    /// (#method.append_geo) and null for records appended with [`append`](#method.append).
+    #[cfg(feature = "geoip")]
+    pub fn with_geo_columns(mut self) -> Self {
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let mut fields: Vec<Field> =
+            self.schema.fields().iter().map(|field| field.as_ref().clone()).collect();
+        for field in &GeoField::ALL {
+            let data_type = match *field {
+                GeoField::Country | GeoField::AsOrganization => dictionary.clone(),
+                GeoField::City => DataType::Utf8,
+                GeoField::Asn => DataType::UInt32,
+            };
+            fields.push(Field::new(field.snake_case_name(), data_type, true));
+        }
+        self.schema = Arc::new(Schema::new(fields));
+        self.geo = Some(GeoColumnBuilders {
+            country: StringDictionaryBuilder::new(),
+            city: StringBuilder::new(),
+            asn: UInt32Builder::new(),
+            as_organization: StringDictionaryBuilder::new(),
+        });
+        self
+    }
+
+    /// The schema of the batches this builder produces.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The number of records appended since the last batch was finished.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, record: &ELBRecord) -> Result<(), ArrowError> {
+        self.append_fields(record)?;
+        #[cfg(feature = "geoip")]
+        if let Some(ref mut geo) = self.geo {
+            geo.append(&GeoInfo::default());
+        }
+        Ok(())
+    }
+
+    /// Append an enriched record.  Its geo fields are left out unless the builder was created
+    /// [`with_geo_columns`](#method.with_geo_columns).
+    #[cfg(feature = "geoip")]
+    pub fn append_geo(&mut self, record: &GeoRecord) -> Result<(), ArrowError> {
+        self.append_fields(record)?;
+        if let Some(ref mut geo) = self.geo {
+            geo.append(&record.client);
+        }
+        Ok(())
+    }
+
+    fn append_fields(&mut self, record: &ELBRecord) -> Result<(), ArrowError> {
+        fn defined(value: &str) -> Option<&str> {
+            if value == ::UNDEFINED_CHAR { None } else { Some(value) }
+        }
+
+        self.timestamp.append_value(epoch_micros(&record.timestamp));
+        self.elb_name.append_value(record.elb_name);
+        self.client_address.append(Some(record.client_address))?;
+        self.backend_address.append(record.backend_address)?;
+        self.request_processing_time.append_value(record.request_processing_time);
+        self.backend_processing_time.append_value(record.backend_processing_time);
+        self.response_processing_time.append_value(record.response_processing_time);
+        self.elb_status_code.append_value(record.elb_status_code);
+        self.backend_status_code.append_option(record.backend_status_code);
+        self.received_bytes.append_value(record.received_bytes);
+        self.sent_bytes.append_value(record.sent_bytes);
+        self.request_method.append_option(record.request_method.map(|method| method.as_str()));
+        self.request_url.append_option(defined(record.request_url));
+        self.request_http_version
+            .append_option(record.request_http_version.map(|version| version.as_str()));
+        self.user_agent.append_option(defined(record.user_agent));
+        self.ssl_cipher.append_option(record.ssl_cipher.map(|cipher| cipher.name()));
+        self.ssl_protocol.append_option(record.ssl_protocol.map(|protocol| protocol.as_str()));
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Build a batch from the records appended since the last batch and reset the builder.
+    pub fn finish(&mut self) -> Result<RecordBatch, ArrowError> {
+        let columns: Vec<ArrayRef> = vec![Arc::new(self.timestamp.finish()),
+                                          Arc::new(self.elb_name.finish()),
+                                          self.client_address.finish(),
+                                          self.backend_address.finish(),
+                                          Arc::new(self.request_processing_time.finish()),
+                                          Arc::new(self.backend_processing_time.finish()),
+                                          Arc::new(self.response_processing_time.finish()),
+                                          Arc::new(self.elb_status_code.finish()),
+                                          Arc::new(self.backend_status_code.finish()),
+                                          Arc::new(self.received_bytes.finish()),
+                                          Arc::new(self.sent_bytes.finish()),
+                                          Arc::new(self.request_method.finish()),
+                                          Arc::new(self.request_url.finish()),
+                                          Arc::new(self.request_http_version.finish()),
+                                          Arc::new(self.user_agent.finish()),
+                                          Arc::new(self.ssl_cipher.finish()),
+                                          Arc::new(self.ssl_protocol.finish())];
+        #[cfg(feature = "geoip")]
+        let columns = match self.geo {
+            Some(ref mut geo) => columns.into_iter().chain(geo.finish()).collect(),
+            None => columns,
+        };
+        self.len = 0;
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+
+    /// The address encoding used by this builder.
+    pub fn address_encoding(&self) -> AddressEncoding {
+        self.address_encoding
+    }
+}
+
+#[cfg(test)]
+mod arrow_tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int32Type, TimestampMicrosecondType, UInt16Type};
+    use arrow_array::Array;
+    use arrow_schema::DataType;
+
+    use super::{AddressEncoding, ELBRecordBatchBuilder, to_record_batch};
+    use parse_record;
+
+    const V2_TEST_RECORD: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                                  172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                                  \"GET http://some.domain.com:80/path0 HTTP/1.1\" \
+                                  \"curl/7.38.0\" ECDHE-RSA-AES128-GCM-SHA256 TLSv1.2";
+
+    const UNREACHABLE_TEST_RECORD: &str = "2015-08-15T23:43:06.000000Z elb-name \
+                                           172.16.1.6:54815 - -1 -1 -1 503 - 0 0 \
+                                           \"GET http://some.domain.com:80/path0 HTTP/1.1\"";
+
+    #[test]
+    fn converts_records_into_a_batch_with_a_column_per_field() {
+        let records = vec![parse_record(V2_TEST_RECORD).unwrap(),
+                           parse_record(UNREACHABLE_TEST_RECORD).unwrap()];
+
+        let batch = to_record_batch(&records, AddressEncoding::Utf8).unwrap();
+
+        assert_eq!((batch.num_rows(), batch.num_columns()), (2, 17))
+    }
+
+    #[test]
+    fn stores_timestamps_as_utc_microseconds() {
+        let records = vec![parse_record(V2_TEST_RECORD).unwrap()];
+
+        let batch = to_record_batch(&records, AddressEncoding::Utf8).unwrap();
+
+        let timestamps = batch.column(0).as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(timestamps.value(0), 1439682185302180);
+        assert_eq!(timestamps.timezone(), Some("UTC"))
+    }
+
+    #[test]
+    fn dictionary_encodes_the_elb_name() {
+        let records = vec![parse_record(V2_TEST_RECORD).unwrap(),
+                           parse_record(UNREACHABLE_TEST_RECORD).unwrap()];
+
+        let batch = to_record_batch(&records, AddressEncoding::Utf8).unwrap();
+
+        let elb_names = batch.column_by_name("elb_name").unwrap().as_dictionary::<Int32Type>();
+        assert_eq!((elb_names.keys().values().to_vec(), elb_names.values().len()),
+                   (vec![0, 0], 1))
+    }
+
+    #[test]
+    fn stores_fields_recorded_as_undefined_as_nulls() {
+        let records = vec![parse_record(UNREACHABLE_TEST_RECORD).unwrap()];
+
+        let batch = to_record_batch(&records, AddressEncoding::Utf8).unwrap();
+
+        let backend_status_codes =
+            batch.column_by_name("backend_status_code").unwrap().as_primitive::<UInt16Type>();
+        assert!(backend_status_codes.is_null(0));
+        assert!(batch.column_by_name("ssl_protocol").unwrap().is_null(0))
+    }
+
+    #[test]
+    fn stores_addresses_as_fixed_binary_when_configured() {
+        let records = vec![parse_record(V2_TEST_RECORD).unwrap()];
+
+        let batch = to_record_batch(&records, AddressEncoding::FixedBinary).unwrap();
+
+        let client_addresses = batch.column(2).as_fixed_size_binary();
+        assert_eq!(client_addresses.data_type(), &DataType::FixedSizeBinary(6));
+        assert_eq!(client_addresses.value(0), &[172, 16, 1, 6, 0xd6, 0x1e])
+    }
+
+    #[test]
+    fn resets_the_builder_when_a_batch_is_finished() {
+        let mut builder = ELBRecordBatchBuilder::new(AddressEncoding::Utf8);
+        builder.append(&parse_record(V2_TEST_RECORD).unwrap()).unwrap();
+
+        let first = builder.finish().unwrap();
+        let second = builder.finish().unwrap();
+
+        assert_eq!((first.num_rows(), second.num_rows(), builder.is_empty()), (1, 0, true))
+    }
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn adds_geo_columns_when_configured() {
+        use arrow_array::types::UInt32Type;
+
+        use geoip::GeoIpDatabase;
+        use geoip::test_databases::city_and_asn;
+
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+        let line = V2_TEST_RECORD.replace("172.16.1.6", "10.1.1.6");
+        let mut builder = ELBRecordBatchBuilder::new(AddressEncoding::Utf8).with_geo_columns();
+        builder.append_geo(&database.enrich(parse_record(&line).unwrap())).unwrap();
+        builder.append(&parse_record(V2_TEST_RECORD).unwrap()).unwrap();
+
+        let batch = builder.finish().unwrap();
+
+        let asns = batch.column_by_name("client_asn").unwrap().as_primitive::<UInt32Type>();
+        assert_eq!(batch.num_columns(), 21);
+        assert_eq!((asns.value(0), asns.is_null(1)), (64500, true));
+        assert_eq!(batch.column_by_name("client_city").unwrap().as_string::<i32>().value(0),
+                   "Berlin")
+    }
+}
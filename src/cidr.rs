@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use ELBRecord;
+
+/// Which address of a record a [`CidrTable`](struct.CidrTable.html) looks up.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressField {
+    Client,
+    /// Records without a backend address never match.
+    Backend,
+}
+
+/// A table of tagged IPv4 ranges, e.g. office ranges, partner networks and known scanners, for
+/// filtering and tagging records by address.
+///
+/// Ranges are kept in a binary prefix trie, so a lookup takes at most 32 steps however many
+/// ranges there are.  When ranges overlap, the most specific range containing an address wins.
+///
+/// Tables are usually loaded from a text file with one range per line, in CIDR notation and
+/// optionally followed by a tag.  A bare address is a `/32` range.  Empty lines and text after a
+/// `#` are ignored:
+///
+/// ```text
+/// # Offices
+/// 203.0.113.0/24   office
+/// 198.51.100.7     office
+/// 192.0.2.0/25     partner
+/// ```
+#[derive(Debug, Clone)]
+pub struct CidrTable {
+    nodes: Vec<Node>,
+    tags: Vec<String>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// Indexes into `nodes`, with 0, the root, meaning there is no child.
+    children: [usize; 2],
+    tag: Option<usize>,
+}
+
+impl CidrTable {
+    /// Create an empty table.
+    pub fn new() -> CidrTable {
+        CidrTable {
+            nodes: vec![Node::default()],
+            tags: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Read a table from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CidrTable, CidrTableError> {
+        CidrTable::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Read a table from the contents of a table file.
+    pub fn parse(text: &str) -> Result<CidrTable, CidrTableError> {
+        let mut table = CidrTable::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut parts = line.split_whitespace();
+            let range = match parts.next() {
+                Some(range) => range,
+                None => continue,
+            };
+            let tag = parts.next().unwrap_or("");
+            let invalid = || {
+                CidrTableError::InvalidLine {
+                    line_number: idx + 1,
+                    line: line.trim().to_owned(),
+                }
+            };
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+            let (network, prefix_len) = parse_range(range).ok_or_else(invalid)?;
+            table.insert(network, prefix_len, tag);
+        }
+        Ok(table)
+    }
+
+    /// Add the range of addresses starting with the first `prefix_len` bits of `network`,
+    /// replacing the tag of the same range if it was already added.  Bits of `network` beyond
+    /// the prefix are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is more than 32.
+    pub fn insert<S: Into<String>>(&mut self, network: Ipv4Addr, prefix_len: u8, tag: S) {
+        assert!(prefix_len <= 32, "prefix length {} is more than 32", prefix_len);
+        let bits = u32::from(network);
+        let mut node = 0;
+        for depth in 0..prefix_len as u32 {
+            let bit = (bits >> (31 - depth) & 1) as usize;
+            if self.nodes[node].children[bit] == 0 {
+                self.nodes.push(Node::default());
+                self.nodes[node].children[bit] = self.nodes.len() - 1;
+            }
+            node = self.nodes[node].children[bit];
+        }
+        let tag = tag.into();
+        let tag = match self.tags.iter().position(|known| *known == tag) {
+            Some(tag) => tag,
+            None => {
+                self.tags.push(tag);
+                self.tags.len() - 1
+            }
+        };
+        if self.nodes[node].tag.replace(tag).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// The number of ranges in the table.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The tag of the most specific range containing `address`, or `None` if no range does.
+    /// Ranges added without a tag have an empty tag.
+    pub fn lookup(&self, address: Ipv4Addr) -> Option<&str> {
+        let bits = u32::from(address);
+        let mut node = 0;
+        let mut tag = self.nodes[0].tag;
+        for depth in 0..32 {
+            node = self.nodes[node].children[(bits >> (31 - depth) & 1) as usize];
+            if node == 0 {
+                break;
+            }
+            tag = self.nodes[node].tag.or(tag);
+        }
+        tag.map(|tag| &*self.tags[tag])
+    }
+
+    /// Return `true` if any range contains `address`.
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        self.lookup(address).is_some()
+    }
+
+    /// The tag of the most specific range containing the given address of `record`.
+    pub fn tag(&self, record: &ELBRecord, field: AddressField) -> Option<&str> {
+        match field {
+            AddressField::Client => self.lookup(*record.client_address.ip()),
+            AddressField::Backend => {
+                record.backend_address.and_then(|address| self.lookup(*address.ip()))
+            }
+        }
+    }
+
+    /// Return `true` if a range contains the given address of `record`, e.g. to filter records.
+    pub fn matches(&self, record: &ELBRecord, field: AddressField) -> bool {
+        self.tag(record, field).is_some()
+    }
+}
+
+impl Default for CidrTable {
+    fn default() -> CidrTable {
+        CidrTable::new()
+    }
+}
+
+fn parse_range(range: &str) -> Option<(Ipv4Addr, u8)> {
+    let mut parts = range.splitn(2, '/');
+    let network = parts.next()?.parse().ok()?;
+    let prefix_len = match parts.next() {
+        Some(prefix_len) => prefix_len.parse().ok().filter(|&len| len <= 32)?,
+        None => 32,
+    };
+    Some((network, prefix_len))
+}
+
+/// Returned when a [`CidrTable`](struct.CidrTable.html) cannot be loaded.
+#[derive(Debug)]
+pub enum CidrTableError {
+    /// The table file could not be read.
+    Io(io::Error),
+    /// A line is not a range optionally followed by a tag.  Lines are numbered from 1.
+    InvalidLine { line_number: usize, line: String },
+}
+
+impl Display for CidrTableError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            CidrTableError::Io(ref e) => write!(f, "I/O error: {}", e),
+            CidrTableError::InvalidLine { line_number, ref line } => {
+                write!(f,
+                       "Line {} is not a range optionally followed by a tag: {}.",
+                       line_number,
+                       line)
+            }
+        }
+    }
+}
+
+impl Error for CidrTableError {
+    fn description(&self) -> &str {
+        match *self {
+            CidrTableError::Io(_) => "I/O error",
+            CidrTableError::InvalidLine { .. } => "invalid range",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CidrTableError::Io(ref e) => Some(e),
+            CidrTableError::InvalidLine { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for CidrTableError {
+    fn from(e: io::Error) -> CidrTableError {
+        CidrTableError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod cidr_table_tests {
+    use std::net::Ipv4Addr;
+
+    use parse_record;
+    use super::{AddressField, CidrTable, CidrTableError};
+
+    const TEST_TABLE: &str = "# Offices\n\
+                              10.0.0.0/8      office\n\
+                              10.20.0.0/16    partner  # a partner inside our range\n\
+                              \n\
+                              172.16.1.5      backend\n\
+                              192.168.0.0/16\n";
+
+    fn ip(address: &str) -> Ipv4Addr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn returns_the_tag_of_the_most_specific_range() {
+        let table = CidrTable::parse(TEST_TABLE).unwrap();
+
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.lookup(ip("10.1.2.3")), Some("office"));
+        assert_eq!(table.lookup(ip("10.20.2.3")), Some("partner"));
+        assert_eq!(table.lookup(ip("192.168.7.7")), Some(""));
+        assert_eq!(table.lookup(ip("11.0.0.1")), None)
+    }
+
+    #[test]
+    fn ignores_bits_beyond_the_prefix() {
+        let mut table = CidrTable::new();
+        table.insert(ip("10.1.2.3"), 8, "office");
+
+        assert!(table.contains(ip("10.200.0.1")))
+    }
+
+    #[test]
+    fn matches_everything_with_a_zero_length_prefix() {
+        let table = CidrTable::parse("0.0.0.0/0 internet\n10.0.0.0/8 office").unwrap();
+
+        assert_eq!(table.lookup(ip("8.8.8.8")), Some("internet"));
+        assert_eq!(table.lookup(ip("10.0.0.1")), Some("office"))
+    }
+
+    #[test]
+    fn tags_records_by_client_and_backend_address() {
+        let table = CidrTable::parse(TEST_TABLE).unwrap();
+        let record = parse_record("2015-08-15T23:43:05.302180Z elb-name 10.20.1.6:54814 \
+                                   172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                                   \"GET http://some.domain.com:80/ HTTP/1.1\"")
+            .unwrap();
+        let unrouted = parse_record("2015-08-15T23:43:05.302180Z elb-name 11.0.0.1:54814 - -1 \
+                                     -1 -1 503 - 0 0 \"GET http://some.domain.com:80/ HTTP/1.1\"")
+            .unwrap();
+
+        assert_eq!(table.tag(&record, AddressField::Client), Some("partner"));
+        assert_eq!(table.tag(&record, AddressField::Backend), Some("backend"));
+        assert!(!table.matches(&unrouted, AddressField::Client));
+        assert!(!table.matches(&unrouted, AddressField::Backend))
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_range() {
+        match CidrTable::parse("10.0.0.0/8 office\n10.0.0.0/33 office\n") {
+            Err(CidrTableError::InvalidLine { line_number, line }) => {
+                assert_eq!((line_number, &*line), (2, "10.0.0.0/33 office"))
+            }
+            other => panic!("expected an invalid line, got {:?}", other),
+        }
+    }
+}
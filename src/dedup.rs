@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::{BufRead, Read};
+
+use {ELBRecordField, RecordSplitter};
+use reader::read_line_lossy;
+
+/// What identifies a record when looking for duplicates.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DedupKey {
+    /// The whole line, ignoring its line ending.
+    Line,
+    /// The raw text of the given fields.  Fields missing from a line, such as the V2 fields of a
+    /// V1 record, are treated as empty.
+    Fields(Vec<ELBRecordField>),
+}
+
+enum Seen {
+    Exact(HashSet<String>),
+    Bloom(BloomFilter),
+}
+
+/// Identifies records that have already been seen, e.g. because a replayed S3 sync delivered the
+/// same log files more than once.
+///
+/// In exact mode every key is kept in memory, so no record is ever mistaken for a duplicate but
+/// memory grows with the number of distinct records.  In probabilistic mode keys are recorded in
+/// a Bloom filter of a fixed size, chosen from the expected number of distinct records and the
+/// acceptable rate of false positives.  Duplicates are always detected, but a small fraction of
+/// new records may be reported as duplicates, more so once more records than expected have been
+/// seen.  The filter hashes keys with a hash that does not vary between runs or platforms.
+pub struct Deduplicator {
+    key: DedupKey,
+    seen: Seen,
+    duplicates: u64,
+}
+
+impl Deduplicator {
+    /// Keep every distinct key in memory.
+    pub fn exact() -> Deduplicator {
+        Deduplicator {
+            key: DedupKey::Line,
+            seen: Seen::Exact(HashSet::new()),
+            duplicates: 0,
+        }
+    }
+
+    /// Use a Bloom filter sized for `expected_records` distinct records with a false positive
+    /// rate of `false_positive_rate`, e.g. 0.001.
+    pub fn probabilistic(expected_records: u64, false_positive_rate: f64) -> Deduplicator {
+        Deduplicator {
+            seen: Seen::Bloom(BloomFilter::new(expected_records, false_positive_rate)),
+            ..Deduplicator::exact()
+        }
+    }
+
+    /// Identify records by `key`, the whole line by default.
+    pub fn with_key(mut self, key: DedupKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Return `true` if a line with the same key as `line` has been seen before, and remember
+    /// `line` otherwise.
+    pub fn is_duplicate(&mut self, line: &str) -> bool {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let key = match self.key {
+            DedupKey::Line => line.to_owned(),
+            DedupKey::Fields(ref fields) => {
+                let split_record = line.split_record();
+                let values: Vec<&str> = fields.iter()
+                    .map(|&field| split_record.get(field as usize).cloned().unwrap_or(""))
+                    .collect();
+                // Lines never contain a line feed, so it cannot be confused with field text.
+                values.join("\n")
+            }
+        };
+        let duplicate = match self.seen {
+            Seen::Exact(ref mut keys) => !keys.insert(key),
+            Seen::Bloom(ref mut filter) => !filter.insert(key.as_bytes()),
+        };
+        if duplicate {
+            self.duplicates += 1;
+        }
+        duplicate
+    }
+
+    /// The number of duplicates found so far.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Read from `reader` with duplicate lines left out.  Empty lines are passed through.
+    pub fn filter<R: BufRead>(self, reader: R) -> DedupReader<R> {
+        DedupReader {
+            reader,
+            deduplicator: self,
+            line: String::new(),
+            consumed: 0,
+        }
+    }
+}
+
+/// A reader that leaves out duplicate lines, created by [`Deduplicator::filter`]
+/// (struct.Deduplicator.html#method.filter).
+///
+/// It can be passed to anything that reads records from a `BufRead`, such as a
+/// [`RecordReader`](struct.RecordReader.html).  Offsets and line numbers counted from what it
+/// returns do not account for the lines left out, so they do not match the underlying input.
+/// Bytes that are not valid UTF-8 are replaced with U+FFFD, so such a line is passed through, or
+/// left out as a duplicate, like any other.
+pub struct DedupReader<R> {
+    reader: R,
+    deduplicator: Deduplicator,
+    line: String,
+    consumed: usize,
+}
+
+impl<R> DedupReader<R> {
+    /// The deduplicator, e.g. to see how many duplicates were left out.
+    pub fn deduplicator(&self) -> &Deduplicator {
+        &self.deduplicator
+    }
+}
+
+impl<R: BufRead> Read for DedupReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = {
+            let available = self.fill_buf()?;
+            let read = available.len().min(buf.len());
+            buf[..read].copy_from_slice(&available[..read]);
+            read
+        };
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for DedupReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.consumed == self.line.len() {
+            self.consumed = 0;
+            if read_line_lossy(&mut self.reader, &mut self.line)? == 0 {
+                break;
+            }
+            let empty = self.line.trim_end_matches(['\r', '\n']).is_empty();
+            if !empty && self.deduplicator.is_duplicate(&self.line) {
+                self.line.clear();
+            }
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed = (self.consumed + amount).min(self.line.len());
+    }
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    fn new(expected_items: u64, false_positive_rate: f64) -> BloomFilter {
+        let ln2 = ::std::f64::consts::LN_2;
+        let rate = false_positive_rate.clamp(1e-12, 0.5);
+        let items = expected_items.max(1) as f64;
+        let bit_count = ((-items * rate.ln() / (ln2 * ln2)).ceil() as u64).max(64);
+        let hash_count = ((bit_count as f64 / items * ln2).round() as u32).max(1);
+        BloomFilter {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+        }
+    }
+
+    /// Add `key`, returning `false` if it may have been added before.
+    fn insert(&mut self, key: &[u8]) -> bool {
+        let (h1, h2) = (stable_hash(key, 0), stable_hash(key, 1) | 1);
+        let mut added = false;
+        for i in 0..self.hash_count as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            if self.bits[word] & mask == 0 {
+                self.bits[word] |= mask;
+                added = true;
+            }
+        }
+        added
+    }
+}
+
+/// FNV-1a over `bytes`, seeded, followed by the SplitMix64 finalizer to spread the bits.
+pub(crate) fn stable_hash(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325 ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod deduplicator_tests {
+    use std::io::BufRead;
+
+    use ELBRecordField;
+    use super::{DedupKey, Deduplicator};
+
+    fn record(elb_name: &str, sent_bytes: u32) -> String {
+        format!("2015-08-15T23:43:05.302180Z {} 172.16.1.6:54814 172.16.1.5:9000 0.000039 \
+                 0.145507 0.00003 200 200 0 {} \"GET http://some.domain.com:80/ HTTP/1.1\"",
+                elb_name,
+                sent_bytes)
+    }
+
+    #[test]
+    fn finds_repeated_lines() {
+        let mut deduplicator = Deduplicator::exact();
+
+        let duplicates: Vec<bool> =
+            [record("a", 1), record("b", 1), record("a", 1), format!("{}\r\n", record("b", 1))]
+                .iter()
+                .map(|line| deduplicator.is_duplicate(line))
+                .collect();
+
+        assert_eq!(duplicates, vec![false, false, true, true]);
+        assert_eq!(deduplicator.duplicates(), 2)
+    }
+
+    #[test]
+    fn compares_only_the_key_fields() {
+        let mut deduplicator = Deduplicator::exact()
+            .with_key(DedupKey::Fields(vec![ELBRecordField::Timestamp,
+                                            ELBRecordField::ELBName,
+                                            ELBRecordField::UserAgent]));
+
+        let duplicates: Vec<bool> = [record("a", 1), record("a", 2), record("b", 1)]
+            .iter()
+            .map(|line| deduplicator.is_duplicate(line))
+            .collect();
+
+        assert_eq!(duplicates, vec![false, true, false])
+    }
+
+    #[test]
+    fn finds_every_duplicate_in_probabilistic_mode() {
+        let mut deduplicator = Deduplicator::probabilistic(1000, 0.001);
+        let lines: Vec<String> = (0..1000).map(|i| record("a", i)).collect();
+
+        let first_pass = lines.iter().filter(|line| deduplicator.is_duplicate(line)).count();
+        let second_pass = lines.iter().filter(|line| deduplicator.is_duplicate(line)).count();
+
+        assert!(first_pass <= 5, "{} false positives", first_pass);
+        assert_eq!(second_pass, 1000)
+    }
+
+    #[test]
+    fn leaves_duplicate_lines_out_of_a_reader() {
+        let input = format!("{}\n{}\n\n{}\n{}",
+                            record("a", 1),
+                            record("b", 1),
+                            record("a", 1),
+                            record("c", 1));
+        let mut reader = Deduplicator::exact().filter(input.as_bytes());
+
+        let lines: Vec<String> = (&mut reader).lines().map(|line| line.unwrap()).collect();
+
+        assert_eq!(lines,
+                   vec![record("a", 1), record("b", 1), String::new(), record("c", 1)]);
+        assert_eq!(reader.deduplicator().duplicates(), 1)
+    }
+
+    #[test]
+    fn passes_lines_that_are_not_utf8_through() {
+        let mut input = format!("{}\n", record("a", 1)).into_bytes();
+        input.extend_from_slice(b"not \xff a record\nnot \xff a record\n");
+        input.extend_from_slice(record("b", 1).as_bytes());
+        let mut reader = Deduplicator::exact().filter(&input[..]);
+
+        let lines: Vec<String> = (&mut reader).lines().map(|line| line.unwrap()).collect();
+
+        assert_eq!(lines,
+                   vec![record("a", 1), "not \u{fffd} a record".to_owned(), record("b", 1)]);
+        assert_eq!(reader.deduplicator().duplicates(), 1)
+    }
+}
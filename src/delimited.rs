@@ -0,0 +1,327 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::io::Write;
+
+#[cfg(feature = "geoip")]
+use geoip::{GeoField, GeoRecord};
+use {ELBRecord, ELBRecordField, FieldValue};
+
+/// How the columns of the header row are named.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeaderStyle {
+    /// The `Display` names of the fields, e.g. `ELB status code`.
+    DisplayNames,
+    /// The `ELBRecord` field names, e.g. `elb_status_code`.
+    SnakeCase,
+}
+
+/// Writes `ELBRecord`s as CSV or TSV rows.
+///
+/// CSV values are quoted as described in RFC 4180: values containing the delimiter, a double
+/// quote or a line break are wrapped in double quotes and their double quotes are doubled.
+/// Because TSV cannot quote values, tabs, line breaks and backslashes in TSV values are escaped
+/// as `\t`, `\n`, `\r` and `\\`.  Fields recorded as `-` are written as empty values.
+///
+/// The header row, unless disabled, is written before the first record or when the writer is
+/// flushed, whichever happens first.
+///
+/// With the `geoip` feature, [`with_geo_columns`](#method.with_geo_columns) adds columns for
+/// the location of the client, which are filled in by [`write_geo_record`]
+/// (#method.write_geo_record).
+pub struct DelimitedWriter<W: Write> {
+    writer: W,
+    format: Format,
+    columns: Vec<ELBRecordField>,
+    #[cfg(feature = "geoip")]
+    geo_columns: Vec<GeoField>,
+    header: Option<HeaderStyle>,
+    header_written: bool,
+    value_buffer: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Format {
+    Csv,
+    Tsv,
+}
+
+impl<W: Write> DelimitedWriter<W> {
+    /// A comma separated writer with every field as a column and a snake_case header.
+    pub fn csv(writer: W) -> DelimitedWriter<W> {
+        DelimitedWriter::new(writer, Format::Csv)
+    }
+
+    /// A tab separated writer with every field as a column and a snake_case header.
+    pub fn tsv(writer: W) -> DelimitedWriter<W> {
+        DelimitedWriter::new(writer, Format::Tsv)
+    }
+
+    fn new(writer: W, format: Format) -> DelimitedWriter<W> {
+        DelimitedWriter {
+            writer,
+            format,
+            columns: ELBRecordField::ALL.to_vec(),
+            #[cfg(feature = "geoip")]
+            geo_columns: Vec::new(),
+            header: Some(HeaderStyle::SnakeCase),
+            header_written: false,
+            value_buffer: String::new(),
+        }
+    }
+
+    /// Write only `columns`, in the given order.
+    pub fn with_columns(mut self, columns: &[ELBRecordField]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+
+    /// Write `columns` after the record's columns, in the given order.  They are empty for
+    /// records written with [`write_record`](#method.write_record).
+    #[cfg(feature = "geoip")]
+    pub fn with_geo_columns(mut self, columns: &[GeoField]) -> Self {
+        self.geo_columns = columns.to_vec();
+        self
+    }
+
+    /// Name the header's columns using `style`.
+    pub fn with_header(mut self, style: HeaderStyle) -> Self {
+        self.header = Some(style);
+        self
+    }
+
+    /// Do not write a header row.
+    pub fn without_header(mut self) -> Self {
+        self.header = None;
+        self
+    }
+
+    /// Write a single record as a row.
+    pub fn write_record(&mut self, record: &ELBRecord) -> io::Result<()> {
+        self.write_fields(record)?;
+        #[cfg(feature = "geoip")]
+        for idx in 0..self.geo_columns.len() {
+            self.value_buffer.clear();
+            self.write_value(self.columns.len() + idx)?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    /// Write a single enriched record as a row, including the geo columns.
+    #[cfg(feature = "geoip")]
+    pub fn write_geo_record(&mut self, record: &GeoRecord) -> io::Result<()> {
+        self.write_fields(record)?;
+        for idx in 0..self.geo_columns.len() {
+            self.value_buffer.clear();
+            match record.client.field(self.geo_columns[idx]) {
+                FieldValue::Undefined => {}
+                value => {
+                    let _ = write!(self.value_buffer, "{}", value);
+                }
+            }
+            self.write_value(self.columns.len() + idx)?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_fields(&mut self, record: &ELBRecord) -> io::Result<()> {
+        self.write_header_if_needed()?;
+        for idx in 0..self.columns.len() {
+            self.value_buffer.clear();
+            match record.field(self.columns[idx]) {
+                FieldValue::Undefined => {}
+                value => {
+                    // Writing to a String cannot fail.
+                    let _ = write!(self.value_buffer, "{}", value);
+                }
+            }
+            self.write_value(idx)?;
+        }
+        Ok(())
+    }
+
+    /// Write the header row if it has not been written yet and flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_header_if_needed()?;
+        self.writer.flush()
+    }
+
+    /// Flush the writer and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header_if_needed(&mut self) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+
+        let style = match self.header {
+            Some(style) => style,
+            None => return Ok(()),
+        };
+        for idx in 0..self.columns.len() {
+            self.value_buffer.clear();
+            match style {
+                HeaderStyle::DisplayNames => {
+                    let _ = write!(self.value_buffer, "{}", self.columns[idx]);
+                }
+                HeaderStyle::SnakeCase => {
+                    self.value_buffer.push_str(self.columns[idx].snake_case_name())
+                }
+            }
+            self.write_value(idx)?;
+        }
+        #[cfg(feature = "geoip")]
+        for idx in 0..self.geo_columns.len() {
+            self.value_buffer.clear();
+            match style {
+                HeaderStyle::DisplayNames => {
+                    let _ = write!(self.value_buffer, "{}", self.geo_columns[idx]);
+                }
+                HeaderStyle::SnakeCase => {
+                    self.value_buffer.push_str(self.geo_columns[idx].snake_case_name())
+                }
+            }
+            self.write_value(self.columns.len() + idx)?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_value(&mut self, column_idx: usize) -> io::Result<()> {
+        if column_idx > 0 {
+            self.writer.write_all(match self.format {
+                Format::Csv => b",",
+                Format::Tsv => b"\t",
+            })?;
+        }
+
+        let value = self.value_buffer.as_str();
+        match self.format {
+            Format::Csv => {
+                if value.contains([',', '"', '\n', '\r']) {
+                    write!(self.writer, "\"{}\"", value.replace('"', "\"\""))
+                } else {
+                    self.writer.write_all(value.as_bytes())
+                }
+            }
+            Format::Tsv => {
+                for (idx, c) in value.char_indices() {
+                    let escaped: &[u8] = match c {
+                        '\t' => b"\\t",
+                        '\n' => b"\\n",
+                        '\r' => b"\\r",
+                        '\\' => b"\\\\",
+                        _ => &value.as_bytes()[idx..idx + c.len_utf8()],
+                    };
+                    self.writer.write_all(escaped)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod delimited_writer_tests {
+    use super::DelimitedWriter;
+    use super::HeaderStyle;
+    use {ELBRecordField, parse_record};
+
+    const TEST_RECORD: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                               172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                               \"GET http://some.domain.com:80/path0?a=1,2 HTTP/1.1\" \
+                               \"Mozilla/5.0 (X11, Linux)\ttools\\x\" - -";
+
+    fn write_test_record<F>(configure: F) -> String
+        where F: FnOnce(DelimitedWriter<Vec<u8>>) -> DelimitedWriter<Vec<u8>>
+    {
+        let record = parse_record(TEST_RECORD).unwrap();
+        let mut writer = configure(DelimitedWriter::csv(Vec::new()));
+        writer.write_record(&record).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_a_snake_case_header_followed_by_every_field() {
+        let csv = write_test_record(|writer| writer);
+
+        assert_eq!(csv.lines().next().unwrap(),
+                   "timestamp,elb_name,client_address,backend_address,request_processing_time,\
+                    backend_processing_time,response_processing_time,elb_status_code,\
+                    backend_status_code,received_bytes,sent_bytes,request_method,request_url,\
+                    request_http_version,user_agent,ssl_cipher,ssl_protocol")
+    }
+
+    #[test]
+    fn writes_the_selected_columns_in_order_with_display_names() {
+        let csv = write_test_record(|writer| {
+            writer.with_columns(&[ELBRecordField::ELBStatusCode, ELBRecordField::ELBName])
+                .with_header(HeaderStyle::DisplayNames)
+        });
+
+        assert_eq!(csv, "ELB status code,ELB name\n200,elb-name\n")
+    }
+
+    #[test]
+    fn quotes_csv_values_containing_delimiters() {
+        let csv = write_test_record(|writer| {
+            writer.with_columns(&[ELBRecordField::RequestURL, ELBRecordField::UserAgent])
+                .without_header()
+        });
+
+        assert_eq!(csv,
+                   "\"http://some.domain.com:80/path0?a=1,2\",\
+                    \"Mozilla/5.0 (X11, Linux)\ttools\\x\"\n")
+    }
+
+    #[test]
+    fn escapes_tabs_and_backslashes_in_tsv_values() {
+        let record = parse_record(TEST_RECORD).unwrap();
+        let mut writer = DelimitedWriter::tsv(Vec::new())
+            .with_columns(&[ELBRecordField::ELBName, ELBRecordField::UserAgent])
+            .without_header();
+        writer.write_record(&record).unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+                   "elb-name\tMozilla/5.0 (X11, Linux)\\ttools\\\\x\n")
+    }
+
+    #[test]
+    fn writes_undefined_fields_as_empty_values() {
+        let csv = write_test_record(|writer| {
+            writer.with_columns(&[ELBRecordField::SSLCipher, ELBRecordField::SSLProtocol])
+                .without_header()
+        });
+
+        assert_eq!(csv, ",\n")
+    }
+
+    #[test]
+    fn writes_the_header_when_flushed_without_records() {
+        let writer = DelimitedWriter::csv(Vec::new()).with_columns(&[ELBRecordField::Timestamp]);
+
+        assert_eq!(writer.into_inner().unwrap(), b"timestamp\n")
+    }
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn writes_geo_columns_after_the_record() {
+        use geoip::{GeoField, GeoIpDatabase};
+        use geoip::test_databases::city_and_asn;
+
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+        let line = TEST_RECORD.replace("172.16.1.6", "10.1.1.6");
+        let mut writer = DelimitedWriter::csv(Vec::new())
+            .with_columns(&[ELBRecordField::ClientAddress])
+            .with_geo_columns(&[GeoField::Country, GeoField::Asn, GeoField::City]);
+        writer.write_geo_record(&database.enrich(parse_record(&line).unwrap())).unwrap();
+        writer.write_record(&parse_record(TEST_RECORD).unwrap()).unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+                   "client_address,client_country,client_asn,client_city\n\
+                    10.1.1.6:54814,DE,64500,Berlin\n\
+                    172.16.1.6:54814,,,\n")
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, UTC};
+
+use dedup::stable_hash;
+use {ELBRecord, ELBRecordField, epoch_micros, from_epoch_micros};
+
+/// An estimate of the number of distinct items added to it, kept in a few kilobytes however
+/// many items there are.
+///
+/// This is the HyperLogLog algorithm of Flajolet, Fusy, Gandouet and Meunier, with linear
+/// counting for small cardinalities.  With a precision of `p` there are `2^p` one byte registers
+/// and the standard error of the estimate is about `1.04 / sqrt(2^p)`, e.g. 0.8% with the
+/// default precision of 14.
+///
+/// Counters with the same precision can be merged, e.g. to combine the counts of parallel
+/// workers, and the result is the same as if every item had been added to one counter.  Items
+/// are hashed with a hash that does not vary between runs or platforms, so counters built on
+/// different machines can be merged too; use [`to_bytes`](#method.to_bytes) and
+/// [`from_bytes`](#method.from_bytes) to move them between processes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Create an empty counter with `2^precision` registers.
+    ///
+    /// # Panics
+    ///
+    /// If `precision` is not between 4 and 18.
+    pub fn new(precision: u8) -> HyperLogLog {
+        assert!((4..=18).contains(&precision),
+                "precision {} is not between 4 and 18",
+                precision);
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Add an item.
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = stable_hash(item, 0);
+        let idx = (hash >> (64 - self.precision)) as usize;
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(65 - self.precision as u32);
+        if self.registers[idx] < rank as u8 {
+            self.registers[idx] = rank as u8;
+        }
+    }
+
+    /// The estimated number of distinct items added.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+
+    /// Add every item added to `other`.
+    ///
+    /// # Panics
+    ///
+    /// If the counters have different precisions.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(self.precision,
+                   other.precision,
+                   "cannot merge counters with different precisions");
+        for (register, &rank) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(rank);
+        }
+    }
+
+    /// The precision followed by the registers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.registers.len());
+        bytes.push(self.precision);
+        bytes.extend_from_slice(&self.registers);
+        bytes
+    }
+
+    /// Read a counter written by [`to_bytes`](#method.to_bytes), or return `None` if `bytes` are
+    /// not one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<HyperLogLog> {
+        let (&precision, registers) = bytes.split_first()?;
+        if !(4..=18).contains(&precision) || registers.len() != 1 << precision ||
+           registers.iter().any(|&rank| rank > 65 - precision) {
+            return None;
+        }
+        Some(HyperLogLog {
+            precision,
+            registers: registers.to_vec(),
+        })
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> HyperLogLog {
+        HyperLogLog::new(14)
+    }
+}
+
+/// What a [`DistinctCounter`](struct.DistinctCounter.html) counts the distinct values of.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DistinctKey {
+    /// The client's IP address, ignoring the port.
+    ClientIp,
+    /// The combined values of the given fields.
+    Fields(Vec<ELBRecordField>),
+}
+
+/// The records a [`DistinctCounter`](struct.DistinctCounter.html) counts together.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct DistinctBucket {
+    /// The start of the time bucket, or `None` when records are not bucketed by time.
+    pub start: Option<DateTime<UTC>>,
+    /// `None` when records are not counted per ELB.
+    pub elb_name: Option<String>,
+}
+
+/// Estimates the number of distinct values of a key, e.g. unique clients or URLs, per time
+/// bucket and optionally per ELB, with a [`HyperLogLog`](struct.HyperLogLog.html) for each.
+///
+/// Buckets are aligned to the Unix epoch, e.g. every whole hour.  Counters built by parallel
+/// workers over different records are combined with [`merge`](#method.merge).
+///
+/// ```rust,no_run
+/// # let records: Vec<elp::ELBRecord> = Vec::new();
+/// let mut clients = elp::DistinctCounter::new(elp::DistinctKey::ClientIp)
+///     .with_bucket(std::time::Duration::from_secs(60 * 60))
+///     .by_elb_name();
+/// for record in &records {
+///     clients.insert(record);
+/// }
+/// for (bucket, counter) in clients.counters() {
+///     println!("{:?} {:?} {}", bucket.start, bucket.elb_name, counter.estimate());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DistinctCounter {
+    key: DistinctKey,
+    precision: u8,
+    bucket: Option<i64>,
+    by_elb_name: bool,
+    counters: BTreeMap<DistinctBucket, HyperLogLog>,
+}
+
+impl DistinctCounter {
+    /// Count the distinct values of `key` over all records with a precision of 14.
+    pub fn new(key: DistinctKey) -> DistinctCounter {
+        DistinctCounter {
+            key,
+            precision: 14,
+            bucket: None,
+            by_elb_name: false,
+            counters: BTreeMap::new(),
+        }
+    }
+
+    /// Use counters with `2^precision` registers.
+    ///
+    /// # Panics
+    ///
+    /// If `precision` is not between 4 and 18.
+    pub fn with_precision(mut self, precision: u8) -> Self {
+        assert!((4..=18).contains(&precision),
+                "precision {} is not between 4 and 18",
+                precision);
+        self.precision = precision;
+        self
+    }
+
+    /// Count each time bucket of length `bucket` separately.
+    ///
+    /// # Panics
+    ///
+    /// If `bucket` is shorter than a microsecond.
+    pub fn with_bucket(mut self, bucket: Duration) -> Self {
+        let bucket = bucket.as_secs() as i64 * 1_000_000 + bucket.subsec_micros() as i64;
+        assert!(bucket > 0, "the bucket must be at least a microsecond long");
+        self.bucket = Some(bucket);
+        self
+    }
+
+    /// Count the records of each ELB separately.
+    pub fn by_elb_name(mut self) -> Self {
+        self.by_elb_name = true;
+        self
+    }
+
+    /// Add the key of `record` to the counter of its bucket.
+    pub fn insert(&mut self, record: &ELBRecord) {
+        let bucket = DistinctBucket {
+            start: self.bucket.map(|bucket| {
+                let timestamp = epoch_micros(&record.timestamp);
+                from_epoch_micros(timestamp - timestamp.rem_euclid(bucket))
+            }),
+            elb_name: if self.by_elb_name {
+                Some(record.elb_name.to_owned())
+            } else {
+                None
+            },
+        };
+        let precision = self.precision;
+        let counter = self.counters.entry(bucket).or_insert_with(|| HyperLogLog::new(precision));
+        match self.key {
+            DistinctKey::ClientIp => counter.insert(&record.client_address.ip().octets()),
+            DistinctKey::Fields(ref fields) => {
+                let values: Vec<String> =
+                    fields.iter().map(|&field| record.field(field).to_string()).collect();
+                // Field values never contain a line feed, so it cannot be confused with them.
+                counter.insert(values.join("\n").as_bytes())
+            }
+        }
+    }
+
+    /// Add the counts of `other`, bucket by bucket.
+    ///
+    /// # Panics
+    ///
+    /// If the counters have different precisions.
+    pub fn merge(&mut self, other: &DistinctCounter) {
+        for (bucket, counter) in &other.counters {
+            match self.counters.get_mut(bucket) {
+                Some(own) => own.merge(counter),
+                None => {
+                    assert_eq!(self.precision,
+                               counter.precision,
+                               "cannot merge counters with different precisions");
+                    self.counters.insert(bucket.clone(), counter.clone());
+                }
+            }
+        }
+    }
+
+    /// The counter of every bucket with at least one record, ordered by start then ELB name.
+    pub fn counters(&self) -> &BTreeMap<DistinctBucket, HyperLogLog> {
+        &self.counters
+    }
+}
+
+#[cfg(test)]
+mod distinct_counter_tests {
+    use std::time::Duration;
+
+    use {ELBRecordField, parse_record};
+    use super::{DistinctBucket, DistinctCounter, DistinctKey, HyperLogLog};
+
+    fn line(time: &str, elb_name: &str, client: &str, url: &str) -> String {
+        format!("2015-08-15T{}Z {} {}:54814 172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 \
+                 0 7582 \"GET http://a.com:80{} HTTP/1.1\" \"curl/7.43.0\" - -",
+                time,
+                elb_name,
+                client,
+                url)
+    }
+
+    #[test]
+    fn estimates_within_a_few_standard_errors() {
+        let mut counter = HyperLogLog::default();
+        for idx in 0..100_000u32 {
+            counter.insert(&idx.to_be_bytes());
+            counter.insert(&idx.to_be_bytes());
+        }
+        let error = (counter.estimate() as f64 - 100_000.0).abs() / 100_000.0;
+
+        assert!(error < 0.03, "error {} is too large", error)
+    }
+
+    #[test]
+    fn counts_small_cardinalities_exactly() {
+        let mut counter = HyperLogLog::new(14);
+        for item in &["a", "b", "c", "a"] {
+            counter.insert(item.as_bytes());
+        }
+
+        assert_eq!(counter.estimate(), 3);
+        assert_eq!(HyperLogLog::new(14).estimate(), 0)
+    }
+
+    #[test]
+    fn merges_to_the_same_counter_as_adding_everything_to_one() {
+        let mut all = HyperLogLog::new(10);
+        let mut even = HyperLogLog::new(10);
+        let mut odd = HyperLogLog::new(10);
+        for idx in 0..10_000u32 {
+            all.insert(&idx.to_be_bytes());
+            if idx % 2 == 0 {
+                even.insert(&idx.to_be_bytes());
+            } else {
+                odd.insert(&idx.to_be_bytes());
+            }
+        }
+        even.merge(&odd);
+
+        assert_eq!(even, all)
+    }
+
+    #[test]
+    fn reads_back_the_bytes_it_writes() {
+        let mut counter = HyperLogLog::new(4);
+        counter.insert(b"a");
+
+        assert_eq!(HyperLogLog::from_bytes(&counter.to_bytes()), Some(counter));
+        assert_eq!(HyperLogLog::from_bytes(&[4, 0, 0]), None)
+    }
+
+    #[test]
+    fn counts_each_bucket_and_elb_separately() {
+        let mut counter = DistinctCounter::new(DistinctKey::ClientIp)
+            .with_bucket(Duration::from_secs(60 * 60))
+            .by_elb_name();
+        for line in &[line("10:00:00.000000", "a", "10.0.0.1", "/"),
+                      line("10:59:59.000000", "a", "10.0.0.2", "/"),
+                      line("10:30:00.000000", "b", "10.0.0.1", "/"),
+                      line("11:00:00.000000", "a", "10.0.0.1", "/")] {
+            counter.insert(&parse_record(line).unwrap());
+        }
+        let estimates: Vec<(String, String, u64)> = counter.counters()
+            .iter()
+            .map(|(bucket, counter)| {
+                (bucket.start.unwrap().to_rfc3339(),
+                 bucket.elb_name.clone().unwrap(),
+                 counter.estimate())
+            })
+            .collect();
+
+        assert_eq!(estimates,
+                   [("2015-08-15T10:00:00+00:00".to_owned(), "a".to_owned(), 2),
+                    ("2015-08-15T10:00:00+00:00".to_owned(), "b".to_owned(), 1),
+                    ("2015-08-15T11:00:00+00:00".to_owned(), "a".to_owned(), 1)])
+    }
+
+    #[test]
+    fn merges_counters_of_parallel_workers() {
+        let key = DistinctKey::Fields(vec![ELBRecordField::RequestURL]);
+        let mut first = DistinctCounter::new(key.clone());
+        let mut second = DistinctCounter::new(key);
+        first.insert(&parse_record(&line("10:00:00.000000", "a", "10.0.0.1", "/a")).unwrap());
+        second.insert(&parse_record(&line("10:00:00.000000", "a", "10.0.0.1", "/a")).unwrap());
+        second.insert(&parse_record(&line("10:00:00.000000", "a", "10.0.0.1", "/b")).unwrap());
+        first.merge(&second);
+        let bucket = DistinctBucket {
+            start: None,
+            elb_name: None,
+        };
+
+        assert_eq!(first.counters()[&bucket].estimate(), 2)
+    }
+}
@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use chrono::{DateTime, TimeZone, UTC};
+
+/// The components of the name AWS gives an access log file, e.g.
+/// `123456789012_elasticloadbalancing_us-west-2_my-elb_20140215T2340Z_172.160.001.192_abc.log`.
+///
+/// See the [access log docs](http://docs.aws.amazon.com/ElasticLoadBalancing/latest/DeveloperGuide/access-log-collection.html)
+/// for a description of each component.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LogFileName<'a> {
+    pub account_id: &'a str,
+    pub region: &'a str,
+    pub load_balancer_name: &'a str,
+    /// The end of the interval the file covers.  AWS writes the time to the minute.
+    pub end_time: DateTime<UTC>,
+    pub load_balancer_ip: &'a str,
+    pub random_string: &'a str,
+}
+
+impl<'a> LogFileName<'a> {
+    /// Split a file name, without any directory, into its components.
+    pub fn parse(file_name: &'a str) -> Result<LogFileName<'a>, LogFileNameParseError> {
+        let stem = file_name.strip_suffix(".log").ok_or(LogFileNameParseError)?;
+        let parts: Vec<&str> = stem.split('_').collect();
+        if parts.len() != 7 || parts[1] != "elasticloadbalancing" {
+            return Err(LogFileNameParseError);
+        }
+        let end_time = UTC.datetime_from_str(parts[4], "%Y%m%dT%H%MZ")
+            .map_err(|_| LogFileNameParseError)?;
+        Ok(LogFileName {
+            account_id: parts[0],
+            region: parts[2],
+            load_balancer_name: parts[3],
+            end_time,
+            load_balancer_ip: parts[5],
+            random_string: parts[6],
+        })
+    }
+}
+
+/// Returned when a file name does not follow the AWS access log naming convention.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LogFileNameParseError;
+
+impl Display for LogFileNameParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Not an ELB access log file name.")
+    }
+}
+
+impl Error for LogFileNameParseError {
+    fn description(&self) -> &str {
+        "not an ELB access log file name"
+    }
+}
+
+#[cfg(test)]
+mod log_file_name_tests {
+    use chrono::{TimeZone, UTC};
+
+    use super::{LogFileName, LogFileNameParseError};
+
+    #[test]
+    fn returns_the_components_of_an_aws_file_name() {
+        let name = LogFileName::parse("123456789012_elasticloadbalancing_us-west-2_my-elb_\
+                                       20140215T2340Z_172.160.001.192_20sg8hgm.log")
+            .unwrap();
+
+        assert_eq!(name,
+                   LogFileName {
+                       account_id: "123456789012",
+                       region: "us-west-2",
+                       load_balancer_name: "my-elb",
+                       end_time: UTC.ymd(2014, 2, 15).and_hms(23, 40, 0),
+                       load_balancer_ip: "172.160.001.192",
+                       random_string: "20sg8hgm",
+                   })
+    }
+
+    #[test]
+    fn rejects_other_file_names() {
+        for name in &["access.log",
+                      "123456789012_elasticloadbalancing_us-west-2_my-elb_20140215T2340Z_\
+                       172.160.001.192_20sg8hgm.log.tmp",
+                      "123456789012_elasticloadbalancing_us-west-2_my-elb_yesterday_\
+                       172.160.001.192_20sg8hgm.log"] {
+            assert_eq!(LogFileName::parse(name), Err(LogFileNameParseError))
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, UTC};
+
+use {LogFileName, ParsingResult, ReaderPosition, RecordReader, epoch_micros};
+
+const CHECKPOINT_HEADER: &str = "elp-follow-checkpoint 1";
+
+/// Processes the access log files that arrive in a directory, e.g. one kept in sync with an S3
+/// bucket, in the order of the end times in their names.
+///
+/// Only files named by AWS's access log naming convention are processed; see [`LogFileName`]
+/// (struct.LogFileName.html).  Other files, such as the temporary files some sync tools write
+/// before renaming them into place, are ignored.
+///
+/// Progress is kept in a checkpoint file, when one is used, so a follower resumed after a restart
+/// carries on where it stopped.  The checkpoint holds the names of the files processed, along
+/// with the [`ReaderPosition`](struct.ReaderPosition.html) reached in the file being processed.
+///
+/// Sync tools deliver the files of an ELB's nodes in no fixed order, so a file may arrive after
+/// a later one has been processed.  Such a file is still processed if it ends within a lookback
+/// window, set with [`with_lookback`](#method.with_lookback), before the latest end time
+/// processed.  Only the files within the window are remembered, so the checkpoint does not grow
+/// with the number of files processed, and files ending before the window are skipped.
+///
+/// The position is saved after every record by default, so a resumed follower continues with the
+/// record after the last one handled and each record is handled once.  A record whose handling
+/// was interrupted before the position was saved is handled again.
+pub struct DirectoryFollower {
+    directory: PathBuf,
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: u64,
+    poll_interval: Duration,
+    /// In microseconds.
+    lookback: i64,
+    /// The end times and names of the processed files within the lookback window.
+    processed: BTreeSet<(DateTime<UTC>, String)>,
+    /// The position reached in the file being processed, whose path is its file name.
+    position: Option<ReaderPosition>,
+}
+
+impl DirectoryFollower {
+    /// Follow `directory` without a checkpoint, so every file in it is processed.
+    pub fn new<P: AsRef<Path>>(directory: P) -> DirectoryFollower {
+        DirectoryFollower {
+            directory: directory.as_ref().to_path_buf(),
+            checkpoint: None,
+            checkpoint_interval: 1,
+            poll_interval: Duration::from_secs(10),
+            lookback: 60 * 60 * 1_000_000,
+            processed: BTreeSet::new(),
+            position: None,
+        }
+    }
+
+    /// Follow `directory`, continuing from the checkpoint file at `checkpoint` and recording
+    /// progress in it from now on.  The checkpoint file is created when the first record has been
+    /// processed if it does not exist.
+    ///
+    /// Fails with `InvalidData` if the checkpoint file is not one.
+    pub fn resume<P, C>(directory: P, checkpoint: C) -> io::Result<DirectoryFollower>
+        where P: AsRef<Path>,
+              C: AsRef<Path>
+    {
+        let checkpoint = checkpoint.as_ref().to_path_buf();
+        let mut follower = DirectoryFollower::new(directory);
+        match File::open(&checkpoint) {
+            Ok(file) => follower.read_checkpoint(BufReader::new(file))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        follower.checkpoint = Some(checkpoint);
+        Ok(follower)
+    }
+
+    /// How long [`follow`](#method.follow) waits between looks at the directory, 10 seconds by
+    /// default.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Process files that arrive late if they end no more than `lookback` before the latest end
+    /// time processed, 1 hour by default.  A longer lookback tolerates later files at the cost of
+    /// a larger checkpoint.
+    pub fn with_lookback(mut self, lookback: Duration) -> Self {
+        self.lookback = lookback.as_secs() as i64 * 1_000_000 + lookback.subsec_micros() as i64;
+        self
+    }
+
+    /// Save the position in the checkpoint file after every `records` records, 1 by default,
+    /// rather than after each one.  Fewer writes speed up processing, but up to `records - 1`
+    /// records may be handled again after a restart.  The checkpoint is always saved when a file
+    /// has been processed.
+    pub fn with_checkpoint_interval(mut self, records: u64) -> Self {
+        self.checkpoint_interval = records.max(1);
+        self
+    }
+
+    /// The log files in the directory that have not been processed, oldest first.  Files with
+    /// the same end time are ordered by name.
+    pub fn pending_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut pending = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let file_name = match entry.file_name().into_string() {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let end_time = match LogFileName::parse(&file_name) {
+                Ok(name) => name.end_time,
+                Err(_) => continue,
+            };
+            let file = (end_time, file_name);
+            if !self.is_before_lookback(&file.0) && !self.processed.contains(&file) {
+                pending.push((file.0, file.1, entry.path()));
+            }
+        }
+        pending.sort();
+        Ok(pending.into_iter().map(|(_, _, path)| path).collect())
+    }
+
+    /// Process the pending files, passing each non-empty line's parsing result, along with the
+    /// file it was read from, to `handler`.  A file that was being processed is continued after
+    /// the last record handled.  Returns the number of files processed.
+    ///
+    /// If `handler` fails, processing stops and the record it failed on is handled again by the
+    /// next call.
+    pub fn process_pending<F>(&mut self, mut handler: F) -> io::Result<usize>
+        where F: FnMut(&Path, ParsingResult) -> io::Result<()>
+    {
+        let pending = self.pending_files()?;
+        for path in &pending {
+            let file_name = file_name(path)?;
+            let mut reader = match self.position {
+                Some(ref position) if position.path == Path::new(file_name) => {
+                    RecordReader::resume(&ReaderPosition {
+                        path: path.clone(),
+                        ..position.clone()
+                    })?
+                }
+                _ => RecordReader::open(path)?,
+            };
+            let mut unsaved = 0;
+            while let Some(result) = reader.next_record()? {
+                handler(path, result)?;
+                self.position = Some(ReaderPosition {
+                    path: PathBuf::from(file_name),
+                    offset: reader.offset(),
+                    line_number: reader.line_number(),
+                });
+                unsaved += 1;
+                if unsaved >= self.checkpoint_interval {
+                    self.save_checkpoint()?;
+                    unsaved = 0;
+                }
+            }
+            self.mark_processed(path)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Process pending files as they arrive until `stop` returns `true`.  `stop` is called after
+    /// each look at the directory.
+    pub fn follow<F, S>(&mut self, mut handler: F, mut stop: S) -> io::Result<()>
+        where F: FnMut(&Path, ParsingResult) -> io::Result<()>,
+              S: FnMut() -> bool
+    {
+        loop {
+            self.process_pending(&mut handler)?;
+            if stop() {
+                return Ok(());
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    fn mark_processed(&mut self, path: &Path) -> io::Result<()> {
+        let file_name = file_name(path)?;
+        let end_time = LogFileName::parse(file_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .end_time;
+        self.processed.insert((end_time, file_name.to_owned()));
+        while let Some(oldest) = self.processed.iter().next().cloned() {
+            if !self.is_before_lookback(&oldest.0) {
+                break;
+            }
+            self.processed.remove(&oldest);
+        }
+        self.position = None;
+        self.save_checkpoint()
+    }
+
+    /// Whether a file ending at `end_time` ends before the lookback window.
+    fn is_before_lookback(&self, end_time: &DateTime<UTC>) -> bool {
+        match self.processed.iter().next_back() {
+            Some((latest, _)) => {
+                epoch_micros(end_time) < epoch_micros(latest).saturating_sub(self.lookback)
+            }
+            None => false,
+        }
+    }
+
+    fn read_checkpoint<R: BufRead>(&mut self, reader: R) -> io::Result<()> {
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(CHECKPOINT_HEADER) {
+            return Err(invalid_checkpoint());
+        }
+        for line in lines {
+            let line = line?;
+            let (kind, value) = match line.find(' ') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => return Err(invalid_checkpoint()),
+            };
+            match kind {
+                "processed" => {
+                    let end_time =
+                        LogFileName::parse(value).map_err(|_| invalid_checkpoint())?.end_time;
+                    self.processed.insert((end_time, value.to_owned()));
+                }
+                "position" => {
+                    self.position = Some(value.parse().map_err(|_| invalid_checkpoint())?)
+                }
+                _ => return Err(invalid_checkpoint()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the checkpoint file by writing a temporary file next to it and renaming it, so a
+    /// crash never leaves a partially written checkpoint behind.
+    fn save_checkpoint(&self) -> io::Result<()> {
+        let checkpoint = match self.checkpoint {
+            Some(ref checkpoint) => checkpoint,
+            None => return Ok(()),
+        };
+        let mut temp_name = checkpoint.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = checkpoint.with_file_name(temp_name);
+
+        let mut file = io::BufWriter::new(File::create(&temp_path)?);
+        writeln!(file, "{}", CHECKPOINT_HEADER)?;
+        for (_, file_name) in &self.processed {
+            writeln!(file, "processed {}", file_name)?;
+        }
+        if let Some(ref position) = self.position {
+            writeln!(file, "position {}", position)?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, checkpoint)
+    }
+}
+
+fn file_name(path: &Path) -> io::Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))
+}
+
+fn invalid_checkpoint() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a follower checkpoint")
+}
+
+#[cfg(test)]
+mod directory_follower_tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::time::Duration;
+
+    use super::DirectoryFollower;
+
+    const TEST_RECORD: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                               172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                               \"GET http://some.domain.com:80/path0 HTTP/1.1\"";
+
+    fn log_name(end_time: &str, random: &str) -> String {
+        format!("123456789012_elasticloadbalancing_us-west-2_my-elb_{}_172.160.001.192_{}.log",
+                end_time,
+                random)
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elp-follow-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        dir
+    }
+
+    fn add_log(dir: &Path, name: &str, lines: usize) {
+        let contents: Vec<&str> = (0..lines).map(|_| TEST_RECORD).collect();
+        fs::write(dir.join("logs").join(name), contents.join("\n")).unwrap();
+    }
+
+    fn process(follower: &mut DirectoryFollower) -> Vec<String> {
+        let mut seen = Vec::new();
+        follower.process_pending(|path, result| {
+                assert!(result.is_ok());
+                seen.push(path.file_name().unwrap().to_string_lossy().into_owned());
+                Ok(())
+            })
+            .unwrap();
+        seen
+    }
+
+    #[test]
+    fn processes_files_in_the_order_of_their_end_times() {
+        let dir = test_dir("order");
+        add_log(&dir, &log_name("20140215T2345Z", "a"), 1);
+        add_log(&dir, &log_name("20140215T2340Z", "b"), 2);
+        add_log(&dir, "notes.txt", 1);
+        let mut follower = DirectoryFollower::new(dir.join("logs"));
+
+        let seen = process(&mut follower);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen,
+                   vec![log_name("20140215T2340Z", "b"),
+                        log_name("20140215T2340Z", "b"),
+                        log_name("20140215T2345Z", "a")])
+    }
+
+    #[test]
+    fn processes_each_file_once() {
+        let dir = test_dir("once");
+        add_log(&dir, &log_name("20140215T2340Z", "a"), 1);
+        let mut follower = DirectoryFollower::new(dir.join("logs"));
+        process(&mut follower);
+        add_log(&dir, &log_name("20140215T2345Z", "b"), 1);
+
+        let seen = process(&mut follower);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen, vec![log_name("20140215T2345Z", "b")])
+    }
+
+    #[test]
+    fn resumes_from_a_checkpoint() {
+        let dir = test_dir("resume");
+        let checkpoint = dir.join("checkpoint");
+        add_log(&dir, &log_name("20140215T2340Z", "a"), 1);
+        process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint).unwrap());
+        add_log(&dir, &log_name("20140215T2345Z", "b"), 1);
+
+        let seen = process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint)
+            .unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen, vec![log_name("20140215T2345Z", "b")])
+    }
+
+    #[test]
+    fn does_not_checkpoint_a_file_whose_handler_failed() {
+        let dir = test_dir("failure");
+        let checkpoint = dir.join("checkpoint");
+        add_log(&dir, &log_name("20140215T2340Z", "a"), 1);
+        let mut follower = DirectoryFollower::resume(dir.join("logs"), &checkpoint).unwrap();
+
+        let result = follower.process_pending(|_, _| {
+            Err(::std::io::Error::other("handler failed"))
+        });
+        let pending = follower.pending_files().unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+        assert_eq!(pending.len(), 1)
+    }
+
+    #[test]
+    fn resumes_a_file_after_the_last_record_handled() {
+        let dir = test_dir("mid-file");
+        let checkpoint = dir.join("checkpoint");
+        add_log(&dir, &log_name("20140215T2340Z", "a"), 3);
+        let mut follower = DirectoryFollower::resume(dir.join("logs"), &checkpoint).unwrap();
+        let mut handled = 0;
+        let result = follower.process_pending(|_, _| {
+            handled += 1;
+            if handled == 2 {
+                Err(::std::io::Error::other("stopped"))
+            } else {
+                Ok(())
+            }
+        });
+
+        let seen = process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint)
+            .unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+        assert_eq!(seen.len(), 2)
+    }
+
+    #[test]
+    fn keeps_only_the_files_within_the_lookback_in_the_checkpoint() {
+        let dir = test_dir("lookback");
+        let checkpoint = dir.join("checkpoint");
+        add_log(&dir, &log_name("20140215T2335Z", "a"), 1);
+        add_log(&dir, &log_name("20140215T2340Z", "b"), 1);
+        add_log(&dir, &log_name("20140215T2345Z", "c"), 1);
+        process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint)
+            .unwrap()
+            .with_lookback(Duration::from_secs(5 * 60)));
+
+        let contents = fs::read_to_string(&checkpoint).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents,
+                   format!("elp-follow-checkpoint 1\n\
+                            processed {}\n\
+                            processed {}\n",
+                           log_name("20140215T2340Z", "b"),
+                           log_name("20140215T2345Z", "c")))
+    }
+
+    #[test]
+    fn processes_a_file_that_arrives_late_within_the_lookback() {
+        let dir = test_dir("late");
+        let checkpoint = dir.join("checkpoint");
+        add_log(&dir, &log_name("20140215T2345Z", "a"), 1);
+        process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint).unwrap());
+        add_log(&dir, &log_name("20140215T2340Z", "late"), 1);
+        add_log(&dir, &log_name("20140215T2345Z", "b"), 1);
+
+        let seen = process(&mut DirectoryFollower::resume(dir.join("logs"), &checkpoint)
+            .unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen,
+                   vec![log_name("20140215T2340Z", "late"), log_name("20140215T2345Z", "b")])
+    }
+
+    #[test]
+    fn skips_a_file_that_arrives_after_the_lookback() {
+        let dir = test_dir("too-late");
+        add_log(&dir, &log_name("20140215T2345Z", "a"), 1);
+        let mut follower = DirectoryFollower::new(dir.join("logs"))
+            .with_lookback(Duration::from_secs(10 * 60));
+        process(&mut follower);
+        add_log(&dir, &log_name("20140215T2330Z", "late"), 1);
+
+        let seen = process(&mut follower);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(seen.is_empty())
+    }
+
+    #[test]
+    fn refuses_a_checkpoint_in_an_unknown_format() {
+        let dir = test_dir("bad-checkpoint");
+        let checkpoint = dir.join("checkpoint");
+        fs::write(&checkpoint, "some-file.log\n").unwrap();
+
+        let result = DirectoryFollower::resume(dir.join("logs"), &checkpoint);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()), Some(::std::io::ErrorKind::InvalidData))
+    }
+}
@@ -0,0 +1,423 @@
+//! Offline enrichment of client addresses with their country, city and autonomous system from
+//! local MaxMind databases.
+//!
+//! Both the commercial GeoIP2 databases and the free GeoLite2 databases are supported.  Nothing
+//! is downloaded: databases are read from disk, so keeping them up to date is left to tools such
+//! as MaxMind's `geoipupdate`.
+//!
+//! Enriched records are represented by [`GeoRecord`](struct.GeoRecord.html).  The CSV/TSV, JSON
+//! Lines and, when their features are enabled, Arrow and Parquet exporters can add the
+//! [`GeoField`](enum.GeoField.html)s of a `GeoRecord` as extra columns.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+
+use {ELBRecord, FieldValue};
+
+/// The information a [`GeoIpDatabase`](struct.GeoIpDatabase.html) has about an address.
+///
+/// Each value is `None` when no database has it.  The values borrow from the database that
+/// produced them.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct GeoInfo<'g> {
+    /// The ISO 3166-1 alpha-2 code of the country, e.g. `DE`.
+    pub country: Option<&'g str>,
+    /// The English name of the city.
+    pub city: Option<&'g str>,
+    /// The number of the autonomous system the address belongs to.
+    pub asn: Option<u32>,
+    /// The organization the autonomous system is registered to.
+    pub as_organization: Option<&'g str>,
+}
+
+impl<'g> GeoInfo<'g> {
+    /// The value of a single field.
+    pub fn field(&self, field: GeoField) -> FieldValue<'g> {
+        let text = |value: Option<&'g str>| value.map_or(FieldValue::Undefined, FieldValue::Text);
+        match field {
+            GeoField::Country => text(self.country),
+            GeoField::City => text(self.city),
+            GeoField::Asn => {
+                self.asn.map_or(FieldValue::Undefined, |asn| FieldValue::Integer(asn as u64))
+            }
+            GeoField::AsOrganization => text(self.as_organization),
+        }
+    }
+}
+
+/// A column added to exported records by GeoIP enrichment.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum GeoField {
+    Country,
+    City,
+    Asn,
+    AsOrganization,
+}
+
+impl GeoField {
+    /// Every field in the order exporters write them.
+    pub const ALL: [GeoField; 4] =
+        [GeoField::Country, GeoField::City, GeoField::Asn, GeoField::AsOrganization];
+
+    /// The name exporters give the field's column, e.g. `client_country`.
+    pub fn snake_case_name(&self) -> &'static str {
+        match *self {
+            GeoField::Country => "client_country",
+            GeoField::City => "client_city",
+            GeoField::Asn => "client_asn",
+            GeoField::AsOrganization => "client_as_organization",
+        }
+    }
+}
+
+impl Display for GeoField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            GeoField::Country => "client country",
+            GeoField::City => "client city",
+            GeoField::Asn => "client ASN",
+            GeoField::AsOrganization => "client AS organization",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A record along with what is known about its client address.
+///
+/// A `GeoRecord` dereferences to its `ELBRecord`, so the record's fields can be used directly.
+#[derive(Debug)]
+pub struct GeoRecord<'a, 'g> {
+    pub record: ELBRecord<'a>,
+    pub client: GeoInfo<'g>,
+}
+
+impl<'a, 'g> Deref for GeoRecord<'a, 'g> {
+    type Target = ELBRecord<'a>;
+
+    fn deref(&self) -> &ELBRecord<'a> {
+        &self.record
+    }
+}
+
+/// One or more MaxMind databases, e.g. GeoLite2-City and GeoLite2-ASN, queried together.
+///
+/// Databases whose type names an ASN or ISP database provide the autonomous system; the others,
+/// such as City and Country databases, provide the country and city.  When more than one
+/// database provides the same information, the first one given that has it for an address is
+/// used.
+#[derive(Debug)]
+pub struct GeoIpDatabase {
+    locations: Vec<Reader<Vec<u8>>>,
+    autonomous_systems: Vec<Reader<Vec<u8>>>,
+}
+
+impl GeoIpDatabase {
+    /// Read the database files at `paths`.
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut databases = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let reader = Reader::open_readfile(path).map_err(|error| {
+                    GeoIpError {
+                        path: Some(path.to_path_buf()),
+                        error,
+                    }
+                })?;
+            databases.push(reader);
+        }
+        Ok(GeoIpDatabase::from_readers(databases))
+    }
+
+    /// Use databases that have already been read into memory.
+    pub fn from_bytes(databases: Vec<Vec<u8>>) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut readers = Vec::with_capacity(databases.len());
+        for database in databases {
+            let reader = Reader::from_source(database).map_err(|error| {
+                    GeoIpError {
+                        path: None,
+                        error,
+                    }
+                })?;
+            readers.push(reader);
+        }
+        Ok(GeoIpDatabase::from_readers(readers))
+    }
+
+    fn from_readers(readers: Vec<Reader<Vec<u8>>>) -> GeoIpDatabase {
+        let (autonomous_systems, locations) = readers.into_iter().partition(|reader| {
+            let database_type = &reader.metadata.database_type;
+            database_type.contains("ASN") || database_type.contains("ISP")
+        });
+        GeoIpDatabase {
+            locations,
+            autonomous_systems,
+        }
+    }
+
+    /// What the databases know about `address`.  Entries that cannot be decoded are treated as
+    /// missing.
+    pub fn lookup(&self, address: Ipv4Addr) -> GeoInfo<'_> {
+        let address = IpAddr::V4(address);
+        let mut info = GeoInfo::default();
+        for reader in &self.locations {
+            if let Ok(city) = reader.lookup::<geoip2::City>(address) {
+                if info.country.is_none() {
+                    info.country = city.country.and_then(|country| country.iso_code);
+                }
+                if info.city.is_none() {
+                    info.city = city.city
+                        .and_then(|city| city.names)
+                        .and_then(|names| names.get("en").cloned());
+                }
+            }
+        }
+        for reader in &self.autonomous_systems {
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(address) {
+                info.asn = info.asn.or(asn.autonomous_system_number);
+                info.as_organization =
+                    info.as_organization.or(asn.autonomous_system_organization);
+            }
+        }
+        info
+    }
+
+    /// Wrap `record` with what the databases know about its client address.
+    pub fn enrich<'a>(&self, record: ELBRecord<'a>) -> GeoRecord<'a, '_> {
+        GeoRecord {
+            client: self.lookup(*record.client_address.ip()),
+            record,
+        }
+    }
+}
+
+/// Returned when a MaxMind database cannot be read.
+#[derive(Debug)]
+pub struct GeoIpError {
+    /// The file the database was read from, if it was read from a file.
+    pub path: Option<PathBuf>,
+    pub error: MaxMindDBError,
+}
+
+impl Display for GeoIpError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.path {
+            Some(ref path) => {
+                write!(f, "Could not read the MaxMind database {}: {}.", path.display(), self.error)
+            }
+            None => write!(f, "Could not read a MaxMind database: {}.", self.error),
+        }
+    }
+}
+
+impl Error for GeoIpError {
+    fn description(&self) -> &str {
+        "could not read a MaxMind database"
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Builds small MaxMind databases for the tests of GeoIP enrichment.
+#[cfg(test)]
+pub(crate) mod test_databases {
+    /// A value in the MaxMind DB data format.
+    pub enum Data {
+        Text(&'static str),
+        UInt16(u16),
+        UInt32(u32),
+        UInt64(u64),
+        Map(Vec<(&'static str, Data)>),
+        Array(Vec<Data>),
+    }
+
+    fn encode(data: &Data, out: &mut Vec<u8>) {
+        fn control(out: &mut Vec<u8>, data_type: u8, size: usize) {
+            assert!(size < 29 + 256);
+            let size_bits = size.min(29) as u8;
+            if data_type <= 7 {
+                out.push(data_type << 5 | size_bits);
+            } else {
+                out.push(size_bits);
+                out.push(data_type - 7);
+            }
+            if size >= 29 {
+                out.push((size - 29) as u8);
+            }
+        }
+        fn unsigned(out: &mut Vec<u8>, data_type: u8, value: u64) {
+            let bytes = value.to_be_bytes();
+            let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+            control(out, data_type, 8 - skip);
+            out.extend_from_slice(&bytes[skip..]);
+        }
+
+        match *data {
+            Data::Text(text) => {
+                control(out, 2, text.len());
+                out.extend_from_slice(text.as_bytes());
+            }
+            Data::UInt16(value) => unsigned(out, 5, value as u64),
+            Data::UInt32(value) => unsigned(out, 6, value as u64),
+            Data::UInt64(value) => unsigned(out, 9, value),
+            Data::Map(ref entries) => {
+                control(out, 7, entries.len());
+                for &(key, ref value) in entries {
+                    encode(&Data::Text(key), out);
+                    encode(value, out);
+                }
+            }
+            Data::Array(ref values) => {
+                control(out, 11, values.len());
+                for value in values {
+                    encode(value, out);
+                }
+            }
+        }
+    }
+
+    /// An IPv4 database of type `database_type` holding `networks`, each an address, a prefix
+    /// length and the data stored for the network.  Networks must not overlap.
+    pub fn build(database_type: &'static str, networks: Vec<([u8; 4], u32, Data)>) -> Vec<u8> {
+        #[derive(Clone, Copy)]
+        enum Record {
+            Empty,
+            Node(usize),
+            Data(usize),
+        }
+
+        let mut data_section = Vec::new();
+        let mut nodes = vec![[Record::Empty; 2]];
+        for (address, prefix_len, data) in networks {
+            let offset = data_section.len();
+            encode(&data, &mut data_section);
+            let bits = u32::from_be_bytes(address);
+            let mut node = 0;
+            for depth in 0..prefix_len {
+                let bit = (bits >> (31 - depth) & 1) as usize;
+                if depth + 1 == prefix_len {
+                    nodes[node][bit] = Record::Data(offset);
+                } else {
+                    node = match nodes[node][bit] {
+                        Record::Node(child) => child,
+                        _ => {
+                            nodes.push([Record::Empty; 2]);
+                            nodes[node][bit] = Record::Node(nodes.len() - 1);
+                            nodes.len() - 1
+                        }
+                    };
+                }
+            }
+        }
+
+        let node_count = nodes.len();
+        let mut database = Vec::new();
+        for node in &nodes {
+            for record in node {
+                let value = match *record {
+                    Record::Empty => node_count,
+                    Record::Node(child) => child,
+                    Record::Data(offset) => node_count + 16 + offset,
+                };
+                database.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+            }
+        }
+        database.extend_from_slice(&[0; 16]);
+        database.extend_from_slice(&data_section);
+        database.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+        encode(&Data::Map(vec![("binary_format_major_version", Data::UInt16(2)),
+                               ("binary_format_minor_version", Data::UInt16(0)),
+                               ("build_epoch", Data::UInt64(1_439_682_185)),
+                               ("database_type", Data::Text(database_type)),
+                               ("description", Data::Map(vec![("en", Data::Text("Test"))])),
+                               ("ip_version", Data::UInt16(4)),
+                               ("languages", Data::Array(vec![Data::Text("en")])),
+                               ("node_count", Data::UInt32(node_count as u32)),
+                               ("record_size", Data::UInt16(24))]),
+               &mut database);
+        database
+    }
+
+    /// A City database placing 10.1.0.0/16 in Berlin, Germany, and an ASN database placing
+    /// 10.0.0.0/8 in AS 64500.
+    pub fn city_and_asn() -> Vec<Vec<u8>> {
+        let berlin = Data::Map(vec![("city",
+                                     Data::Map(vec![("names",
+                                                     Data::Map(vec![("de", Data::Text("Berlin")),
+                                                                    ("en",
+                                                                     Data::Text("Berlin"))]))])),
+                                    ("country",
+                                     Data::Map(vec![("iso_code", Data::Text("DE"))]))]);
+        let asn = Data::Map(vec![("autonomous_system_number", Data::UInt32(64500)),
+                                 ("autonomous_system_organization", Data::Text("Example AS"))]);
+        vec![build("GeoLite2-City", vec![([10, 1, 0, 0], 16, berlin)]),
+             build("GeoLite2-ASN", vec![([10, 0, 0, 0], 8, asn)])]
+    }
+}
+
+#[cfg(test)]
+mod geoip_tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use parse_record;
+    use FieldValue;
+    use super::{GeoField, GeoInfo, GeoIpDatabase};
+    use super::test_databases::city_and_asn;
+
+    #[test]
+    fn combines_the_location_and_autonomous_system_of_an_address() {
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+
+        assert_eq!(database.lookup("10.1.2.3".parse().unwrap()),
+                   GeoInfo {
+                       country: Some("DE"),
+                       city: Some("Berlin"),
+                       asn: Some(64500),
+                       as_organization: Some("Example AS"),
+                   });
+        assert_eq!(database.lookup("10.2.0.1".parse().unwrap()),
+                   GeoInfo {
+                       asn: Some(64500),
+                       as_organization: Some("Example AS"),
+                       ..GeoInfo::default()
+                   });
+        assert_eq!(database.lookup("192.168.0.1".parse().unwrap()), GeoInfo::default())
+    }
+
+    #[test]
+    fn enriches_a_record_by_its_client_address() {
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+        let record = parse_record("2015-08-15T23:43:05.302180Z elb-name 10.1.1.6:54814 \
+                                   172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                                   \"GET http://some.domain.com:80/ HTTP/1.1\"")
+            .unwrap();
+
+        let enriched = database.enrich(record);
+
+        assert_eq!(enriched.elb_status_code, 200);
+        assert_eq!(enriched.client.field(GeoField::City), FieldValue::Text("Berlin"));
+        assert_eq!(enriched.client.field(GeoField::Asn), FieldValue::Integer(64500))
+    }
+
+    #[test]
+    fn opens_database_files() {
+        let path = env::temp_dir().join(format!("elp-geoip-{}.mmdb", process::id()));
+        fs::write(&path, &city_and_asn()[0]).unwrap();
+
+        let database = GeoIpDatabase::open(&[&path]);
+        let missing = GeoIpDatabase::open(&[path.with_extension("missing")]);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(database.unwrap().lookup("10.1.0.1".parse().unwrap()).country, Some("DE"));
+        assert!(missing.unwrap_err().to_string().contains("Could not read the MaxMind database"))
+    }
+}
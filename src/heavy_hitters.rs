@@ -0,0 +1,373 @@
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::time::Duration;
+
+use chrono::{DateTime, UTC};
+
+use {ELBRecord, epoch_micros, from_epoch_micros};
+
+/// What a [`HeavyHitters`](struct.HeavyHitters.html) tracker counts records by.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeavyHitterKey {
+    /// The client's IP address, ignoring the port.
+    ClientIp,
+    /// The URL path, ignoring the query string.
+    Path,
+    UserAgent,
+    /// The backend address, including the port.  Records without a backend are not counted.
+    Backend,
+}
+
+/// What each record adds to the count of its key.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeavyHitterWeight {
+    /// One per record.
+    Requests,
+    /// The bytes received from and sent to the client.
+    Bytes,
+}
+
+/// An item reported by a [`SpaceSaving`](struct.SpaceSaving.html) summary.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HeavyHitter {
+    pub item: String,
+    /// An upper bound on the item's true count.
+    pub count: u64,
+    /// How much `count` may overstate the true count, so `count - error` is a lower bound.
+    pub error: u64,
+}
+
+/// The Space-Saving algorithm of Metwally, Agrawal and El Abbadi, which finds the most frequent
+/// items of a stream in fixed memory.
+///
+/// At most `capacity` items are counted.  When a new item arrives and the summary is full, the
+/// item with the smallest count is replaced by the new one, which inherits that count as its
+/// error.  Every item whose true count is more than `total / capacity` is guaranteed to be in the
+/// summary, so keep a few times more items than will be reported to make the top of the
+/// summary accurate.
+#[derive(Debug, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    entries: Vec<HeavyHitter>,
+    positions: HashMap<String, usize>,
+    /// The count and index into `entries` of every entry, so the smallest is found quickly.
+    order: BTreeSet<(u64, usize)>,
+    total: u64,
+}
+
+impl SpaceSaving {
+    /// Create an empty summary counting at most `capacity` items.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn new(capacity: usize) -> SpaceSaving {
+        assert!(capacity > 0, "a Space-Saving summary needs a capacity of at least 1");
+        SpaceSaving {
+            capacity,
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            order: BTreeSet::new(),
+            total: 0,
+        }
+    }
+
+    /// Add `weight` to the count of `item`.
+    pub fn offer(&mut self, item: &str, weight: u64) {
+        self.total += weight;
+        if let Some(&idx) = self.positions.get(item) {
+            let entry = &mut self.entries[idx];
+            self.order.remove(&(entry.count, idx));
+            entry.count += weight;
+            self.order.insert((entry.count, idx));
+        } else if self.entries.len() < self.capacity {
+            let idx = self.entries.len();
+            self.entries.push(HeavyHitter {
+                item: item.to_owned(),
+                count: weight,
+                error: 0,
+            });
+            self.positions.insert(item.to_owned(), idx);
+            self.order.insert((weight, idx));
+        } else {
+            let (min, idx) = *self.order.iter().next().unwrap();
+            self.order.remove(&(min, idx));
+            let entry = &mut self.entries[idx];
+            self.positions.remove(&entry.item);
+            *entry = HeavyHitter {
+                item: item.to_owned(),
+                count: min + weight,
+                error: min,
+            };
+            self.positions.insert(item.to_owned(), idx);
+            self.order.insert((min + weight, idx));
+        }
+    }
+
+    /// The `k` items with the highest counts, highest first.
+    pub fn top(&self, k: usize) -> Vec<HeavyHitter> {
+        self.order.iter().rev().take(k).map(|&(_, idx)| self.entries[idx].clone()).collect()
+    }
+
+    /// The sum of the weights offered so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The number of items being counted.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The heavy hitters of one time window, returned by a [`HeavyHitters`]
+/// (struct.HeavyHitters.html) tracker.
+#[derive(Debug, Clone)]
+pub struct HeavyHittersWindow {
+    /// The start of the window, or the timestamp of the first record when the whole stream is
+    /// tracked.
+    pub start: DateTime<UTC>,
+    pub summary: SpaceSaving,
+}
+
+/// Tracks the clients, paths, user agents or backends with the most requests or bytes, e.g.
+/// to find out who is hammering an ELB during an incident.
+///
+/// Counts are kept in a [`SpaceSaving`](struct.SpaceSaving.html) summary, so memory use is
+/// fixed however many distinct keys there are.  Use one tracker per key and weight of interest.
+///
+/// By default the whole stream is tracked.  With [`with_window`](#method.with_window) it is cut
+/// into windows aligned to the Unix epoch, e.g. every whole minute, and a window's summary is
+/// returned by [`push`](#method.push) once a record from a later window arrives.  Records are
+/// expected to be ordered by timestamp; a record from an earlier window is counted in the
+/// current one.
+///
+/// ```rust,no_run
+/// # let records: Vec<elp::ELBRecord> = Vec::new();
+/// let mut tracker = elp::HeavyHitters::new(elp::HeavyHitterKey::ClientIp, 100)
+///     .with_weight(elp::HeavyHitterWeight::Bytes)
+///     .with_window(std::time::Duration::from_secs(60));
+/// for record in &records {
+///     if let Some(window) = tracker.push(record) {
+///         for hitter in window.summary.top(10) {
+///             println!("{} {} {}", window.start, hitter.item, hitter.count);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeavyHitters {
+    key: HeavyHitterKey,
+    weight: HeavyHitterWeight,
+    window: Option<i64>,
+    /// The start of the current window, in microseconds since the epoch.
+    start: Option<i64>,
+    summary: SpaceSaving,
+}
+
+impl HeavyHitters {
+    /// Count requests by `key` over the whole stream, keeping at most `capacity` keys.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn new(key: HeavyHitterKey, capacity: usize) -> HeavyHitters {
+        HeavyHitters {
+            key,
+            weight: HeavyHitterWeight::Requests,
+            window: None,
+            start: None,
+            summary: SpaceSaving::new(capacity),
+        }
+    }
+
+    /// Add `weight` for each record, one request by default.
+    pub fn with_weight(mut self, weight: HeavyHitterWeight) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Track each window of length `window` separately.
+    ///
+    /// # Panics
+    ///
+    /// If `window` is shorter than a microsecond.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        let window = window.as_secs() as i64 * 1_000_000 + window.subsec_micros() as i64;
+        assert!(window > 0, "the window must be at least a microsecond long");
+        self.window = Some(window);
+        self
+    }
+
+    /// Count `record` and return the previous window's heavy hitters if it is the first record
+    /// of a new window.
+    pub fn push(&mut self, record: &ELBRecord) -> Option<HeavyHittersWindow> {
+        let timestamp = epoch_micros(&record.timestamp);
+        let start = self.window
+            .map_or(timestamp, |window| timestamp - timestamp.rem_euclid(window));
+        let ended = match self.start {
+            Some(current) if self.window.is_some() && start > current => {
+                let summary = SpaceSaving::new(self.summary.capacity);
+                self.start = Some(start);
+                Some(HeavyHittersWindow {
+                    start: from_epoch_micros(current),
+                    summary: mem::replace(&mut self.summary, summary),
+                })
+            }
+            Some(_) => None,
+            None => {
+                self.start = Some(start);
+                None
+            }
+        };
+
+        let weight = match self.weight {
+            HeavyHitterWeight::Requests => 1,
+            HeavyHitterWeight::Bytes => record.received_bytes + record.sent_bytes,
+        };
+        match self.key {
+            HeavyHitterKey::ClientIp => {
+                self.summary.offer(&record.client_address.ip().to_string(), weight)
+            }
+            HeavyHitterKey::Path => {
+                let path = record.url().map_or(record.request_url, |url| url.path);
+                self.summary.offer(path, weight)
+            }
+            HeavyHitterKey::UserAgent => self.summary.offer(record.user_agent, weight),
+            HeavyHitterKey::Backend => {
+                if let Some(backend) = record.backend_address {
+                    self.summary.offer(&backend.to_string(), weight)
+                }
+            }
+        }
+        ended
+    }
+
+    /// The summary of the current window, or of the whole stream.
+    pub fn summary(&self) -> &SpaceSaving {
+        &self.summary
+    }
+
+    /// Return the heavy hitters of the current window, or of the whole stream, or `None` if no
+    /// records were pushed.
+    pub fn finish(self) -> Option<HeavyHittersWindow> {
+        let summary = self.summary;
+        self.start.map(|start| {
+            HeavyHittersWindow {
+                start: from_epoch_micros(start),
+                summary,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod heavy_hitters_tests {
+    use std::time::Duration;
+
+    use parse_record;
+    use super::{HeavyHitter, HeavyHitterKey, HeavyHitterWeight, HeavyHitters, SpaceSaving};
+
+    fn line(time: &str, client: &str, url: &str, sent_bytes: u64) -> String {
+        format!("2015-08-15T{}Z elb-name {}:54814 172.16.1.5:9000 0.000039 0.145507 0.00003 \
+                 200 200 0 {} \"GET http://a.com:80{} HTTP/1.1\" \"curl/7.43.0\" - -",
+                time,
+                client,
+                sent_bytes,
+                url)
+    }
+
+    fn items(hitters: Vec<HeavyHitter>) -> Vec<(String, u64)> {
+        hitters.into_iter().map(|hitter| (hitter.item, hitter.count)).collect()
+    }
+
+    #[test]
+    fn counts_exactly_while_below_capacity() {
+        let mut summary = SpaceSaving::new(3);
+        for item in &["a", "b", "a", "c", "a", "b"] {
+            summary.offer(item, 1);
+        }
+
+        assert_eq!(summary.top(2),
+                   [HeavyHitter {
+                        item: "a".to_owned(),
+                        count: 3,
+                        error: 0,
+                    },
+                    HeavyHitter {
+                        item: "b".to_owned(),
+                        count: 2,
+                        error: 0,
+                    }]);
+        assert_eq!(summary.total(), 6)
+    }
+
+    #[test]
+    fn keeps_frequent_items_when_full() {
+        let mut summary = SpaceSaving::new(4);
+        for idx in 0..1000 {
+            summary.offer("frequent", 1);
+            summary.offer(&format!("rare{}", idx), 1);
+        }
+        let top = summary.top(1);
+
+        assert_eq!(summary.len(), 4);
+        assert_eq!(top[0].item, "frequent");
+        assert!(top[0].count - top[0].error <= 1000 && top[0].count >= 1000)
+    }
+
+    #[test]
+    fn replaces_the_smallest_item_and_records_its_count_as_error() {
+        let mut summary = SpaceSaving::new(2);
+        summary.offer("a", 5);
+        summary.offer("b", 2);
+        summary.offer("c", 1);
+
+        assert_eq!(summary.top(2)[1],
+                   HeavyHitter {
+                       item: "c".to_owned(),
+                       count: 3,
+                       error: 2,
+                   })
+    }
+
+    #[test]
+    fn tracks_keys_by_bytes_over_the_whole_stream() {
+        let mut tracker = HeavyHitters::new(HeavyHitterKey::Path, 10)
+            .with_weight(HeavyHitterWeight::Bytes);
+        for line in &[line("10:00:00.000000", "10.0.0.1", "/a?page=1", 100),
+                      line("10:05:00.000000", "10.0.0.2", "/b", 150),
+                      line("10:10:00.000000", "10.0.0.1", "/a?page=2", 100)] {
+            assert!(tracker.push(&parse_record(line).unwrap()).is_none());
+        }
+        let stream = tracker.finish().unwrap();
+
+        assert_eq!(stream.start.to_rfc3339(), "2015-08-15T10:00:00+00:00");
+        assert_eq!(items(stream.summary.top(2)),
+                   [("/a".to_owned(), 200), ("/b".to_owned(), 150)])
+    }
+
+    #[test]
+    fn returns_each_window_when_the_next_one_starts() {
+        let mut tracker = HeavyHitters::new(HeavyHitterKey::ClientIp, 10)
+            .with_window(Duration::from_secs(60));
+        let mut windows = Vec::new();
+        for line in &[line("10:00:10.000000", "10.0.0.1", "/", 0),
+                      line("10:00:50.000000", "10.0.0.1", "/", 0),
+                      line("10:00:55.000000", "10.0.0.2", "/", 0),
+                      line("10:01:05.000000", "10.0.0.2", "/", 0)] {
+            windows.extend(tracker.push(&parse_record(line).unwrap()));
+        }
+        windows.extend(tracker.finish());
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].start.to_rfc3339(), "2015-08-15T10:00:00+00:00");
+        assert_eq!(items(windows[0].summary.top(1)), [("10.0.0.1".to_owned(), 2)]);
+        assert_eq!(windows[1].start.to_rfc3339(), "2015-08-15T10:01:00+00:00");
+        assert_eq!(items(windows[1].summary.top(5)), [("10.0.0.2".to_owned(), 1)])
+    }
+}
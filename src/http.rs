@@ -0,0 +1,155 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/// The HTTP method of the request recorded by the ELB.
+///
+/// Methods that are not defined by RFC 7231 or RFC 5789 are kept, as they appear in the record,
+/// in the `Other` variant.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum HttpMethod<'a> {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Other(&'a str),
+}
+
+impl<'a> HttpMethod<'a> {
+    /// The method as it is written in an HTTP request line.
+    pub fn as_str(&self) -> &'a str {
+        match *self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Other(method) => method,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for HttpMethod<'a> {
+    fn from(method: &'a str) -> HttpMethod<'a> {
+        match method {
+            "GET" => HttpMethod::Get,
+            "HEAD" => HttpMethod::Head,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "DELETE" => HttpMethod::Delete,
+            "CONNECT" => HttpMethod::Connect,
+            "OPTIONS" => HttpMethod::Options,
+            "TRACE" => HttpMethod::Trace,
+            "PATCH" => HttpMethod::Patch,
+            _ => HttpMethod::Other(method),
+        }
+    }
+}
+
+impl<'a> Display for HttpMethod<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The HTTP protocol version of the request recorded by the ELB.
+///
+/// Versions other than the ones below, e.g. a newer version or whatever a misbehaving client sent,
+/// are kept, as they appear in the record, in the `Other` variant.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum HttpVersion<'a> {
+    Http09,
+    Http10,
+    Http11,
+    Http2,
+    Other(&'a str),
+}
+
+impl<'a> HttpVersion<'a> {
+    /// The version as it is written in an ELB access log record.
+    pub fn as_str(&self) -> &'a str {
+        match *self {
+            HttpVersion::Http09 => "HTTP/0.9",
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+            HttpVersion::Http2 => "HTTP/2.0",
+            HttpVersion::Other(version) => version,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for HttpVersion<'a> {
+    fn from(version: &'a str) -> HttpVersion<'a> {
+        match version {
+            "HTTP/0.9" => HttpVersion::Http09,
+            "HTTP/1.0" => HttpVersion::Http10,
+            "HTTP/1.1" => HttpVersion::Http11,
+            "HTTP/2.0" | "HTTP/2" => HttpVersion::Http2,
+            _ => HttpVersion::Other(version),
+        }
+    }
+}
+
+impl<'a> Display for HttpVersion<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod http_method_tests {
+    use super::HttpMethod;
+
+    #[test]
+    fn returns_the_matching_variant_for_a_standard_method() {
+        assert_eq!(HttpMethod::from("PATCH"), HttpMethod::Patch)
+    }
+
+    #[test]
+    fn returns_other_for_a_non_standard_method() {
+        assert_eq!(HttpMethod::from("PROPFIND"), HttpMethod::Other("PROPFIND"))
+    }
+
+    #[test]
+    fn does_not_match_methods_case_insensitively() {
+        assert_eq!(HttpMethod::from("get"), HttpMethod::Other("get"))
+    }
+
+    #[test]
+    fn displays_the_method_as_it_appears_in_the_request_line() {
+        assert_eq!(format!("{}", HttpMethod::Delete), "DELETE");
+        assert_eq!(format!("{}", HttpMethod::Other("PURGE")), "PURGE")
+    }
+}
+
+#[cfg(test)]
+mod http_version_tests {
+    use super::HttpVersion;
+
+    #[test]
+    fn returns_the_matching_variant_for_every_version_recorded_by_an_elb() {
+        assert_eq!(HttpVersion::from("HTTP/0.9"), HttpVersion::Http09);
+        assert_eq!(HttpVersion::from("HTTP/1.0"), HttpVersion::Http10);
+        assert_eq!(HttpVersion::from("HTTP/1.1"), HttpVersion::Http11);
+        assert_eq!(HttpVersion::from("HTTP/2.0"), HttpVersion::Http2)
+    }
+
+    #[test]
+    fn returns_other_for_an_unrecognized_version() {
+        assert_eq!(HttpVersion::from("HTTP/3.5"), HttpVersion::Other("HTTP/3.5"));
+        assert_eq!(HttpVersion::Other("HTTP/3.5").as_str(), "HTTP/3.5")
+    }
+
+    #[test]
+    fn displays_the_version_as_it_appears_in_the_record() {
+        assert_eq!(format!("{}", HttpVersion::Http11), "HTTP/1.1")
+    }
+}
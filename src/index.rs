@@ -0,0 +1,452 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, UTC};
+
+use {ParsingResult, ReaderPosition, RecordReader, checked_from_epoch_micros, epoch_micros};
+
+const INDEX_HEADER: &str = "elp-time-index 1";
+
+/// An index of the timestamps in a set of log files, so the records in a time window can be read
+/// without scanning every file.
+///
+/// Each file is divided into blocks of about [`with_block_size`](#method.with_block_size) bytes,
+/// ending at line boundaries, and the index records the earliest and latest timestamp in each
+/// block along with where it starts and ends.  Blocks are indexed separately, rather than just
+/// the file as a whole, because records are only roughly ordered and a large file may cover
+/// more than the window of interest.
+///
+/// An index is saved to and loaded from a sidecar file with [`save`](#method.save) and
+/// [`load`](#method.load).  Reading from an indexed file fails with `InvalidData` if its length
+/// has changed since it was indexed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimeIndex {
+    block_size: u64,
+    files: Vec<IndexedFile>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct IndexedFile {
+    path: PathBuf,
+    length: u64,
+    blocks: Vec<IndexedBlock>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct IndexedBlock {
+    start: u64,
+    line_number: u64,
+    end: u64,
+    min: DateTime<UTC>,
+    max: DateTime<UTC>,
+}
+
+/// A part of an indexed file that may hold records in a time window, as returned by
+/// [`TimeIndex::ranges`](struct.TimeIndex.html#method.ranges).
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndexedRange {
+    /// Where to resume reading the file.
+    pub start: ReaderPosition,
+    /// The offset of the end of the range, which is at the end of a line.
+    pub end: u64,
+    /// The earliest timestamp in the range.
+    pub min: DateTime<UTC>,
+    /// The latest timestamp in the range.
+    pub max: DateTime<UTC>,
+}
+
+impl TimeIndex {
+    /// Create an empty index with 1 MiB blocks.
+    pub fn new() -> TimeIndex {
+        TimeIndex {
+            block_size: 1024 * 1024,
+            files: Vec::new(),
+        }
+    }
+
+    /// Divide files added from now on into blocks of at least `block_size` bytes, except for the
+    /// last block in each file.  Smaller blocks make reads more selective but the index larger.
+    pub fn with_block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Index the file at `path`, replacing any earlier entry for the same path.  Lines that are
+    /// not records are ignored, and a block without any records is left out of the index.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut reader = RecordReader::open(path)?;
+        let mut blocks = Vec::new();
+        let mut block: Option<IndexedBlock> = None;
+        let (mut start, mut line_number) = (0, 0);
+        while let Some(result) = reader.next_record()? {
+            if let Ok(record) = result {
+                let timestamp = record.timestamp;
+                block = Some(match block {
+                    Some(block) => {
+                        IndexedBlock {
+                            min: block.min.min(timestamp),
+                            max: block.max.max(timestamp),
+                            ..block
+                        }
+                    }
+                    None => {
+                        IndexedBlock {
+                            start,
+                            line_number,
+                            end: start,
+                            min: timestamp,
+                            max: timestamp,
+                        }
+                    }
+                });
+            }
+            if reader.offset() - start >= self.block_size {
+                if let Some(mut block) = block.take() {
+                    block.end = reader.offset();
+                    blocks.push(block);
+                }
+                start = reader.offset();
+                line_number = reader.line_number();
+            }
+        }
+        if let Some(mut block) = block {
+            block.end = reader.offset();
+            blocks.push(block);
+        }
+
+        let file = IndexedFile {
+            path: path.to_path_buf(),
+            length: fs::metadata(path)?.len(),
+            blocks,
+        };
+        match self.files.iter().position(|indexed| indexed.path == file.path) {
+            Some(i) => self.files[i] = file,
+            None => self.files.push(file),
+        }
+        Ok(())
+    }
+
+    /// The earliest and latest timestamps in the file at `path`, or `None` if it is not indexed
+    /// or has no records.
+    pub fn file_range<P: AsRef<Path>>(&self, path: P) -> Option<(DateTime<UTC>, DateTime<UTC>)> {
+        self.files
+            .iter()
+            .find(|file| file.path == path.as_ref())
+            .and_then(|file| {
+                file.blocks.iter().fold(None, |range, block| {
+                    Some(match range {
+                        Some((min, max)) => (block.min.min(min), block.max.max(max)),
+                        None => (block.min, block.max),
+                    })
+                })
+            })
+    }
+
+    /// The ranges of the indexed files that may hold records from `start` up to, but not
+    /// including, `end`, in the order the files were added.  Adjacent blocks are combined into
+    /// one range.
+    pub fn ranges(&self, start: &DateTime<UTC>, end: &DateTime<UTC>) -> Vec<IndexedRange> {
+        let mut ranges: Vec<IndexedRange> = Vec::new();
+        for file in &self.files {
+            let mut previous_end = None;
+            for block in &file.blocks {
+                if block.min >= *end || block.max < *start {
+                    continue;
+                }
+                match ranges.last_mut() {
+                    Some(range) if previous_end == Some(block.start) => {
+                        range.end = block.end;
+                        range.min = range.min.min(block.min);
+                        range.max = range.max.max(block.max);
+                    }
+                    _ => {
+                        ranges.push(IndexedRange {
+                            start: ReaderPosition {
+                                path: file.path.clone(),
+                                offset: block.start,
+                                line_number: block.line_number,
+                            },
+                            end: block.end,
+                            min: block.min,
+                            max: block.max,
+                        })
+                    }
+                }
+                previous_end = Some(block.end);
+            }
+        }
+        ranges
+    }
+
+    /// Read the records from `start` up to, but not including, `end`, passing each to `handler`
+    /// along with the file it was read from.  Only the ranges returned by
+    /// [`ranges`](#method.ranges) are read.  Lines in them that are not records are passed to
+    /// `handler` as parsing errors.
+    pub fn read_between<F>(&self,
+                           start: &DateTime<UTC>,
+                           end: &DateTime<UTC>,
+                           mut handler: F)
+                           -> io::Result<()>
+        where F: FnMut(&Path, ParsingResult) -> io::Result<()>
+    {
+        for range in self.ranges(start, end) {
+            let path = &range.start.path;
+            let indexed_length = self.files
+                .iter()
+                .find(|file| file.path == *path)
+                .map(|file| file.length);
+            if Some(fs::metadata(path)?.len()) != indexed_length {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "the file has changed since it was indexed"));
+            }
+            let mut reader = RecordReader::resume(&range.start)?;
+            while reader.offset() < range.end {
+                match reader.next_record()? {
+                    Some(Ok(record)) => {
+                        if record.timestamp >= *start && record.timestamp < *end {
+                            handler(path, Ok(record))?;
+                        }
+                    }
+                    Some(Err(errors)) => handler(path, Err(errors))?,
+                    None => break,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the index to the sidecar file at `path`.  The index is written to a temporary file
+    /// next to it first and renamed into place, so a crash never leaves a partial index behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp_name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let mut out = io::BufWriter::new(File::create(&temp_path)?);
+        writeln!(out, "{}", INDEX_HEADER)?;
+        writeln!(out, "block-size {}", self.block_size)?;
+        for file in &self.files {
+            writeln!(out, "file {} {}", file.length, file.path.display())?;
+            for block in &file.blocks {
+                writeln!(out,
+                         "block {} {} {} {} {}",
+                         block.start,
+                         block.line_number,
+                         block.end,
+                         epoch_micros(&block.min),
+                         epoch_micros(&block.max))?;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// Read an index written by [`save`](#method.save).
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<TimeIndex> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        match lines.next() {
+            Some(Ok(ref header)) if header == INDEX_HEADER => {}
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid_index()),
+        }
+        let mut index = TimeIndex::new();
+        for line in lines {
+            let line = line?;
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("block-size"), Some(block_size)) => {
+                    index.block_size = block_size.parse().map_err(|_| invalid_index())?;
+                }
+                (Some("file"), Some(file)) => {
+                    let mut parts = file.splitn(2, ' ');
+                    match (parts.next().and_then(|length| length.parse().ok()), parts.next()) {
+                        (Some(length), Some(path)) if !path.is_empty() => {
+                            index.files.push(IndexedFile {
+                                path: PathBuf::from(path),
+                                length,
+                                blocks: Vec::new(),
+                            })
+                        }
+                        _ => return Err(invalid_index()),
+                    }
+                }
+                (Some("block"), Some(block)) => {
+                    let block = parse_block(block).ok_or_else(invalid_index)?;
+                    index.files.last_mut().ok_or_else(invalid_index)?.blocks.push(block);
+                }
+                (Some(""), None) => {}
+                _ => return Err(invalid_index()),
+            }
+        }
+        Ok(index)
+    }
+}
+
+impl Default for TimeIndex {
+    fn default() -> TimeIndex {
+        TimeIndex::new()
+    }
+}
+
+fn parse_block(block: &str) -> Option<IndexedBlock> {
+    let numbers: Vec<i64> = block.split(' ').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+    if numbers.len() != 5 || numbers[..3].iter().any(|&n| n < 0) {
+        return None;
+    }
+    Some(IndexedBlock {
+        start: numbers[0] as u64,
+        line_number: numbers[1] as u64,
+        end: numbers[2] as u64,
+        min: checked_from_epoch_micros(numbers[3])?,
+        max: checked_from_epoch_micros(numbers[4])?,
+    })
+}
+
+fn invalid_index() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a valid time index")
+}
+
+#[cfg(test)]
+mod time_index_tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use chrono::{DateTime, TimeZone, Timelike, UTC};
+
+    use super::TimeIndex;
+
+    fn record(minute: u32) -> String {
+        format!("2015-08-15T14:{:02}:00.000000Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                 0.000039 0.145507 0.00003 200 200 0 7582 \
+                 \"GET http://some.domain.com:80/ HTTP/1.1\"\n",
+                minute)
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elp-index-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn add_log(dir: &Path, name: &str, minutes: &[u32]) -> PathBuf {
+        let path = dir.join(name);
+        let contents: String = minutes.iter().map(|&minute| record(minute)).collect();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn at(minute: u32) -> DateTime<UTC> {
+        UTC.ymd(2015, 8, 15).and_hms(14, minute, 0)
+    }
+
+    fn minutes_between(index: &TimeIndex, start: u32, end: u32) -> Vec<u32> {
+        let mut minutes = Vec::new();
+        index.read_between(&at(start), &at(end), |_, result| {
+                minutes.push(result.unwrap().timestamp.minute());
+                Ok(())
+            })
+            .unwrap();
+        minutes
+    }
+
+    #[test]
+    fn reads_only_the_blocks_overlapping_a_window() {
+        let dir = test_dir("blocks");
+        let path = add_log(&dir, "a.log", &[0, 1, 3, 2, 4, 6, 5, 7]);
+        let mut index = TimeIndex::new().with_block_size(record(0).len() as u64 * 2);
+        index.add_file(&path).unwrap();
+
+        let ranges = index.ranges(&at(2), &at(5));
+        let minutes = minutes_between(&index, 2, 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+        let line = record(0).len() as u64;
+        assert_eq!(ranges.iter().map(|range| (range.start.offset, range.end)).collect::<Vec<_>>(),
+                   vec![(line * 2, line * 6)]);
+        assert_eq!(ranges[0].start.line_number, 2);
+        assert_eq!(minutes, vec![3, 2, 4])
+    }
+
+    #[test]
+    fn skips_files_outside_a_window() {
+        let dir = test_dir("files");
+        let early = add_log(&dir, "early.log", &[0, 1]);
+        let late = add_log(&dir, "late.log", &[5, 6]);
+        let mut index = TimeIndex::new();
+        index.add_file(&early).unwrap();
+        index.add_file(&late).unwrap();
+
+        let ranges = index.ranges(&at(4), &at(10));
+        let file_range = index.file_range(&late);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].start.path, late);
+        assert_eq!(file_range, Some((at(5), at(6))))
+    }
+
+    #[test]
+    fn round_trips_through_a_sidecar_file() {
+        let dir = test_dir("sidecar");
+        let path = add_log(&dir, "my logs.log", &[0, 1, 2]);
+        let mut index = TimeIndex::new().with_block_size(1);
+        index.add_file(&path).unwrap();
+        index.save(dir.join("index")).unwrap();
+
+        let loaded = TimeIndex::load(dir.join("index")).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, index)
+    }
+
+    #[test]
+    fn refuses_a_sidecar_with_an_out_of_range_timestamp() {
+        let dir = test_dir("corrupt");
+        let path = add_log(&dir, "a.log", &[0, 1]);
+        let mut index = TimeIndex::new();
+        index.add_file(&path).unwrap();
+        index.save(dir.join("index")).unwrap();
+        let contents = fs::read_to_string(dir.join("index")).unwrap();
+        let corrupt: Vec<_> = contents.lines()
+            .map(|line| if line.starts_with("block ") {
+                let mut parts: Vec<_> = line.split(' ').take(4).collect();
+                parts.extend(&["0", "9223372036854775807"]);
+                parts.join(" ")
+            } else {
+                line.to_owned()
+            })
+            .collect();
+        fs::write(dir.join("index"), corrupt.join("\n")).unwrap();
+
+        let result = TimeIndex::load(dir.join("index"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()),
+                   Some(::std::io::ErrorKind::InvalidData))
+    }
+
+    #[test]
+    fn refuses_to_read_a_file_that_changed_after_indexing() {
+        let dir = test_dir("changed");
+        let path = add_log(&dir, "a.log", &[0, 1]);
+        let mut index = TimeIndex::new();
+        index.add_file(&path).unwrap();
+        add_log(&dir, "a.log", &[0]);
+
+        let result = index.read_between(&at(0), &at(10), |_, _| Ok(()));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()),
+                   Some(::std::io::ErrorKind::InvalidData))
+    }
+}
@@ -0,0 +1,353 @@
+use std::io;
+use std::io::Write;
+
+#[cfg(feature = "geoip")]
+use geoip::{GeoField, GeoRecord};
+
+use {ELBRecord, ELBRecordField, ELBRecordParsingError, FieldValue, ParsingErrors, ParsingResult,
+     epoch_micros};
+
+/// How the keys of the JSON objects are named.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FieldNaming {
+    /// The `ELBRecord` field names, e.g. `client_address`.
+    SnakeCase,
+    /// The entry names used by AWS's access log documentation, e.g. `client:port`.  The request
+    /// method, URL and HTTP version are written as a single `request` value, as they appear in
+    /// the record.
+    AwsDocumentation,
+}
+
+/// How timestamps are written.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimestampFormat {
+    /// A string as it appears in the record, e.g. `"2015-08-15T23:43:05.302180Z"`.
+    Rfc3339,
+    /// An integer number of seconds since the Unix epoch.
+    EpochSeconds,
+    /// An integer number of milliseconds since the Unix epoch.
+    EpochMillis,
+    /// An integer number of microseconds since the Unix epoch.
+    EpochMicros,
+}
+
+/// Writes `ELBRecord`s and `ParsingErrors` as newline-delimited JSON objects.
+///
+/// Records are written as one object per line with a key per field.  Numeric fields are JSON
+/// numbers, addresses are `"ip:port"` strings and fields recorded as `-` are `null`.  Records
+/// that could not be parsed are written to the same stream as objects with an `error` key
+/// holding the list of parsing errors:
+///
+/// ```text
+/// {"error":[{"field":"sent_bytes","message":"Parsing of field sent bytes failed ..."}]}
+/// ```
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+    naming: FieldNaming,
+    timestamp_format: TimestampFormat,
+    include_raw: bool,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// A writer using snake_case keys and RFC 3339 timestamps that does not include raw lines.
+    pub fn new(writer: W) -> JsonLinesWriter<W> {
+        JsonLinesWriter {
+            writer,
+            naming: FieldNaming::SnakeCase,
+            timestamp_format: TimestampFormat::Rfc3339,
+            include_raw: false,
+        }
+    }
+
+    pub fn with_field_naming(mut self, naming: FieldNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    /// Add the raw line under the `raw` key of every object written by [`write_result`]
+    /// (#method.write_result) and [`write_errors`](#method.write_errors).
+    pub fn with_raw_line(mut self, include_raw: bool) -> Self {
+        self.include_raw = include_raw;
+        self
+    }
+
+    /// Write a record.  The raw line is not available to this method so it is never included.
+    pub fn write_record(&mut self, record: &ELBRecord) -> io::Result<()> {
+        self.writer.write_all(b"{")?;
+        self.write_fields(record)?;
+        self.writer.write_all(b"}\n")
+    }
+
+    /// Write an enriched record, with the geo fields after the record's fields.  Geo keys are
+    /// always in snake_case, e.g. `client_country`.
+    #[cfg(feature = "geoip")]
+    pub fn write_geo_record(&mut self, record: &GeoRecord) -> io::Result<()> {
+        self.writer.write_all(b"{")?;
+        self.write_fields(record)?;
+        for field in &GeoField::ALL {
+            self.writer.write_all(b",")?;
+            write_json_string(&mut self.writer, field.snake_case_name())?;
+            self.writer.write_all(b":")?;
+            self.write_value(record.client.field(*field))?;
+        }
+        self.writer.write_all(b"}\n")
+    }
+
+    /// Write the errors of a record that could not be parsed.
+    pub fn write_errors(&mut self, errors: &ParsingErrors) -> io::Result<()> {
+        self.writer.write_all(b"{\"error\":[")?;
+        for (idx, error) in errors.errors.iter().enumerate() {
+            if idx > 0 {
+                self.writer.write_all(b",")?;
+            }
+            self.writer.write_all(b"{\"field\":")?;
+            match *error {
+                ELBRecordParsingError::ParsingError { field_name, .. } => {
+                    let key = self.key(field_name);
+                    write_json_string(&mut self.writer, key)?;
+                }
+                ELBRecordParsingError::MalformedRecord => self.writer.write_all(b"null")?,
+            }
+            self.writer.write_all(b",\"message\":")?;
+            write_json_string(&mut self.writer, &error.to_string())?;
+            self.writer.write_all(b"}")?;
+        }
+        self.writer.write_all(b"]")?;
+        if self.include_raw {
+            self.writer.write_all(b",\"raw\":")?;
+            write_json_string(&mut self.writer, errors.record)?;
+        }
+        self.writer.write_all(b"}\n")
+    }
+
+    /// Write the result of parsing `raw_line`, which is either a record or its errors.
+    pub fn write_result(&mut self, raw_line: &str, result: &ParsingResult) -> io::Result<()> {
+        match *result {
+            Ok(ref record) => {
+                self.writer.write_all(b"{")?;
+                self.write_fields(record)?;
+                if self.include_raw {
+                    self.writer.write_all(b",\"raw\":")?;
+                    write_json_string(&mut self.writer, raw_line)?;
+                }
+                self.writer.write_all(b"}\n")
+            }
+            Err(ref errors) => self.write_errors(errors),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush the writer and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn key(&self, field: ELBRecordField) -> &'static str {
+        match self.naming {
+            FieldNaming::SnakeCase => field.snake_case_name(),
+            FieldNaming::AwsDocumentation => field.aws_name(),
+        }
+    }
+
+    fn write_fields(&mut self, record: &ELBRecord) -> io::Result<()> {
+        for (idx, field) in ELBRecordField::ALL.iter().enumerate() {
+            let is_request_part = *field == ELBRecordField::RequestURL ||
+                                  *field == ELBRecordField::RequestHTTPVersion;
+            if self.naming == FieldNaming::AwsDocumentation && is_request_part {
+                continue;
+            }
+            if idx > 0 {
+                self.writer.write_all(b",")?;
+            }
+            let key = self.key(*field);
+            write_json_string(&mut self.writer, key)?;
+            self.writer.write_all(b":")?;
+
+            if self.naming == FieldNaming::AwsDocumentation &&
+               *field == ELBRecordField::RequestMethod {
+                let request = format!("{} {} {}",
+                                      record.field(ELBRecordField::RequestMethod),
+                                      record.field(ELBRecordField::RequestURL),
+                                      record.field(ELBRecordField::RequestHTTPVersion));
+                write_json_string(&mut self.writer, &request)?;
+            } else {
+                self.write_value(record.field(*field))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: FieldValue) -> io::Result<()> {
+        match value {
+            FieldValue::Timestamp(ts) => {
+                let micros = epoch_micros(&ts);
+                match self.timestamp_format {
+                    TimestampFormat::Rfc3339 => {
+                        write_json_string(&mut self.writer, &value.to_string())
+                    }
+                    TimestampFormat::EpochSeconds => write!(self.writer, "{}", ts.timestamp()),
+                    TimestampFormat::EpochMillis => write!(self.writer, "{}", micros / 1_000),
+                    TimestampFormat::EpochMicros => write!(self.writer, "{}", micros),
+                }
+            }
+            FieldValue::Text(text) => write_json_string(&mut self.writer, text),
+            FieldValue::Address(address) => {
+                write_json_string(&mut self.writer, &address.to_string())
+            }
+            FieldValue::Float(float) if float.is_finite() => write!(self.writer, "{}", float),
+            FieldValue::Integer(integer) => write!(self.writer, "{}", integer),
+            FieldValue::Float(_) |
+            FieldValue::Undefined => self.writer.write_all(b"null"),
+        }
+    }
+}
+
+fn write_json_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    let mut unescaped_start = 0;
+    for (idx, byte) in value.bytes().enumerate() {
+        let escaped: Option<&[u8]> = match byte {
+            b'"' => Some(b"\\\""),
+            b'\\' => Some(b"\\\\"),
+            b'\n' => Some(b"\\n"),
+            b'\r' => Some(b"\\r"),
+            b'\t' => Some(b"\\t"),
+            0x00..=0x1f => None,
+            _ => continue,
+        };
+        writer.write_all(&value.as_bytes()[unescaped_start..idx])?;
+        match escaped {
+            Some(escaped) => writer.write_all(escaped)?,
+            None => write!(writer, "\\u{:04x}", byte)?,
+        }
+        unescaped_start = idx + 1;
+    }
+    writer.write_all(&value.as_bytes()[unescaped_start..])?;
+    writer.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod json_lines_writer_tests {
+    use super::FieldNaming;
+    use super::JsonLinesWriter;
+    use super::TimestampFormat;
+    use parse_record;
+
+    const TEST_RECORD: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                               172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                               \"GET http://some.domain.com:80/path0 HTTP/1.1\" \
+                               \"curl/7.38.0 \\x\" - -";
+
+    fn write_test_line<F>(line: &str, configure: F) -> String
+        where F: FnOnce(JsonLinesWriter<Vec<u8>>) -> JsonLinesWriter<Vec<u8>>
+    {
+        let mut writer = configure(JsonLinesWriter::new(Vec::new()));
+        writer.write_result(line, &parse_record(line)).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_a_record_as_a_single_line_object_with_snake_case_keys() {
+        let json = write_test_line(TEST_RECORD, |writer| writer);
+
+        assert_eq!(json,
+                   "{\"timestamp\":\"2015-08-15T23:43:05.302180Z\",\"elb_name\":\"elb-name\",\
+                    \"client_address\":\"172.16.1.6:54814\",\
+                    \"backend_address\":\"172.16.1.5:9000\",\
+                    \"request_processing_time\":0.000039,\
+                    \"backend_processing_time\":0.145507,\
+                    \"response_processing_time\":0.00003,\"elb_status_code\":200,\
+                    \"backend_status_code\":200,\"received_bytes\":0,\"sent_bytes\":7582,\
+                    \"request_method\":\"GET\",\
+                    \"request_url\":\"http://some.domain.com:80/path0\",\
+                    \"request_http_version\":\"HTTP/1.1\",\"user_agent\":\"curl/7.38.0 \\\\x\",\
+                    \"ssl_cipher\":null,\"ssl_protocol\":null}\n")
+    }
+
+    #[test]
+    fn writes_aws_documentation_keys_with_a_combined_request() {
+        let json = write_test_line(TEST_RECORD, |writer| {
+            writer.with_field_naming(FieldNaming::AwsDocumentation)
+        });
+
+        assert!(json.contains("\"elb\":\"elb-name\",\"client:port\":\"172.16.1.6:54814\""));
+        assert!(json.contains("\"request\":\"GET http://some.domain.com:80/path0 HTTP/1.1\","))
+    }
+
+    #[test]
+    fn writes_timestamps_in_the_configured_format() {
+        let json = write_test_line(TEST_RECORD, |writer| {
+            writer.with_timestamp_format(TimestampFormat::EpochMicros)
+        });
+
+        assert!(json.starts_with("{\"timestamp\":1439682185302180,"))
+    }
+
+    #[test]
+    fn includes_the_raw_line_when_configured() {
+        let json = write_test_line(TEST_RECORD, |writer| writer.with_raw_line(true));
+
+        assert!(json.ends_with(",\"raw\":\"2015-08-15T23:43:05.302180Z elb-name \
+                                172.16.1.6:54814 172.16.1.5:9000 0.000039 0.145507 0.00003 200 \
+                                200 0 7582 \\\"GET http://some.domain.com:80/path0 HTTP/1.1\\\" \
+                                \\\"curl/7.38.0 \\\\x\\\" - -\"}\n"))
+    }
+
+    #[test]
+    fn writes_parsing_errors_as_an_error_object() {
+        let bad_record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                          0.000039 0.145507 0.00003 200 200 0 bad_sent_bytes \"GET \
+                          http://some.domain.com:80/path0 HTTP/1.1\"";
+
+        let json = write_test_line(bad_record, |writer| writer);
+
+        assert_eq!(json,
+                   "{\"error\":[{\"field\":\"sent_bytes\",\"message\":\"Parsing of field sent \
+                    bytes failed with the following error: invalid digit found in string.\"}]}\n")
+    }
+
+    #[test]
+    fn writes_a_null_field_for_malformed_records() {
+        let json = write_test_line("not a record", |writer| writer.with_raw_line(true));
+
+        assert_eq!(json,
+                   "{\"error\":[{\"field\":null,\"message\":\"Record is malformed.\"}],\
+                    \"raw\":\"not a record\"}\n")
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        let mut writer = Vec::new();
+        super::write_json_string(&mut writer, "a\u{1}b\tc").unwrap();
+
+        assert_eq!(String::from_utf8(writer).unwrap(), "\"a\\u0001b\\tc\"")
+    }
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn writes_geo_fields_after_the_record() {
+        use geoip::GeoIpDatabase;
+        use geoip::test_databases::city_and_asn;
+
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+        let line = TEST_RECORD.replace("172.16.1.6", "10.2.1.6");
+        let mut writer = JsonLinesWriter::new(Vec::new());
+        writer.write_geo_record(&database.enrich(parse_record(&line).unwrap())).unwrap();
+
+        let json = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert!(json.ends_with(",\"ssl_protocol\":null,\"client_country\":null,\
+                                \"client_city\":null,\"client_asn\":64500,\
+                                \"client_as_organization\":\"Example AS\"}\n"),
+                "{}",
+                json)
+    }
+}
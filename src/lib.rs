@@ -3,6 +3,16 @@ extern crate chrono;
 extern crate log;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "arrow")]
+extern crate arrow_array;
+#[cfg(feature = "arrow")]
+extern crate arrow_schema;
+#[cfg(feature = "geoip")]
+extern crate maxminddb;
+#[cfg(feature = "parquet")]
+extern crate parquet as parquet_crate;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
 
 use self::chrono::{DateTime, UTC};
 use std::error::Error;
@@ -12,6 +22,63 @@ use std::fmt::{Display, Formatter};
 use std::fmt;
 use std::ops::Index;
 
+#[cfg(feature = "arrow")]
+pub mod arrow;
+mod cidr;
+mod dedup;
+mod delimited;
+mod distinct;
+mod filename;
+mod follow;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+mod heavy_hitters;
+mod http;
+mod index;
+mod json;
+mod log_line;
+mod merge;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+mod prometheus;
+pub mod query;
+mod reader;
+mod redact;
+mod sample;
+mod session;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod statsd;
+mod status;
+mod tls;
+mod url;
+#[cfg(feature = "user-agent")]
+pub mod user_agent;
+
+pub use cidr::{AddressField, CidrTable, CidrTableError};
+pub use dedup::{DedupKey, DedupReader, Deduplicator};
+pub use delimited::{DelimitedWriter, HeaderStyle};
+pub use distinct::{DistinctBucket, DistinctCounter, DistinctKey, HyperLogLog};
+pub use filename::{LogFileName, LogFileNameParseError};
+pub use follow::DirectoryFollower;
+pub use heavy_hitters::{HeavyHitter, HeavyHitterKey, HeavyHitterWeight, HeavyHitters,
+                        HeavyHittersWindow, SpaceSaving};
+pub use http::{HttpMethod, HttpVersion};
+pub use index::{IndexedRange, TimeIndex};
+pub use json::{FieldNaming, JsonLinesWriter, TimestampFormat};
+pub use log_line::LogLineWriter;
+pub use merge::{MergedRecord, RecordMerger};
+pub use prometheus::{MetricsServer, PrometheusMetrics, serve_metrics};
+pub use reader::{ReaderPosition, ReaderPositionParseError, RecordReader};
+pub use redact::{AddressRedaction, ParamRedaction, Redactor};
+pub use sample::{Reservoir, SampleKey, Sampler};
+pub use session::{Session, SessionKey, Sessionizer};
+pub use statsd::{StatsdFormat, StatsdSink};
+pub use status::StatusClass;
+pub use tls::{CipherStrength, CipherSuite, TlsProtocol};
+pub use url::{PathSegments, PathTemplater, QueryParams, RequestUrl, SegmentPattern,
+              UrlParseError, percent_decode};
+
 // AWS doesn't version their log file format so these version numbers were
 // selected by me to bring some sanity to the various formats.
 const ELB_RECORD_V1_FIELD_COUNT: usize = 14;
@@ -24,20 +91,130 @@ pub struct ELBRecord<'a> {
     pub timestamp: DateTime<UTC>,
     pub elb_name: &'a str,
     pub client_address: SocketAddrV4,
-    pub backend_address: SocketAddrV4,
+    /// `None` when the ELB could not send the request to a registered instance.
+    pub backend_address: Option<SocketAddrV4>,
     pub request_processing_time: f32,
     pub backend_processing_time: f32,
     pub response_processing_time: f32,
     pub elb_status_code: u16,
-    pub backend_status_code: u16,
+    /// `None` when the backend did not respond, in which case the response was generated by
+    /// the ELB.
+    pub backend_status_code: Option<u16>,
     pub received_bytes: u64,
     pub sent_bytes: u64,
-    pub request_method: &'a str,
+    /// `None` when the ELB recorded `-`, as it does for TCP listeners.
+    pub request_method: Option<HttpMethod<'a>>,
     pub request_url: &'a str,
-    pub request_http_version: &'a str,
+    /// `None` when the ELB recorded `-`, as it does for TCP listeners.
+    pub request_http_version: Option<HttpVersion<'a>>,
     pub user_agent: &'a str,
-    pub ssl_cipher: &'a str,
-    pub ssl_protocol: &'a str,
+    /// `None` for V1 records and for requests to listeners that do not terminate SSL/TLS.
+    pub ssl_cipher: Option<CipherSuite<'a>>,
+    /// `None` for V1 records and for requests to listeners that do not terminate SSL/TLS.
+    pub ssl_protocol: Option<TlsProtocol<'a>>,
+}
+
+impl<'a> ELBRecord<'a> {
+    /// A structured view of [`request_url`](#structfield.request_url).
+    ///
+    /// Returns `None` when the ELB recorded `-`, as it does for TCP listeners, or when the URL
+    /// cannot be split into its components.  Use [`RequestUrl::parse`]
+    /// (struct.RequestUrl.html#method.parse) directly to find out why a URL was rejected.
+    pub fn url(&self) -> Option<RequestUrl<'a>> {
+        RequestUrl::parse(self.request_url).ok()
+    }
+
+    /// The value of a single field.
+    pub fn field(&self, field: ELBRecordField) -> FieldValue<'a> {
+        fn text_or_undefined(text: &str) -> FieldValue<'_> {
+            if text == UNDEFINED_CHAR {
+                FieldValue::Undefined
+            } else {
+                FieldValue::Text(text)
+            }
+        }
+
+        match field {
+            ELBRecordField::Timestamp => FieldValue::Timestamp(self.timestamp),
+            ELBRecordField::ELBName => FieldValue::Text(self.elb_name),
+            ELBRecordField::ClientAddress => FieldValue::Address(self.client_address),
+            ELBRecordField::BackendAddress => {
+                self.backend_address.map_or(FieldValue::Undefined, FieldValue::Address)
+            }
+            ELBRecordField::RequestProcessingTime => {
+                FieldValue::Float(self.request_processing_time)
+            }
+            ELBRecordField::BackendProcessingTime => {
+                FieldValue::Float(self.backend_processing_time)
+            }
+            ELBRecordField::ResponseProcessingTime => {
+                FieldValue::Float(self.response_processing_time)
+            }
+            ELBRecordField::ELBStatusCode => FieldValue::Integer(self.elb_status_code as u64),
+            ELBRecordField::BackendStatusCode => {
+                self.backend_status_code
+                    .map_or(FieldValue::Undefined, |code| FieldValue::Integer(code as u64))
+            }
+            ELBRecordField::ReceivedBytes => FieldValue::Integer(self.received_bytes),
+            ELBRecordField::SentBytes => FieldValue::Integer(self.sent_bytes),
+            ELBRecordField::RequestMethod => {
+                self.request_method
+                    .map_or(FieldValue::Undefined, |method| FieldValue::Text(method.as_str()))
+            }
+            ELBRecordField::RequestURL => text_or_undefined(self.request_url),
+            ELBRecordField::RequestHTTPVersion => {
+                self.request_http_version
+                    .map_or(FieldValue::Undefined, |version| FieldValue::Text(version.as_str()))
+            }
+            ELBRecordField::UserAgent => text_or_undefined(self.user_agent),
+            ELBRecordField::SSLCipher => {
+                self.ssl_cipher
+                    .map_or(FieldValue::Undefined, |cipher| FieldValue::Text(cipher.name()))
+            }
+            ELBRecordField::SSLProtocol => {
+                self.ssl_protocol
+                    .map_or(FieldValue::Undefined, |protocol| FieldValue::Text(protocol.as_str()))
+            }
+        }
+    }
+
+    /// The outcome of the request.  See [`StatusClass`](enum.StatusClass.html) for the rules
+    /// used to classify it.
+    pub fn status_class(&self) -> StatusClass {
+        StatusClass::of(self)
+    }
+}
+
+/// The value of a single field of an `ELBRecord`, as returned by [`ELBRecord::field`]
+/// (struct.ELBRecord.html#method.field).
+///
+/// Typed fields such as the request method are reduced to the text that appears in the record
+/// so every field can be handled uniformly by writers and exporters.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FieldValue<'a> {
+    Timestamp(DateTime<UTC>),
+    Text(&'a str),
+    Address(SocketAddrV4),
+    Float(f32),
+    Integer(u64),
+    /// The field was recorded as `-` or is not present in V1 records.
+    Undefined,
+}
+
+impl<'a> Display for FieldValue<'a> {
+    /// Values are displayed as they appear in an ELB access log record.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            FieldValue::Timestamp(ref ts) => {
+                write!(f, "{}", ts.format("%Y-%m-%dT%H:%M:%S%.6fZ"))
+            }
+            FieldValue::Text(text) => write!(f, "{}", text),
+            FieldValue::Address(ref address) => write!(f, "{}", address),
+            FieldValue::Float(float) => write!(f, "{}", float),
+            FieldValue::Integer(integer) => write!(f, "{}", integer),
+            FieldValue::Undefined => write!(f, "{}", UNDEFINED_CHAR),
+        }
+    }
 }
 
 /// The result of an attempt to parse an ELB record.
@@ -113,23 +290,36 @@ pub fn parse_record(record: &str) -> ParsingResult {
 
     let ts = split_record.parse_field(ELBRecordField::Timestamp, &mut errors);
     let clnt_addr = split_record.parse_field(ELBRecordField::ClientAddress, &mut errors);
-    let be_addr = split_record.parse_field(ELBRecordField::BackendAddress, &mut errors);
+    let be_addr = split_record.parse_optional_field(ELBRecordField::BackendAddress, &mut errors);
     let req_proc_time =
         split_record.parse_field(ELBRecordField::RequestProcessingTime, &mut errors);
     let be_proc_time = split_record.parse_field(ELBRecordField::BackendProcessingTime, &mut errors);
     let res_proc_time =
         split_record.parse_field(ELBRecordField::ResponseProcessingTime, &mut errors);
     let elb_sc = split_record.parse_field(ELBRecordField::ELBStatusCode, &mut errors);
-    let be_sc = split_record.parse_field(ELBRecordField::BackendStatusCode, &mut errors);
+    let be_sc = split_record.parse_optional_field(ELBRecordField::BackendStatusCode, &mut errors);
     let bytes_received = split_record.parse_field(ELBRecordField::ReceivedBytes, &mut errors);
     let bytes_sent = split_record.parse_field(ELBRecordField::SentBytes, &mut errors);
+    let req_http_version = match split_record[ELBRecordField::RequestHTTPVersion] {
+        UNDEFINED_CHAR => None,
+        version => Some(HttpVersion::from(version)),
+    };
+    let req_method = match split_record[ELBRecordField::RequestMethod] {
+        UNDEFINED_CHAR => None,
+        method => Some(HttpMethod::from(method)),
+    };
     let (user_agent, ssl_cipher, ssl_protocol) = if split_len == ELB_RECORD_V2_FIELD_COUNT {
         (split_record[ELBRecordField::UserAgent],
-         split_record[ELBRecordField::SSLCipher],
-         split_record[ELBRecordField::SSLProtocol])
-
+         match split_record[ELBRecordField::SSLCipher] {
+             UNDEFINED_CHAR => None,
+             cipher => Some(CipherSuite::from(cipher)),
+         },
+         match split_record[ELBRecordField::SSLProtocol] {
+             UNDEFINED_CHAR => None,
+             protocol => Some(TlsProtocol::from(protocol)),
+         })
     } else {
-        (UNDEFINED_CHAR, UNDEFINED_CHAR, UNDEFINED_CHAR)
+        (UNDEFINED_CHAR, None, None)
     };
 
     if errors.is_empty() {
@@ -147,9 +337,9 @@ pub fn parse_record(record: &str) -> ParsingResult {
             backend_status_code: be_sc.unwrap(),
             received_bytes: bytes_received.unwrap(),
             sent_bytes: bytes_sent.unwrap(),
-            request_method: split_record[ELBRecordField::RequestMethod],
+            request_method: req_method,
             request_url: split_record[ELBRecordField::RequestURL],
-            request_http_version: split_record[ELBRecordField::RequestHTTPVersion],
+            request_http_version: req_http_version,
             user_agent: user_agent,
             ssl_cipher: ssl_cipher,
             ssl_protocol: ssl_protocol,
@@ -162,6 +352,27 @@ pub fn parse_record(record: &str) -> ParsingResult {
     }
 }
 
+/// The number of microseconds between the Unix epoch and `ts`.
+fn epoch_micros(ts: &DateTime<UTC>) -> i64 {
+    use chrono::Timelike;
+
+    ts.timestamp() * 1_000_000 + (ts.nanosecond() / 1_000) as i64
+}
+
+/// The time `micros` microseconds after the Unix epoch.
+fn from_epoch_micros(micros: i64) -> DateTime<UTC> {
+    checked_from_epoch_micros(micros).expect("timestamp out of range")
+}
+
+/// The time `micros` microseconds after the Unix epoch, or `None` if chrono cannot represent it.
+fn checked_from_epoch_micros(micros: i64) -> Option<DateTime<UTC>> {
+    use chrono::TimeZone;
+
+    UTC.timestamp_opt(micros.div_euclid(1_000_000),
+                      micros.rem_euclid(1_000_000) as u32 * 1_000)
+        .single()
+}
+
 trait RecordSplitter {
     fn split_record(&self) -> Vec<&str>;
 }
@@ -331,6 +542,77 @@ impl<'a> Index<ELBRecordField> for Vec<&'a str> {
     }
 }
 
+impl ELBRecordField {
+    /// Every field in the order it appears in a V2 record.
+    pub const ALL: [ELBRecordField; ELB_RECORD_V2_FIELD_COUNT] =
+        [ELBRecordField::Timestamp,
+         ELBRecordField::ELBName,
+         ELBRecordField::ClientAddress,
+         ELBRecordField::BackendAddress,
+         ELBRecordField::RequestProcessingTime,
+         ELBRecordField::BackendProcessingTime,
+         ELBRecordField::ResponseProcessingTime,
+         ELBRecordField::ELBStatusCode,
+         ELBRecordField::BackendStatusCode,
+         ELBRecordField::ReceivedBytes,
+         ELBRecordField::SentBytes,
+         ELBRecordField::RequestMethod,
+         ELBRecordField::RequestURL,
+         ELBRecordField::RequestHTTPVersion,
+         ELBRecordField::UserAgent,
+         ELBRecordField::SSLCipher,
+         ELBRecordField::SSLProtocol];
+
+    /// The name of the corresponding `ELBRecord` field, e.g. `elb_status_code`.
+    pub fn snake_case_name(&self) -> &'static str {
+        match *self {
+            ELBRecordField::Timestamp => "timestamp",
+            ELBRecordField::ELBName => "elb_name",
+            ELBRecordField::ClientAddress => "client_address",
+            ELBRecordField::BackendAddress => "backend_address",
+            ELBRecordField::RequestProcessingTime => "request_processing_time",
+            ELBRecordField::BackendProcessingTime => "backend_processing_time",
+            ELBRecordField::ResponseProcessingTime => "response_processing_time",
+            ELBRecordField::ELBStatusCode => "elb_status_code",
+            ELBRecordField::BackendStatusCode => "backend_status_code",
+            ELBRecordField::ReceivedBytes => "received_bytes",
+            ELBRecordField::SentBytes => "sent_bytes",
+            ELBRecordField::RequestMethod => "request_method",
+            ELBRecordField::RequestURL => "request_url",
+            ELBRecordField::RequestHTTPVersion => "request_http_version",
+            ELBRecordField::UserAgent => "user_agent",
+            ELBRecordField::SSLCipher => "ssl_cipher",
+            ELBRecordField::SSLProtocol => "ssl_protocol",
+        }
+    }
+
+    /// The name of the access log entry containing the field, as used by [AWS's access log
+    /// documentation](https://docs.aws.amazon.com/elasticloadbalancing/latest/classic/access-log-collection.html).
+    ///
+    /// The request method, URL and HTTP version are all part of the `request` entry.
+    pub fn aws_name(&self) -> &'static str {
+        match *self {
+            ELBRecordField::Timestamp => "timestamp",
+            ELBRecordField::ELBName => "elb",
+            ELBRecordField::ClientAddress => "client:port",
+            ELBRecordField::BackendAddress => "backend:port",
+            ELBRecordField::RequestProcessingTime => "request_processing_time",
+            ELBRecordField::BackendProcessingTime => "backend_processing_time",
+            ELBRecordField::ResponseProcessingTime => "response_processing_time",
+            ELBRecordField::ELBStatusCode => "elb_status_code",
+            ELBRecordField::BackendStatusCode => "backend_status_code",
+            ELBRecordField::ReceivedBytes => "received_bytes",
+            ELBRecordField::SentBytes => "sent_bytes",
+            ELBRecordField::RequestMethod |
+            ELBRecordField::RequestURL |
+            ELBRecordField::RequestHTTPVersion => "request",
+            ELBRecordField::UserAgent => "user_agent",
+            ELBRecordField::SSLCipher => "ssl_cipher",
+            ELBRecordField::SSLProtocol => "ssl_protocol",
+        }
+    }
+}
+
 impl Display for ELBRecordField {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
@@ -362,6 +644,13 @@ trait ELBRecordFieldParser {
                       -> Option<T>
         where T: FromStr,
               T::Err: Error + 'static;
+
+    fn parse_optional_field<T>(&self,
+                               field_name: ELBRecordField,
+                               errors: &mut Vec<ELBRecordParsingError>)
+                               -> Option<Option<T>>
+        where T: FromStr,
+              T::Err: Error + 'static;
 }
 
 impl<'a> ELBRecordFieldParser for Vec<&'a str> {
@@ -379,12 +668,26 @@ impl<'a> ELBRecordFieldParser for Vec<&'a str> {
             Err(e) => {
                 errors.push(ELBRecordParsingError::ParsingError {
                     field_name: field_name,
-                    description: e.description().to_owned(),
+                    description: e.to_string(),
                 });
                 None
             }
         }
     }
+
+    fn parse_optional_field<T>(&self,
+                               field_name: ELBRecordField,
+                               errors: &mut Vec<ELBRecordParsingError>)
+                               -> Option<Option<T>>
+        where T: FromStr,
+              T::Err: Error + 'static
+    {
+        if self[field_name] == UNDEFINED_CHAR {
+            Some(None)
+        } else {
+            self.parse_field(field_name, errors).map(Some)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -393,6 +696,7 @@ mod parse_record_tests {
     use super::ELBRecordParsingError;
     use super::ELBRecordField;
     use super::UNDEFINED_CHAR;
+    use super::{CipherSuite, FieldValue, HttpMethod, HttpVersion, StatusClass, TlsProtocol};
 
     const V1_TEST_RECORD: &'static str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
     172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
@@ -403,36 +707,58 @@ mod parse_record_tests {
         "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 0.000039 0.145507 \
          0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path0/path1?param0=p0&param1=p1 \
          HTTP/1.1\" \"Mozilla/5.0 (cloud; like Mac OS X; en-us) AppleWebKit/537.36.0 (KHTML, like \
-         Gecko) Version/4.0.4 Mobile/7B334b Safari/537.36.0\" some_ssl_cipher some_ssl_protocol";
+         Gecko) Version/4.0.4 Mobile/7B334b Safari/537.36.0\" ECDHE-RSA-AES128-GCM-SHA256 TLSv1.2";
 
     #[test]
-    fn returns_a_record_with_the_ssl_protocol_set_to_a_not_available_symbol_when_it_is_not_present
-        () {
+    fn returns_a_record_without_the_ssl_protocol_when_it_is_not_present() {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.ssl_protocol, UNDEFINED_CHAR)
+        assert_eq!(elb_record.ssl_protocol, None)
     }
 
     #[test]
     fn returns_a_record_with_the_ssl_protocol_when_it_is_present() {
         let elb_record = parse_record(V2_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.ssl_protocol, "some_ssl_protocol")
+        assert_eq!(elb_record.ssl_protocol, Some(TlsProtocol::TlsV1_2))
     }
 
     #[test]
-    fn returns_a_record_with_the_ssl_cipher_set_to_a_not_available_symbol_when_it_is_not_present
-        () {
+    fn returns_a_record_with_an_unrecognized_ssl_protocol() {
+        let record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                      0.000039 0.145507 0.00003 200 200 0 7582 \"GET \
+                      https://some.domain.com:443/path0 HTTP/1.1\" \"curl/7.38.0\" \
+                      ECDHE-RSA-AES128-GCM-SHA256 TLSv1.4";
+
+        let elb_record = parse_record(record).unwrap();
+
+        assert_eq!(elb_record.ssl_protocol, Some(TlsProtocol::Other("TLSv1.4")))
+    }
+
+    #[test]
+    fn returns_a_record_without_the_ssl_cipher_when_it_is_not_present() {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.ssl_cipher, UNDEFINED_CHAR)
+        assert_eq!(elb_record.ssl_cipher, None)
     }
 
     #[test]
     fn returns_a_record_with_the_ssl_cipher_when_it_is_present() {
         let elb_record = parse_record(V2_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.ssl_cipher, "some_ssl_cipher")
+        assert_eq!(elb_record.ssl_cipher,
+                   Some(CipherSuite::from("ECDHE-RSA-AES128-GCM-SHA256")))
+    }
+
+    #[test]
+    fn returns_a_record_without_ssl_fields_for_listeners_that_do_not_terminate_ssl() {
+        let plain_record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                            172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \"GET \
+                            http://some.domain.com:80/path0 HTTP/1.1\" \"curl/7.38.0\" - -";
+
+        let elb_record = parse_record(plain_record).unwrap();
+
+        assert_eq!((elb_record.ssl_cipher, elb_record.ssl_protocol), (None, None))
     }
 
     #[test]
@@ -469,7 +795,29 @@ mod parse_record_tests {
     fn returns_a_record_with_the_request_http_version() {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.request_http_version, "HTTP/1.1")
+        assert_eq!(elb_record.request_http_version, Some(HttpVersion::Http11))
+    }
+
+    #[test]
+    fn returns_a_record_with_an_unrecognized_request_http_version() {
+        let record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                      0.000039 0.145507 0.00003 200 200 0 7582 \"GET \
+                      http://some.domain.com:80/path0/path1?param0=p0&param1=p1 HTTP/7.1\"";
+
+        let elb_record = parse_record(record).unwrap();
+
+        assert_eq!(elb_record.request_http_version, Some(HttpVersion::Other("HTTP/7.1")))
+    }
+
+    #[test]
+    fn returns_a_record_without_a_request_method_or_http_version_for_tcp_listeners() {
+        let tcp_record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                          0.000039 0.145507 0.00003 200 200 0 7582 \"- - -\"";
+
+        let elb_record = parse_record(tcp_record).unwrap();
+
+        assert_eq!((elb_record.request_method, elb_record.request_http_version),
+                   (None, None))
     }
 
     #[test]
@@ -480,11 +828,31 @@ mod parse_record_tests {
                    "http://some.domain.com:80/path0/path1?param0=p0&param1=p1")
     }
 
+    #[test]
+    fn returns_a_record_with_a_structured_view_of_the_request_url() {
+        let elb_record = parse_record(V1_TEST_RECORD).unwrap();
+        let url = elb_record.url().unwrap();
+
+        assert_eq!((url.host, url.port, url.path),
+                   (Some("some.domain.com"), Some(80), "/path0/path1"))
+    }
+
     #[test]
     fn returns_a_record_with_the_request_method() {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.request_method, "GET")
+        assert_eq!(elb_record.request_method, Some(HttpMethod::Get))
+    }
+
+    #[test]
+    fn returns_a_record_with_a_non_standard_request_method() {
+        let purge_record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                            172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                            \"PURGE http://some.domain.com:80/path0 HTTP/1.1\"";
+
+        let elb_record = parse_record(purge_record).unwrap();
+
+        assert_eq!(elb_record.request_method, Some(HttpMethod::Other("PURGE")))
     }
 
     #[test]
@@ -534,7 +902,49 @@ mod parse_record_tests {
     fn returns_a_record_with_the_backend_status_code() {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
-        assert_eq!(elb_record.backend_status_code, 200)
+        assert_eq!(elb_record.backend_status_code, Some(200))
+    }
+
+    #[test]
+    fn returns_a_record_without_a_backend_when_the_elb_could_not_reach_one() {
+        let unreachable_record = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 - -1 -1 \
+                                  -1 503 - 0 0 \"GET http://some.domain.com:80/path0 HTTP/1.1\"";
+
+        let elb_record = parse_record(unreachable_record).unwrap();
+
+        assert_eq!((elb_record.backend_address, elb_record.backend_status_code),
+                   (None, None))
+    }
+
+    #[test]
+    fn returns_the_value_of_a_field_as_it_appears_in_the_record() {
+        let elb_record = parse_record(V2_TEST_RECORD).unwrap();
+
+        let values: Vec<String> = ELBRecordField::ALL.iter()
+            .map(|field| elb_record.field(*field).to_string())
+            .collect();
+
+        assert_eq!(values.join(" "),
+                   "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                    0.000039 0.145507 0.00003 200 200 0 7582 GET \
+                    http://some.domain.com:80/path0/path1?param0=p0&param1=p1 HTTP/1.1 \
+                    Mozilla/5.0 (cloud; like Mac OS X; en-us) AppleWebKit/537.36.0 (KHTML, like \
+                    Gecko) Version/4.0.4 Mobile/7B334b Safari/537.36.0 \
+                    ECDHE-RSA-AES128-GCM-SHA256 TLSv1.2")
+    }
+
+    #[test]
+    fn returns_undefined_for_fields_missing_from_v1_records() {
+        let elb_record = parse_record(V1_TEST_RECORD).unwrap();
+
+        assert_eq!(elb_record.field(ELBRecordField::UserAgent), FieldValue::Undefined)
+    }
+
+    #[test]
+    fn returns_a_record_with_the_status_class() {
+        let elb_record = parse_record(V1_TEST_RECORD).unwrap();
+
+        assert_eq!(elb_record.status_class(), StatusClass::Success)
     }
 
     #[test]
@@ -640,7 +1050,7 @@ mod parse_record_tests {
         let elb_record = parse_record(V1_TEST_RECORD).unwrap();
 
         assert_eq!(elb_record.backend_address,
-                   "172.16.1.5:9000".parse().unwrap())
+                   Some("172.16.1.5:9000".parse().unwrap()))
     }
 
     #[test]
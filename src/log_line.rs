@@ -0,0 +1,80 @@
+use std::io;
+use std::io::Write;
+
+use {ELBRecord, ELBRecordField};
+
+/// Writes `ELBRecord`s back out as ELB access log lines.
+///
+/// Every record is written in the V2 format, so a V1 record gains a `"-"` user agent and `-` SSL
+/// fields.  Parsing a written line gives back an equal record.
+pub struct LogLineWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LogLineWriter<W> {
+    pub fn new(writer: W) -> LogLineWriter<W> {
+        LogLineWriter { writer }
+    }
+
+    /// Write a single record as a line.
+    pub fn write_record(&mut self, record: &ELBRecord) -> io::Result<()> {
+        for (idx, &field) in ELBRecordField::ALL.iter().enumerate() {
+            if idx > 0 {
+                self.writer.write_all(b" ")?;
+            }
+            let value = record.field(field);
+            match field {
+                ELBRecordField::RequestMethod | ELBRecordField::UserAgent => {
+                    write!(self.writer, "\"{}", value)?
+                }
+                _ => write!(self.writer, "{}", value)?,
+            }
+            if field == ELBRecordField::RequestHTTPVersion || field == ELBRecordField::UserAgent {
+                self.writer.write_all(b"\"")?;
+            }
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush the writer and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod log_line_writer_tests {
+    use parse_record;
+    use super::LogLineWriter;
+
+    fn round_trip(line: &str) -> String {
+        let mut writer = LogLineWriter::new(Vec::new());
+        writer.write_record(&parse_record(line).unwrap()).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_a_v2_record_as_it_was_read() {
+        let line = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                    0.000039 0.145507 0.00003 200 200 0 7582 \
+                    \"GET http://some.domain.com:80/path0 HTTP/1.1\" \"curl/7.43.0\" \
+                    ECDHE-RSA-AES128-GCM-SHA256 TLSv1.2";
+
+        assert_eq!(round_trip(line), format!("{}\n", line))
+    }
+
+    #[test]
+    fn writes_undefined_fields_as_dashes() {
+        let line = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 - -1 -1 -1 503 - 0 0 \
+                    \"- - -\"";
+
+        assert_eq!(round_trip(line),
+                   "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 - -1 -1 -1 503 - 0 0 \
+                    \"- - -\" \"-\" - -\n")
+    }
+}
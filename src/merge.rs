@@ -0,0 +1,291 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, UTC};
+
+use reader::read_line_lossy;
+use {ParsingResult, epoch_micros, parse_record};
+
+/// A record returned by a [`RecordMerger`](struct.RecordMerger.html).
+#[derive(Debug)]
+pub struct MergedRecord<'a> {
+    /// The index of the stream the record was read from.
+    pub source: usize,
+    /// The line the record was read from, numbered from 1 and counting empty lines.
+    pub line_number: u64,
+    pub result: ParsingResult<'a>,
+}
+
+/// Merges several streams of records, e.g. the files written by each ELB node for the same
+/// interval, into one ordered by timestamp.
+///
+/// Each stream is expected to be ordered by timestamp, give or take the tolerance set with
+/// [`with_tolerance`](#method.with_tolerance).  A line is held back until a line at least the
+/// tolerance later has been read from the same stream, so lines that are out of order by no more
+/// than the tolerance are returned in order.  At most [`with_max_buffered_lines`]
+/// (#method.with_max_buffered_lines) lines are held back per stream, which bounds memory use;
+/// when a stream reaches the limit its earliest line is returned even if it is not yet known to
+/// be in order.
+///
+/// Records with the same timestamp are returned in the order of their streams, then in the order
+/// they were read.  Lines without a parsable timestamp are returned as parsing errors, ordered as
+/// if they had the latest timestamp read from their stream before them.
+pub struct RecordMerger<R> {
+    sources: Vec<Source<R>>,
+    heads: BinaryHeap<Reverse<(i64, usize, u64)>>,
+    tolerance: i64,
+    max_buffered_lines: usize,
+    started: bool,
+    current: Option<(usize, Pending)>,
+}
+
+struct Source<R> {
+    reader: R,
+    buffer: BinaryHeap<Reverse<Pending>>,
+    /// The latest timestamp read so far, in microseconds since the epoch.
+    latest: Option<i64>,
+    exhausted: bool,
+    sequence: u64,
+    line_number: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Pending {
+    timestamp: i64,
+    sequence: u64,
+    line_number: u64,
+    line: String,
+}
+
+impl<R: BufRead> Source<R> {
+    fn head_is_ready(&self, tolerance: i64) -> bool {
+        match (self.buffer.peek(), self.latest) {
+            (Some(Reverse(head)), Some(latest)) => {
+                head.timestamp <= latest.saturating_sub(tolerance)
+            }
+            _ => false,
+        }
+    }
+
+    /// Read lines until the earliest buffered line can be returned.
+    fn fill(&mut self, tolerance: i64, max_buffered_lines: usize) -> io::Result<()> {
+        let mut line = String::new();
+        while !self.exhausted && !self.head_is_ready(tolerance) &&
+              self.buffer.len() < max_buffered_lines {
+            if read_line_lossy(&mut self.reader, &mut line)? == 0 {
+                self.exhausted = true;
+                break;
+            }
+            self.line_number += 1;
+            let trimmed = line.trim_end_matches(['\r', '\n']);
+            if trimmed.is_empty() {
+                continue;
+            }
+            let timestamp = match parse_timestamp(trimmed) {
+                Some(timestamp) => {
+                    self.latest = Some(self.latest.map_or(timestamp, |l| l.max(timestamp)));
+                    timestamp
+                }
+                None => self.latest.unwrap_or(i64::MIN),
+            };
+            self.buffer.push(Reverse(Pending {
+                timestamp,
+                sequence: self.sequence,
+                line_number: self.line_number,
+                line: trimmed.to_owned(),
+            }));
+            self.sequence += 1;
+        }
+        Ok(())
+    }
+}
+
+fn parse_timestamp(line: &str) -> Option<i64> {
+    line.trim_start()
+        .split(' ')
+        .next()
+        .and_then(|timestamp| timestamp.parse::<DateTime<UTC>>().ok())
+        .map(|timestamp| epoch_micros(&timestamp))
+}
+
+impl<R: BufRead> RecordMerger<R> {
+    /// Merge `readers`, which are numbered in the order given, without any tolerance for lines
+    /// that are out of order.
+    pub fn new(readers: Vec<R>) -> RecordMerger<R> {
+        RecordMerger {
+            sources: readers.into_iter()
+                .map(|reader| {
+                    Source {
+                        reader,
+                        buffer: BinaryHeap::new(),
+                        latest: None,
+                        exhausted: false,
+                        sequence: 0,
+                        line_number: 0,
+                    }
+                })
+                .collect(),
+            heads: BinaryHeap::new(),
+            tolerance: 0,
+            max_buffered_lines: 4096,
+            started: false,
+            current: None,
+        }
+    }
+
+    /// Return lines in order that are up to `tolerance` earlier than a line before them in the
+    /// same stream.
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance.as_secs() as i64 * 1_000_000 + tolerance.subsec_micros() as i64;
+        self
+    }
+
+    /// Hold back at most `max_buffered_lines` lines per stream, 4096 by default.  At least one
+    /// line is always buffered.
+    pub fn with_max_buffered_lines(mut self, max_buffered_lines: usize) -> Self {
+        self.max_buffered_lines = max_buffered_lines.max(1);
+        self
+    }
+
+    fn push_head(&mut self, source: usize) -> io::Result<()> {
+        let (tolerance, max_buffered_lines) = (self.tolerance, self.max_buffered_lines);
+        let source_state = &mut self.sources[source];
+        source_state.fill(tolerance, max_buffered_lines)?;
+        if let Some(Reverse(head)) = source_state.buffer.peek() {
+            self.heads.push(Reverse((head.timestamp, source, head.sequence)));
+        }
+        Ok(())
+    }
+
+    /// Return the earliest record across all streams, or `None` once every stream has ended.
+    pub fn next_record(&mut self) -> io::Result<Option<MergedRecord<'_>>> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                self.push_head(source)?;
+            }
+        }
+        let source = match self.heads.pop() {
+            Some(Reverse((_, source, _))) => source,
+            None => return Ok(None),
+        };
+        let pending = self.sources[source].buffer.pop().map(|Reverse(pending)| pending);
+        self.push_head(source)?;
+        self.current = pending.map(|pending| (source, pending));
+        Ok(self.current.as_ref().map(|&(source, ref pending)| {
+            MergedRecord {
+                source,
+                line_number: pending.line_number,
+                result: parse_record(&pending.line),
+            }
+        }))
+    }
+}
+
+impl RecordMerger<BufReader<File>> {
+    /// Merge the files at `paths`.
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> io::Result<RecordMerger<BufReader<File>>> {
+        let mut readers = Vec::with_capacity(paths.len());
+        for path in paths {
+            readers.push(BufReader::new(File::open(path)?));
+        }
+        Ok(RecordMerger::new(readers))
+    }
+}
+
+#[cfg(test)]
+mod record_merger_tests {
+    use std::time::Duration;
+
+    use super::RecordMerger;
+
+    fn record(second: u32, elb_name: &str) -> String {
+        format!("2015-08-15T23:43:{:02}.000000Z {} 172.16.1.6:54814 172.16.1.5:9000 0.000039 \
+                 0.145507 0.00003 200 200 0 7582 \"GET http://some.domain.com:80/ HTTP/1.1\"",
+                second,
+                elb_name)
+    }
+
+    fn log(seconds: &[u32], elb_name: &str) -> String {
+        let lines: Vec<String> = seconds.iter().map(|&second| record(second, elb_name)).collect();
+        lines.join("\n")
+    }
+
+    fn merge(merger: &mut RecordMerger<&[u8]>) -> Vec<(usize, u32)> {
+        use chrono::Timelike;
+
+        let mut merged = Vec::new();
+        while let Some(record) = merger.next_record().unwrap() {
+            let second = record.result.map(|record| record.timestamp.second()).unwrap_or(99);
+            merged.push((record.source, second));
+        }
+        merged
+    }
+
+    #[test]
+    fn merges_streams_by_timestamp() {
+        let (a, b) = (log(&[1, 3, 5], "a"), log(&[2, 3, 4], "b"));
+        let mut merger = RecordMerger::new(vec![a.as_bytes(), b.as_bytes()]);
+
+        assert_eq!(merge(&mut merger),
+                   vec![(0, 1), (1, 2), (0, 3), (1, 3), (1, 4), (0, 5)])
+    }
+
+    #[test]
+    fn returns_a_line_that_is_not_utf8_as_a_parsing_error_and_carries_on() {
+        let mut a = b"not \xff a record\n".to_vec();
+        a.extend_from_slice(log(&[1, 3], "a").as_bytes());
+        let b = log(&[2], "b");
+        let mut merger = RecordMerger::new(vec![&a[..], b.as_bytes()]);
+
+        assert_eq!(merge(&mut merger), vec![(0, 99), (0, 1), (1, 2), (0, 3)])
+    }
+
+    #[test]
+    fn reorders_lines_within_the_tolerance() {
+        let (a, b) = (log(&[1, 4, 2, 6], "a"), log(&[3, 5], "b"));
+        let mut merger = RecordMerger::new(vec![a.as_bytes(), b.as_bytes()])
+            .with_tolerance(Duration::from_secs(2));
+
+        assert_eq!(merge(&mut merger),
+                   vec![(0, 1), (0, 2), (1, 3), (0, 4), (1, 5), (0, 6)])
+    }
+
+    #[test]
+    fn returns_lines_beyond_the_tolerance_as_they_come() {
+        let a = log(&[1, 4, 2], "a");
+        let mut merger = RecordMerger::new(vec![a.as_bytes()]);
+
+        assert_eq!(merge(&mut merger), vec![(0, 1), (0, 4), (0, 2)])
+    }
+
+    #[test]
+    fn limits_the_lines_held_back() {
+        let a = log(&[3, 2, 1], "a");
+        let mut merger = RecordMerger::new(vec![a.as_bytes()])
+            .with_tolerance(Duration::from_secs(60))
+            .with_max_buffered_lines(2);
+
+        assert_eq!(merge(&mut merger), vec![(0, 2), (0, 1), (0, 3)])
+    }
+
+    #[test]
+    fn returns_unparsable_lines_after_the_preceding_record() {
+        let a = format!("{}\n\nnot a record\n{}", record(1, "a"), record(4, "a"));
+        let b = log(&[2], "b");
+        let mut merger = RecordMerger::new(vec![a.as_bytes(), b.as_bytes()]);
+
+        let mut line_numbers = Vec::new();
+        while let Some(record) = merger.next_record().unwrap() {
+            line_numbers.push((record.source, record.line_number, record.result.is_ok()));
+        }
+
+        assert_eq!(line_numbers,
+                   vec![(0, 1, true), (0, 3, false), (1, 1, true), (0, 4, true)])
+    }
+}
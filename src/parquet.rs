@@ -0,0 +1,483 @@
+//! Archiving of `ELBRecord`s as Parquet files.
+//!
+//! Records are converted using the schema described in [`elp::arrow`](../arrow/index.html).
+//! [`ParquetWriter`](struct.ParquetWriter.html) writes a single file while
+//! [`PartitionedParquetWriter`](struct.PartitionedParquetWriter.html) spreads records over a
+//! Hive-style directory tree such as `elb_name=my-elb/date=2015-08-15/hour=23/part-00000.parquet`
+//! so query engines can prune partitions by ELB and time.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use arrow_schema::ArrowError;
+use parquet_crate::arrow::ArrowWriter;
+use parquet_crate::basic::{Compression as ParquetCompression, GzipLevel, ZstdLevel};
+use parquet_crate::errors::ParquetError;
+use parquet_crate::file::properties::WriterProperties;
+
+use arrow::{AddressEncoding, ELBRecordBatchBuilder};
+#[cfg(feature = "geoip")]
+use geoip::GeoRecord;
+use ELBRecord;
+
+/// The compression codec applied to every column.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+/// Settings shared by every file a writer creates.
+#[derive(Debug, Clone, Copy)]
+pub struct ParquetOptions {
+    /// The maximum number of records in a row group.  Defaults to 128Ki.
+    pub row_group_size: usize,
+    /// Defaults to `Compression::Snappy`.
+    pub compression: Compression,
+    /// Defaults to `AddressEncoding::Utf8`.
+    pub address_encoding: AddressEncoding,
+    /// Whether to add the geo columns described in [`elp::arrow`](../arrow/index.html).
+    /// Defaults to `false`.
+    #[cfg(feature = "geoip")]
+    pub geo_columns: bool,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> ParquetOptions {
+        ParquetOptions {
+            row_group_size: 128 * 1024,
+            compression: Compression::Snappy,
+            address_encoding: AddressEncoding::Utf8,
+            #[cfg(feature = "geoip")]
+            geo_columns: false,
+        }
+    }
+}
+
+impl ParquetOptions {
+    fn writer_properties(&self) -> WriterProperties {
+        let compression = match self.compression {
+            Compression::Uncompressed => ParquetCompression::UNCOMPRESSED,
+            Compression::Snappy => ParquetCompression::SNAPPY,
+            Compression::Gzip => ParquetCompression::GZIP(GzipLevel::default()),
+            Compression::Zstd => ParquetCompression::ZSTD(ZstdLevel::default()),
+        };
+        WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size)
+            .set_compression(compression)
+            .build()
+    }
+
+    // Batches are handed to the Arrow writer in chunks no larger than a row group so a row
+    // group never waits on more than one batch.
+    fn batch_size(&self) -> usize {
+        self.row_group_size.clamp(1, 8 * 1024)
+    }
+}
+
+/// Writes records to a single Parquet file.
+///
+/// [`close`](#method.close) must be called to write the file's footer.  A file that was not
+/// closed is not readable.
+pub struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    builder: ELBRecordBatchBuilder,
+    batch_size: usize,
+    records_written: u64,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W, options: ParquetOptions) -> Result<ParquetWriter<W>, ParquetExportError> {
+        let batch_size = options.batch_size();
+        let builder = ELBRecordBatchBuilder::with_capacity(options.address_encoding, batch_size);
+        #[cfg(feature = "geoip")]
+        let builder = if options.geo_columns {
+            builder.with_geo_columns()
+        } else {
+            builder
+        };
+        let writer =
+            ArrowWriter::try_new(writer, builder.schema(), Some(options.writer_properties()))?;
+        Ok(ParquetWriter {
+            writer,
+            builder,
+            batch_size,
+            records_written: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &ELBRecord) -> Result<(), ParquetExportError> {
+        self.builder.append(record)?;
+        self.record_appended()
+    }
+
+    /// Write an enriched record.  Its geo fields are left out unless the writer was created
+    /// with `geo_columns` set.
+    #[cfg(feature = "geoip")]
+    pub fn write_geo(&mut self, record: &GeoRecord) -> Result<(), ParquetExportError> {
+        self.builder.append_geo(record)?;
+        self.record_appended()
+    }
+
+    fn record_appended(&mut self) -> Result<(), ParquetExportError> {
+        self.records_written += 1;
+        if self.builder.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// The number of records written so far.
+    pub fn records_written(&self) -> u64 {
+        self.records_written
+    }
+
+    /// Write any buffered records and the file footer.
+    pub fn close(mut self) -> Result<(), ParquetExportError> {
+        self.write_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<(), ParquetExportError> {
+        if !self.builder.is_empty() {
+            let batch = self.builder.finish()?;
+            self.writer.write(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes records to a directory tree of Parquet files partitioned by ELB name and/or hour.
+///
+/// Partition directories are named `elb_name=<name>` and `date=<YYYY-MM-DD>/hour=<HH>` (UTC).
+/// Each partition gets its own file, named `part-<n>.parquet` where `n` is the first number
+/// that does not clash with an existing file, so writing into a directory that already holds
+/// files never overwrites them.
+///
+/// At most [`max_open_files`](#method.with_max_open_files) files are open at once.  When a
+/// record arrives for a partition that is not open and the limit has been reached, the least
+/// recently written file is closed.  A later record for the closed partition starts a new file
+/// in the same directory.
+pub struct PartitionedParquetWriter {
+    root: PathBuf,
+    options: ParquetOptions,
+    by_elb_name: bool,
+    by_hour: bool,
+    max_open_files: usize,
+    open: HashMap<PathBuf, OpenPartition>,
+    written_files: Vec<PathBuf>,
+    writes: u64,
+}
+
+struct OpenPartition {
+    writer: ParquetWriter<File>,
+    last_write: u64,
+}
+
+impl PartitionedParquetWriter {
+    /// A writer partitioning by ELB name and hour with default options and at most 64 open
+    /// files.
+    pub fn new<P: AsRef<Path>>(root: P) -> PartitionedParquetWriter {
+        PartitionedParquetWriter {
+            root: root.as_ref().to_path_buf(),
+            options: ParquetOptions::default(),
+            by_elb_name: true,
+            by_hour: true,
+            max_open_files: 64,
+            open: HashMap::new(),
+            written_files: Vec::new(),
+            writes: 0,
+        }
+    }
+
+    pub fn with_options(mut self, options: ParquetOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Whether to create an `elb_name=<name>` directory level.
+    pub fn partition_by_elb_name(mut self, by_elb_name: bool) -> Self {
+        self.by_elb_name = by_elb_name;
+        self
+    }
+
+    /// Whether to create `date=<YYYY-MM-DD>/hour=<HH>` directory levels.
+    pub fn partition_by_hour(mut self, by_hour: bool) -> Self {
+        self.by_hour = by_hour;
+        self
+    }
+
+    pub fn with_max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    pub fn write(&mut self, record: &ELBRecord) -> Result<(), ParquetExportError> {
+        self.partition_writer(record)?.write(record)
+    }
+
+    /// Write an enriched record.  Its geo fields are left out unless the writer's options have
+    /// `geo_columns` set.
+    #[cfg(feature = "geoip")]
+    pub fn write_geo(&mut self, record: &GeoRecord) -> Result<(), ParquetExportError> {
+        self.partition_writer(record)?.write_geo(record)
+    }
+
+    fn partition_writer(&mut self,
+                        record: &ELBRecord)
+                        -> Result<&mut ParquetWriter<File>, ParquetExportError> {
+        let partition = self.partition_dir(record);
+        if !self.open.contains_key(&partition) {
+            if self.open.len() >= self.max_open_files {
+                self.close_least_recently_written()?;
+            }
+            let writer = self.create_file(&partition)?;
+            self.open.insert(partition.clone(),
+                             OpenPartition {
+                                 writer,
+                                 last_write: 0,
+                             });
+        }
+
+        self.writes += 1;
+        let open_partition = self.open.get_mut(&partition).expect("the partition was just opened");
+        open_partition.last_write = self.writes;
+        Ok(&mut open_partition.writer)
+    }
+
+    /// Close every open file and return the paths of all the files written, in the order they
+    /// were created.
+    pub fn close(mut self) -> Result<Vec<PathBuf>, ParquetExportError> {
+        for (_, open_partition) in self.open.drain() {
+            open_partition.writer.close()?;
+        }
+        Ok(self.written_files)
+    }
+
+    fn partition_dir(&self, record: &ELBRecord) -> PathBuf {
+        let mut dir = self.root.clone();
+        if self.by_elb_name {
+            dir.push(format!("elb_name={}", sanitize(record.elb_name)));
+        }
+        if self.by_hour {
+            dir.push(record.timestamp.format("date=%Y-%m-%d").to_string());
+            dir.push(record.timestamp.format("hour=%H").to_string());
+        }
+        dir
+    }
+
+    fn create_file(&mut self, dir: &Path) -> Result<ParquetWriter<File>, ParquetExportError> {
+        fs::create_dir_all(dir)?;
+        let mut part = 0;
+        loop {
+            let path = dir.join(format!("part-{:05}.parquet", part));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    self.written_files.push(path);
+                    return ParquetWriter::new(file, self.options);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => part += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn close_least_recently_written(&mut self) -> Result<(), ParquetExportError> {
+        let oldest = self.open
+            .iter()
+            .min_by_key(|&(_, open_partition)| open_partition.last_write)
+            .map(|(partition, _)| partition.clone());
+        if let Some(partition) = oldest {
+            let open_partition = self.open.remove(&partition).expect("the partition is open");
+            open_partition.writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+fn sanitize(elb_name: &str) -> String {
+    elb_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Returned when records cannot be written to a Parquet file.
+#[derive(Debug)]
+pub enum ParquetExportError {
+    /// A file or directory could not be created.
+    Io(io::Error),
+    /// Records could not be converted into an Arrow batch.
+    Arrow(ArrowError),
+    /// The Parquet encoder failed.
+    Parquet(ParquetError),
+}
+
+impl Display for ParquetExportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ParquetExportError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ParquetExportError::Arrow(ref e) => write!(f, "Arrow conversion failed: {}", e),
+            ParquetExportError::Parquet(ref e) => write!(f, "Parquet encoding failed: {}", e),
+        }
+    }
+}
+
+impl Error for ParquetExportError {
+    fn description(&self) -> &str {
+        match *self {
+            ParquetExportError::Io(_) => "I/O error",
+            ParquetExportError::Arrow(_) => "Arrow conversion failed",
+            ParquetExportError::Parquet(_) => "Parquet encoding failed",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ParquetExportError::Io(ref e) => Some(e),
+            ParquetExportError::Arrow(ref e) => Some(e),
+            ParquetExportError::Parquet(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ParquetExportError {
+    fn from(e: io::Error) -> ParquetExportError {
+        ParquetExportError::Io(e)
+    }
+}
+
+impl From<ArrowError> for ParquetExportError {
+    fn from(e: ArrowError) -> ParquetExportError {
+        ParquetExportError::Arrow(e)
+    }
+}
+
+impl From<ParquetError> for ParquetExportError {
+    fn from(e: ParquetError) -> ParquetExportError {
+        ParquetExportError::Parquet(e)
+    }
+}
+
+#[cfg(test)]
+mod parquet_tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::process;
+
+    use parquet_crate::file::reader::{FileReader, SerializedFileReader};
+
+    use super::{Compression, ParquetOptions, ParquetWriter, PartitionedParquetWriter};
+    use parse_record;
+
+    const TEST_RECORDS: [&str; 3] =
+        ["2015-08-15T23:43:05.302180Z elb-a 172.16.1.6:54814 172.16.1.5:9000 0.000039 0.145507 \
+          0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path0 HTTP/1.1\"",
+         "2015-08-15T23:59:59.000000Z elb-a 172.16.1.6:54815 172.16.1.5:9000 0.000039 0.145507 \
+          0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path1 HTTP/1.1\"",
+         "2015-08-16T00:00:01.000000Z elb-b 172.16.1.6:54816 - -1 -1 -1 503 - 0 0 \
+          \"GET http://some.domain.com:80/path2 HTTP/1.1\""];
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elp-parquet-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn row_counts(path: &PathBuf) -> Vec<i64> {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        reader.metadata().row_groups().iter().map(|row_group| row_group.num_rows()).collect()
+    }
+
+    #[test]
+    fn writes_row_groups_no_larger_than_the_row_group_size() {
+        let dir = test_dir("row-groups");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("records.parquet");
+        let options = ParquetOptions {
+            row_group_size: 2,
+            compression: Compression::Zstd,
+            ..ParquetOptions::default()
+        };
+
+        let mut writer = ParquetWriter::new(File::create(&path).unwrap(), options).unwrap();
+        for line in TEST_RECORDS.iter() {
+            writer.write(&parse_record(line).unwrap()).unwrap();
+        }
+        writer.close().unwrap();
+
+        assert_eq!(row_counts(&path), vec![2, 1]);
+        fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn partitions_records_by_elb_name_and_hour() {
+        let dir = test_dir("partitions");
+
+        let mut writer = PartitionedParquetWriter::new(&dir);
+        for line in TEST_RECORDS.iter() {
+            writer.write(&parse_record(line).unwrap()).unwrap();
+        }
+        let files = writer.close().unwrap();
+
+        assert_eq!(files,
+                   vec![dir.join("elb_name=elb-a/date=2015-08-15/hour=23/part-00000.parquet"),
+                        dir.join("elb_name=elb-b/date=2015-08-16/hour=00/part-00000.parquet")]);
+        assert_eq!(row_counts(&files[0]), vec![2]);
+        fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn starts_a_new_file_when_a_closed_partition_is_written_again() {
+        let dir = test_dir("reopen");
+
+        let mut writer = PartitionedParquetWriter::new(&dir)
+            .partition_by_hour(false)
+            .with_max_open_files(1);
+        for idx in &[0, 2, 1] {
+            writer.write(&parse_record(TEST_RECORDS[*idx]).unwrap()).unwrap();
+        }
+        let files = writer.close().unwrap();
+
+        assert_eq!(files,
+                   vec![dir.join("elb_name=elb-a/part-00000.parquet"),
+                        dir.join("elb_name=elb-b/part-00000.parquet"),
+                        dir.join("elb_name=elb-a/part-00001.parquet")]);
+        fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn adds_geo_columns_when_configured() {
+        use geoip::GeoIpDatabase;
+        use geoip::test_databases::city_and_asn;
+
+        let dir = test_dir("geo");
+        let database = GeoIpDatabase::from_bytes(city_and_asn()).unwrap();
+        let options = ParquetOptions {
+            geo_columns: true,
+            ..ParquetOptions::default()
+        };
+
+        let mut writer = PartitionedParquetWriter::new(&dir).with_options(options);
+        for line in TEST_RECORDS.iter() {
+            writer.write_geo(&database.enrich(parse_record(line).unwrap())).unwrap();
+        }
+        let files = writer.close().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&files[0]).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert_eq!((schema.num_columns(), schema.column(20).name()),
+                   (21, "client_as_organization"));
+        fs::remove_dir_all(&dir).unwrap()
+    }
+}
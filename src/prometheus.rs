@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use {ELBRecord, ParsingErrors};
+
+/// The default Prometheus client library buckets, in seconds.
+const DEFAULT_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Prometheus metrics derived from `ELBRecord`s.
+///
+/// The following metrics are maintained, all labelled with the ELB's name as `elb`:
+///
+/// * `elb_requests_total` - a counter of requests, also labelled with the ELB's `status` code,
+///   the request `method` and the `backend` address.  Undefined methods and backends are `-`.
+/// * `elb_request_processing_seconds`, `elb_backend_processing_seconds` and
+///   `elb_response_processing_seconds` - histograms of the three processing times.  The ELB
+///   records `-1` when it could not send a request to a backend; those times are not observed.
+/// * `elb_received_bytes_total` and `elb_sent_bytes_total` - counters of request and response
+///   sizes.
+///
+/// `elb_parsing_errors_total` counts the lines passed to [`record_parsing_errors`]
+/// (#method.record_parsing_errors).
+///
+/// The metrics can be written in the text exposition format with [`write_to`](#method.write_to),
+/// written to a file for the node exporter's textfile collector with [`write_textfile`]
+/// (#method.write_textfile), or served over HTTP with
+/// [`serve_metrics`](fn.serve_metrics.html).
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    buckets: Vec<f64>,
+    requests: BTreeMap<RequestLabels, u64>,
+    request_processing: BTreeMap<String, Histogram>,
+    backend_processing: BTreeMap<String, Histogram>,
+    response_processing: BTreeMap<String, Histogram>,
+    received_bytes: BTreeMap<String, u64>,
+    sent_bytes: BTreeMap<String, u64>,
+    parsing_errors: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct RequestLabels {
+    elb: String,
+    status: u16,
+    method: String,
+    backend: String,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// The number of observations that fell into each bucket, not cumulative.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Histogram {
+        Histogram {
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, buckets: &[f64], value: f64) {
+        if let Some(index) = buckets.iter().position(|&bound| value <= bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> PrometheusMetrics {
+        PrometheusMetrics::new()
+    }
+}
+
+impl PrometheusMetrics {
+    /// Metrics using the default Prometheus histogram buckets, from 5ms to 10s.
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics {
+            buckets: DEFAULT_BUCKETS.to_vec(),
+            requests: BTreeMap::new(),
+            request_processing: BTreeMap::new(),
+            backend_processing: BTreeMap::new(),
+            response_processing: BTreeMap::new(),
+            received_bytes: BTreeMap::new(),
+            sent_bytes: BTreeMap::new(),
+            parsing_errors: 0,
+        }
+    }
+
+    /// Use `buckets`, upper bounds in seconds, for the processing time histograms.  They are
+    /// sorted and deduplicated; the `+Inf` bucket is always present.
+    ///
+    /// Changing the buckets discards the histograms observed so far.
+    pub fn with_buckets(mut self, buckets: &[f64]) -> Self {
+        let mut buckets: Vec<f64> =
+            buckets.iter().cloned().filter(|bound| bound.is_finite()).collect();
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        buckets.dedup();
+        self.buckets = buckets;
+        self.request_processing.clear();
+        self.backend_processing.clear();
+        self.response_processing.clear();
+        self
+    }
+
+    /// Update the metrics with a record.
+    pub fn record(&mut self, record: &ELBRecord) {
+        let labels = RequestLabels {
+            elb: record.elb_name.to_owned(),
+            status: record.elb_status_code,
+            method: record.request_method.map_or(::UNDEFINED_CHAR, |m| m.as_str()).to_owned(),
+            backend: record.backend_address
+                .map_or_else(|| ::UNDEFINED_CHAR.to_owned(), |a| a.to_string()),
+        };
+        *self.requests.entry(labels).or_insert(0) += 1;
+
+        for (histograms, time) in
+            [(&mut self.request_processing, record.request_processing_time),
+             (&mut self.backend_processing, record.backend_processing_time),
+             (&mut self.response_processing, record.response_processing_time)] {
+            if time >= 0.0 {
+                let buckets = &self.buckets;
+                histograms.entry(record.elb_name.to_owned())
+                    .or_insert_with(|| Histogram::new(buckets))
+                    .observe(buckets, time as f64);
+            }
+        }
+
+        *self.received_bytes.entry(record.elb_name.to_owned()).or_insert(0) +=
+            record.received_bytes;
+        *self.sent_bytes.entry(record.elb_name.to_owned()).or_insert(0) += record.sent_bytes;
+    }
+
+    /// Count a line that could not be parsed.
+    pub fn record_parsing_errors(&mut self, _errors: &ParsingErrors) {
+        self.parsing_errors += 1;
+    }
+
+    /// Write the metrics in the Prometheus text exposition format.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_header(&mut writer,
+                     "elb_requests_total",
+                     "Requests handled by the ELB.",
+                     "counter")?;
+        for (labels, count) in &self.requests {
+            writeln!(writer,
+                     "elb_requests_total{{elb=\"{}\",status=\"{}\",method=\"{}\",\
+                      backend=\"{}\"}} {}",
+                     escape(&labels.elb),
+                     labels.status,
+                     escape(&labels.method),
+                     escape(&labels.backend),
+                     count)?;
+        }
+
+        for &(name, help, histograms) in
+            &[("elb_request_processing_seconds",
+               "Time from the ELB receiving a request to sending it to a backend.",
+               &self.request_processing),
+              ("elb_backend_processing_seconds",
+               "Time from the ELB sending a request to a backend to the backend responding.",
+               &self.backend_processing),
+              ("elb_response_processing_seconds",
+               "Time from the ELB receiving a backend's response to sending it to the client.",
+               &self.response_processing)] {
+            write_header(&mut writer, name, help, "histogram")?;
+            for (elb, histogram) in histograms {
+                self.write_histogram(&mut writer, name, elb, histogram)?;
+            }
+        }
+
+        for &(name, help, counters) in
+            &[("elb_received_bytes_total",
+               "Bytes received by the ELB from clients.",
+               &self.received_bytes),
+              ("elb_sent_bytes_total", "Bytes sent by the ELB to clients.", &self.sent_bytes)] {
+            write_header(&mut writer, name, help, "counter")?;
+            for (elb, count) in counters {
+                writeln!(writer, "{}{{elb=\"{}\"}} {}", name, escape(elb), count)?;
+            }
+        }
+
+        write_header(&mut writer,
+                     "elb_parsing_errors_total",
+                     "Lines that could not be parsed as ELB records.",
+                     "counter")?;
+        writeln!(writer, "elb_parsing_errors_total {}", self.parsing_errors)
+    }
+
+    fn write_histogram<W: Write>(&self,
+                                 writer: &mut W,
+                                 name: &str,
+                                 elb: &str,
+                                 histogram: &Histogram)
+                                 -> io::Result<()> {
+        let elb = escape(elb);
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(&histogram.counts) {
+            cumulative += count;
+            writeln!(writer, "{}_bucket{{elb=\"{}\",le=\"{}\"}} {}", name, elb, bound, cumulative)?;
+        }
+        writeln!(writer,
+                 "{}_bucket{{elb=\"{}\",le=\"+Inf\"}} {}",
+                 name,
+                 elb,
+                 histogram.count)?;
+        writeln!(writer, "{}_sum{{elb=\"{}\"}} {}", name, elb, histogram.sum)?;
+        writeln!(writer, "{}_count{{elb=\"{}\"}} {}", name, elb, histogram.count)
+    }
+
+    /// Write the metrics to the file at `path`, e.g. `elb.prom` in the node exporter's textfile
+    /// collector directory.  The metrics are written to a temporary file in the same directory
+    /// that is then renamed, so the collector never reads a partially written file.
+    pub fn write_textfile<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp_name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let mut file = io::BufWriter::new(fs::File::create(&temp_path)?);
+        self.write_to(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, path)
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W,
+                          name: &str,
+                          help: &str,
+                          metric_type: &str)
+                          -> io::Result<()> {
+    writeln!(writer, "# HELP {} {}", name, help)?;
+    writeln!(writer, "# TYPE {} {}", name, metric_type)
+}
+
+/// Escape a label value as required by the text exposition format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serve `metrics` on `GET /metrics` at `address`, e.g. `127.0.0.1:9100`, from a background
+/// thread.  Records are added by locking the same `PrometheusMetrics`.
+///
+/// Requests are handled one at a time, which is enough for a Prometheus server scraping
+/// periodically.  Any other path is answered with a 404.
+pub fn serve_metrics<A: ToSocketAddrs>(metrics: Arc<Mutex<PrometheusMetrics>>,
+                                       address: A)
+                                       -> io::Result<MetricsServer> {
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread_stopped = stopped.clone();
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            if thread_stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            if let Ok(stream) = stream {
+                // A client that goes away mid-request only affects its own scrape.
+                let _ = respond(stream, &metrics);
+            }
+        }
+    });
+    Ok(MetricsServer {
+        local_address,
+        stopped,
+        handle: Some(handle),
+    })
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<PrometheusMetrics>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(&stream);
+        reader.read_line(&mut request_line)?;
+        // Skip the headers; requests to this endpoint have no body.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let mut body = Vec::new();
+            metrics.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .write_to(&mut body)?;
+            ("200 OK", body)
+        }
+        _ => ("404 Not Found", b"Not Found\n".to_vec()),
+    };
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           status,
+           CONTENT_TYPE,
+           body.len())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// A running metrics endpoint started by [`serve_metrics`](fn.serve_metrics.html).  The
+/// endpoint is stopped when this is dropped.
+pub struct MetricsServer {
+    local_address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// The address the endpoint is listening on, e.g. to find the port chosen when binding to
+    /// port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Stop serving and wait for the background thread to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stopped.store(true, Ordering::SeqCst);
+            // Wake the listener up so it notices that it has been stopped.
+            let _ = TcpStream::connect(self.local_address);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod prometheus_metrics_tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    use parse_record;
+    use super::{PrometheusMetrics, serve_metrics};
+
+    const TEST_RECORDS: &[&str] =
+        &["2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 0.000039 \
+           0.145507 0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path0 HTTP/1.1\"",
+          "2015-08-15T23:43:06.000000Z elb-name 172.16.1.7:54815 172.16.1.5:9000 0.000039 \
+           3.2 0.00003 200 200 10 100 \"GET http://some.domain.com:80/path1 HTTP/1.1\"",
+          "2015-08-15T23:43:07.000000Z elb-name 172.16.1.7:54815 - -1 -1 -1 503 - 0 0 \
+           \"- - -\""];
+
+    fn test_metrics() -> PrometheusMetrics {
+        let mut metrics = PrometheusMetrics::new();
+        for line in TEST_RECORDS {
+            metrics.record(&parse_record(line).unwrap());
+        }
+        metrics.record_parsing_errors(&parse_record("not a record").unwrap_err());
+        metrics
+    }
+
+    fn exposition(metrics: &PrometheusMetrics) -> String {
+        let mut output = Vec::new();
+        metrics.write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn counts_requests_by_status_method_and_backend() {
+        let output = exposition(&test_metrics());
+
+        assert!(output.contains("elb_requests_total{elb=\"elb-name\",status=\"200\",\
+                                 method=\"GET\",backend=\"172.16.1.5:9000\"} 2\n"));
+        assert!(output.contains("elb_requests_total{elb=\"elb-name\",status=\"503\",\
+                                 method=\"-\",backend=\"-\"} 1\n"))
+    }
+
+    #[test]
+    fn writes_cumulative_histogram_buckets() {
+        let output = exposition(&test_metrics());
+
+        assert!(output.contains("elb_backend_processing_seconds_bucket{elb=\"elb-name\",\
+                                 le=\"0.25\"} 1\n"));
+        assert!(output.contains("elb_backend_processing_seconds_bucket{elb=\"elb-name\",\
+                                 le=\"5\"} 2\n"));
+        assert!(output.contains("elb_backend_processing_seconds_bucket{elb=\"elb-name\",\
+                                 le=\"+Inf\"} 2\n"));
+        assert!(output.contains("elb_backend_processing_seconds_count{elb=\"elb-name\"} 2\n"))
+    }
+
+    #[test]
+    fn uses_the_configured_buckets() {
+        let mut metrics = PrometheusMetrics::new().with_buckets(&[1.0, 0.1]);
+        metrics.record(&parse_record(TEST_RECORDS[0]).unwrap());
+
+        let output = exposition(&metrics);
+
+        assert!(output.contains("elb_backend_processing_seconds_bucket{elb=\"elb-name\",\
+                                 le=\"0.1\"} 0\nelb_backend_processing_seconds_bucket{\
+                                 elb=\"elb-name\",le=\"1\"} 1\n"))
+    }
+
+    #[test]
+    fn counts_bytes_and_parsing_errors() {
+        let output = exposition(&test_metrics());
+
+        assert!(output.contains("elb_received_bytes_total{elb=\"elb-name\"} 10\n"));
+        assert!(output.contains("elb_sent_bytes_total{elb=\"elb-name\"} 7682\n"));
+        assert!(output.contains("elb_parsing_errors_total 1\n"))
+    }
+
+    #[test]
+    fn writes_a_textfile() {
+        let path = env::temp_dir().join(format!("elp-metrics-{}.prom", process::id()));
+
+        test_metrics().write_textfile(&path).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, exposition(&test_metrics()))
+    }
+
+    fn get(address: ::std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_the_metrics_endpoint() {
+        let metrics = Arc::new(Mutex::new(PrometheusMetrics::new()));
+        let server = serve_metrics(metrics.clone(), "127.0.0.1:0").unwrap();
+        metrics.lock().unwrap().record(&parse_record(TEST_RECORDS[0]).unwrap());
+
+        let response = get(server.local_addr(), "/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("elb_sent_bytes_total{elb=\"elb-name\"} 7582\n"));
+        server.shutdown()
+    }
+
+    #[test]
+    fn answers_other_paths_with_not_found() {
+        let server = serve_metrics(Arc::new(Mutex::new(PrometheusMetrics::new())), "127.0.0.1:0")
+            .unwrap();
+
+        assert!(get(server.local_addr(), "/").starts_with("HTTP/1.1 404 Not Found\r\n"))
+    }
+}
@@ -0,0 +1,1067 @@
+//! SQL queries over ELB access logs without loading them into a database first.
+//!
+//! A [`Query`](struct.Query.html) treats log lines as the rows of a table named `logs` with a
+//! column per [`ELBRecordField`](../enum.ELBRecordField.html), named as returned by
+//! `snake_case_name`.  The supported subset of SQL is
+//!
+//! ```text
+//! SELECT * | expr [AS name], ...
+//! FROM logs
+//! [WHERE expr]
+//! [GROUP BY expr | position, ...]
+//! [ORDER BY expr | name | position [ASC | DESC], ...]
+//! [LIMIT count]
+//! ```
+//!
+//! where expressions are built from columns, numbers, `'text'`, `NULL`, arithmetic, comparisons,
+//! `AND`, `OR`, `NOT`, `IS [NOT] NULL`, `[NOT] LIKE`, `[NOT] IN (...)`, `[NOT] BETWEEN` and the
+//! aggregates `count`, `sum`, `avg`, `min` and `max`.  Fields recorded as `-` are `NULL`, as are
+//! the fields V1 records do not have.  Timestamps compare with text such as `'2015-08-15T14:02'`
+//! or `'2015-08-15'`, which is taken to be UTC.
+//!
+//! Lines are not parsed into `ELBRecord`s.  Only the fields a query refers to are parsed, and
+//! the fields it selects are only parsed once the `WHERE` clause has accepted a line, so
+//! selective queries over a few columns do far less work than parsing every record.  A
+//! consequence is that a line is only rejected when a field the query needs cannot be parsed;
+//! such lines are skipped and counted in [`QueryResult::lines_skipped`]
+//! (struct.QueryResult.html#structfield.lines_skipped).
+//!
+//! ```
+//! use elp::query::{Query, Value};
+//!
+//! let query = Query::parse("SELECT elb_status_code, count(*) FROM logs \
+//!                           WHERE request_method = 'GET' GROUP BY 1 ORDER BY 2 DESC")
+//!     .unwrap();
+//! let result = query.execute_lines(vec![
+//!     "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 0.000039 \
+//!      0.145507 0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path0 HTTP/1.1\"",
+//! ]);
+//!
+//! assert_eq!(result.rows, vec![vec![Value::Integer(200), Value::Integer(1)]]);
+//! ```
+
+mod parser;
+mod value;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddrV4;
+use std::path::Path;
+
+use chrono::{DateTime, UTC};
+
+use {ELBRecordField, HttpVersion, RecordSplitter, TlsProtocol};
+use ELBRecordFieldParser;
+use reader::read_line_lossy;
+use self::parser::{Aggregate, BinaryOp, Expr, Select, Term};
+use self::value::GroupKey;
+
+pub use self::value::Value;
+
+const TABLE_NAME: &str = "logs";
+
+/// A parsed and planned query.  See the [module documentation](index.html) for the supported
+/// SQL.
+#[derive(Debug)]
+pub struct Query {
+    columns: Vec<String>,
+    items: Vec<Expr>,
+    filter: Option<Expr>,
+    group_by: Vec<Expr>,
+    /// Expressions the result is ordered by that are not among its columns.
+    hidden: Vec<Expr>,
+    order_by: Vec<(SortKey, bool)>,
+    aggregates: Vec<(Aggregate, Option<Expr>)>,
+    grouped: bool,
+    limit: Option<u64>,
+    /// The fields a group's columns are taken from, outside of aggregates.
+    group_fields: Vec<ELBRecordField>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SortKey {
+    Column(usize),
+    Hidden(usize),
+}
+
+impl Query {
+    pub fn parse(sql: &str) -> Result<Query, QueryError> {
+        Query::plan(parser::parse(sql)?)
+    }
+
+    fn plan(select: Select) -> Result<Query, QueryError> {
+        if !select.table.eq_ignore_ascii_case(TABLE_NAME) {
+            return Err(QueryError::UnknownTable(select.table));
+        }
+
+        let (columns, mut items): (Vec<String>, Vec<Expr>) = if select.items.is_empty() {
+            ELBRecordField::ALL.iter()
+                .map(|field| (field.snake_case_name().to_owned(), Expr::Column(*field)))
+                .unzip()
+        } else {
+            select.items
+                .into_iter()
+                .map(|item| {
+                    let expr = item.expr;
+                    (item.alias.unwrap_or_else(|| expr.to_string()), expr)
+                })
+                .unzip()
+        };
+        for item in &items {
+            check_columns(item)?;
+        }
+
+        let filter = match select.filter {
+            Some(filter) => {
+                check_columns(&filter)?;
+                if filter.contains_aggregate() {
+                    return Err(QueryError::Invalid("aggregates are not allowed in WHERE"
+                        .to_owned()));
+                }
+                Some(filter)
+            }
+            None => None,
+        };
+
+        let mut group_by = Vec::new();
+        for term in select.group_by {
+            let expr = match resolve(term, &columns, &items)? {
+                Ok(index) => items[index].clone(),
+                Err(expr) => expr,
+            };
+            if expr.contains_aggregate() {
+                return Err(QueryError::Invalid("aggregates are not allowed in GROUP BY"
+                    .to_owned()));
+            }
+            group_by.push(expr);
+        }
+
+        let mut hidden = Vec::new();
+        let mut order_by = Vec::new();
+        for (term, descending) in select.order_by {
+            let key = match resolve(term, &columns, &items)? {
+                Ok(index) => SortKey::Column(index),
+                Err(expr) => {
+                    hidden.push(expr);
+                    SortKey::Hidden(hidden.len() - 1)
+                }
+            };
+            order_by.push((key, descending));
+        }
+
+        let grouped = !group_by.is_empty() ||
+                      items.iter().chain(hidden.iter()).any(Expr::contains_aggregate);
+        let mut aggregates = Vec::new();
+        for expr in items.iter_mut().chain(hidden.iter_mut()) {
+            *expr = extract_aggregates(expr.clone(), &mut aggregates)?;
+        }
+
+        let mut group_fields = Vec::new();
+        for expr in items.iter().chain(hidden.iter()) {
+            collect_fields(expr, &mut group_fields);
+        }
+
+        Ok(Query {
+            columns,
+            items,
+            filter,
+            group_by,
+            hidden,
+            order_by,
+            aggregates,
+            grouped,
+            limit: select.limit,
+            group_fields,
+        })
+    }
+
+    /// The names of the result's columns: their aliases, or the expressions as SQL.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Start an execution that is fed lines one at a time.
+    pub fn executor(&self) -> QueryExecutor<'_> {
+        QueryExecutor {
+            query: self,
+            rows: Vec::new(),
+            groups: HashMap::new(),
+            group_states: Vec::new(),
+            lines_read: 0,
+            lines_skipped: 0,
+            done: false,
+        }
+    }
+
+    /// Run the query over `lines`, stopping early when a `LIMIT` without an `ORDER BY` has been
+    /// reached.
+    pub fn execute_lines<I>(&self, lines: I) -> QueryResult
+        where I: IntoIterator,
+              I::Item: AsRef<str>
+    {
+        let mut executor = self.executor();
+        for line in lines {
+            if !executor.push_line(line.as_ref()) {
+                break;
+            }
+        }
+        executor.finish()
+    }
+
+    /// Run the query over every line read from `reader`.
+    pub fn execute<R: BufRead>(&self, reader: R) -> io::Result<QueryResult> {
+        let mut executor = self.executor();
+        executor.push_reader(reader)?;
+        Ok(executor.finish())
+    }
+
+    /// Run the query over the lines of the files at `paths`, as if they were one file.
+    pub fn execute_files<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<QueryResult> {
+        let mut executor = self.executor();
+        for path in paths {
+            if !executor.push_reader(BufReader::new(File::open(path)?))? {
+                break;
+            }
+        }
+        Ok(executor.finish())
+    }
+}
+
+/// Resolve a `GROUP BY` or `ORDER BY` term to the index of a result column, or to an expression
+/// that is not one of them.
+fn resolve(term: Term,
+           columns: &[String],
+           items: &[Expr])
+           -> Result<Result<usize, Expr>, QueryError> {
+    match term {
+        Term::Position(position) if position <= items.len() => Ok(Ok(position - 1)),
+        Term::Position(position) => {
+            Err(QueryError::Invalid(format!("there is no column at position {}", position)))
+        }
+        Term::Expr(Expr::Name(name)) => {
+            columns.iter()
+                .position(|column| column.eq_ignore_ascii_case(&name))
+                .map(Ok)
+                .ok_or(QueryError::UnknownColumn(name))
+        }
+        Term::Expr(expr) => {
+            check_columns(&expr)?;
+            Ok(items.iter().position(|item| *item == expr).ok_or(expr))
+        }
+    }
+}
+
+fn check_columns(expr: &Expr) -> Result<(), QueryError> {
+    let mut unknown = None;
+    expr.visit(true,
+               &mut |expr| if let Expr::Name(ref name) = *expr {
+                   unknown.get_or_insert_with(|| name.clone());
+               });
+    unknown.map_or(Ok(()), |name| Err(QueryError::UnknownColumn(name)))
+}
+
+fn collect_fields(expr: &Expr, fields: &mut Vec<ELBRecordField>) {
+    expr.visit(true,
+               &mut |expr| if let Expr::Column(field) = *expr {
+                   if !fields.contains(&field) {
+                       fields.push(field);
+                   }
+               });
+}
+
+/// Replace the aggregates in `expr` by references to accumulators added to `aggregates`.
+fn extract_aggregates(expr: Expr,
+                      aggregates: &mut Vec<(Aggregate, Option<Expr>)>)
+                      -> Result<Expr, QueryError> {
+    let boxed = |expr: Box<Expr>, aggregates: &mut Vec<(Aggregate, Option<Expr>)>| {
+        extract_aggregates(*expr, aggregates).map(Box::new)
+    };
+
+    Ok(match expr {
+        Expr::Aggregate(aggregate, arg) => {
+            let arg = arg.map(|arg| *arg);
+            if arg.as_ref().is_some_and(Expr::contains_aggregate) {
+                return Err(QueryError::Invalid("aggregates cannot be nested".to_owned()));
+            }
+            let index = aggregates.iter()
+                .position(|&(a, ref b)| a == aggregate && *b == arg)
+                .unwrap_or_else(|| {
+                    aggregates.push((aggregate, arg));
+                    aggregates.len() - 1
+                });
+            Expr::AggregateRef(index)
+        }
+        Expr::Negate(expr) => Expr::Negate(boxed(expr, aggregates)?),
+        Expr::Not(expr) => Expr::Not(boxed(expr, aggregates)?),
+        Expr::Binary(op, left, right) => {
+            Expr::Binary(op, boxed(left, aggregates)?, boxed(right, aggregates)?)
+        }
+        Expr::IsNull(expr, negated) => Expr::IsNull(boxed(expr, aggregates)?, negated),
+        Expr::Like(expr, pattern, negated) => {
+            Expr::Like(boxed(expr, aggregates)?, pattern, negated)
+        }
+        Expr::In(expr, list, negated) => {
+            let list = list.into_iter()
+                .map(|item| extract_aggregates(item, aggregates))
+                .collect::<Result<Vec<Expr>, QueryError>>()?;
+            Expr::In(boxed(expr, aggregates)?, list, negated)
+        }
+        Expr::Between(expr, low, high, negated) => {
+            Expr::Between(boxed(expr, aggregates)?,
+                          boxed(low, aggregates)?,
+                          boxed(high, aggregates)?,
+                          negated)
+        }
+        expr => expr,
+    })
+}
+
+/// The rows produced by a query.
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// The number of non-empty lines that were read.
+    pub lines_read: u64,
+    /// The number of lines that were not records or had a field the query needed that could not
+    /// be parsed.
+    pub lines_skipped: u64,
+}
+
+/// An execution of a [`Query`](struct.Query.html) that is fed lines one at a time, e.g. from a
+/// source other than a file.
+pub struct QueryExecutor<'q> {
+    query: &'q Query,
+    /// Result columns paired with the values of hidden `ORDER BY` expressions.
+    rows: Vec<(Vec<Value>, Vec<Value>)>,
+    groups: HashMap<Vec<GroupKey>, usize>,
+    group_states: Vec<GroupState>,
+    lines_read: u64,
+    lines_skipped: u64,
+    done: bool,
+}
+
+struct GroupState {
+    /// The values of the group's first line, indexed by field.
+    fields: Vec<Value>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl GroupState {
+    fn new(query: &Query) -> GroupState {
+        GroupState {
+            fields: vec![Value::Null; ELBRecordField::ALL.len()],
+            accumulators: query.aggregates
+                .iter()
+                .map(|&(aggregate, _)| Accumulator::new(aggregate))
+                .collect(),
+        }
+    }
+}
+
+impl<'q> QueryExecutor<'q> {
+    /// Feed a line to the query.  Returns `false` once the query needs no more lines.
+    pub fn push_line(&mut self, line: &str) -> bool {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if self.done || line.is_empty() {
+            return !self.done;
+        }
+        self.lines_read += 1;
+
+        let fields = line.split_record();
+        if fields.len() != ::ELB_RECORD_V1_FIELD_COUNT &&
+           fields.len() != ::ELB_RECORD_V2_FIELD_COUNT {
+            self.lines_skipped += 1;
+            return true;
+        }
+        let mut row = LineRow {
+            values: vec![None; ELBRecordField::ALL.len()],
+            fields,
+        };
+        if self.process(&mut row).is_err() {
+            self.lines_skipped += 1;
+        }
+        !self.done
+    }
+
+    /// Feed every line read from `reader` to the query, replacing invalid UTF-8 with U+FFFD.
+    /// Returns `false` once the query needs no more lines.
+    pub fn push_reader<R: BufRead>(&mut self, mut reader: R) -> io::Result<bool> {
+        let mut line = String::new();
+        while read_line_lossy(&mut reader, &mut line)? > 0 {
+            if !self.push_line(&line) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn process(&mut self, row: &mut LineRow) -> Result<(), Unparsable> {
+        let query = self.query;
+        if let Some(ref filter) = query.filter {
+            if !eval(filter, row)?.is_truthy() {
+                return Ok(());
+            }
+        }
+
+        if !query.grouped {
+            let columns = eval_all(&query.items, row)?;
+            let hidden = eval_all(&query.hidden, row)?;
+            self.rows.push((columns, hidden));
+            match (query.limit, query.order_by.is_empty()) {
+                (Some(limit), true) => self.done = self.rows.len() as u64 >= limit,
+                // Only the first `limit` rows in order are kept, so drop the rest from time to
+                // time instead of holding on to every row.
+                (Some(limit), false) if self.rows.len() as u64 > 2 * limit + 1024 => {
+                    self.sort_rows();
+                    self.rows.truncate(limit as usize);
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let key = eval_all(&query.group_by, row)?.iter().map(Value::group_key).collect();
+        let args = query.aggregates
+            .iter()
+            .map(|(_, arg)| arg.as_ref().map_or(Ok(None), |arg| eval(arg, row).map(Some)))
+            .collect::<Result<Vec<Option<Value>>, Unparsable>>()?;
+        let index = match self.groups.get(&key) {
+            Some(&index) => index,
+            None => {
+                let mut state = GroupState::new(query);
+                for &field in &query.group_fields {
+                    state.fields[field as usize] = row.column(field)?;
+                }
+                self.group_states.push(state);
+                self.groups.insert(key, self.group_states.len() - 1);
+                self.group_states.len() - 1
+            }
+        };
+        for (accumulator, arg) in self.group_states[index].accumulators.iter_mut().zip(args) {
+            accumulator.add(arg);
+        }
+        Ok(())
+    }
+
+    fn sort_rows(&mut self) {
+        let order_by = &self.query.order_by;
+        self.rows.sort_by(|left, right| {
+            for &(key, descending) in order_by {
+                let (left, right) = match key {
+                    SortKey::Column(index) => (&left.0[index], &right.0[index]),
+                    SortKey::Hidden(index) => (&left.1[index], &right.1[index]),
+                };
+                let ordering = left.sort_cmp(right);
+                if ordering != ::std::cmp::Ordering::Equal {
+                    return if descending { ordering.reverse() } else { ordering };
+                }
+            }
+            ::std::cmp::Ordering::Equal
+        });
+    }
+
+    /// Produce the result from the lines fed so far.
+    pub fn finish(mut self) -> QueryResult {
+        let query = self.query;
+        if query.grouped {
+            if self.group_states.is_empty() && query.group_by.is_empty() {
+                // Aggregates over no rows still produce a single row, e.g. a count of 0.
+                self.group_states.push(GroupState::new(query));
+            }
+            for state in &self.group_states {
+                let mut row = GroupRow {
+                    fields: &state.fields,
+                    aggregates: state.accumulators.iter().map(Accumulator::value).collect(),
+                };
+                let columns = eval_all(&query.items, &mut row).unwrap_or_default();
+                let hidden = eval_all(&query.hidden, &mut row).unwrap_or_default();
+                self.rows.push((columns, hidden));
+            }
+        }
+        if !query.order_by.is_empty() {
+            self.sort_rows();
+        }
+        if let Some(limit) = query.limit {
+            self.rows.truncate(limit as usize);
+        }
+        QueryResult {
+            columns: query.columns.clone(),
+            rows: self.rows.into_iter().map(|(columns, _)| columns).collect(),
+            lines_read: self.lines_read,
+            lines_skipped: self.lines_skipped,
+        }
+    }
+}
+
+/// Returned when a field needed to evaluate an expression could not be parsed.
+#[derive(Debug)]
+struct Unparsable;
+
+trait Row {
+    fn column(&mut self, field: ELBRecordField) -> Result<Value, Unparsable>;
+
+    fn aggregate(&self, index: usize) -> Value;
+}
+
+/// A line split into its fields, which are parsed when they are first needed.
+struct LineRow<'l> {
+    fields: Vec<&'l str>,
+    values: Vec<Option<Value>>,
+}
+
+impl<'l> Row for LineRow<'l> {
+    fn column(&mut self, field: ELBRecordField) -> Result<Value, Unparsable> {
+        if let Some(ref value) = self.values[field as usize] {
+            return Ok(value.clone());
+        }
+        let value = parse_column(&self.fields, field).ok_or(Unparsable)?;
+        self.values[field as usize] = Some(value.clone());
+        Ok(value)
+    }
+
+    fn aggregate(&self, _: usize) -> Value {
+        Value::Null
+    }
+}
+
+/// A group of lines, represented by the fields of its first line and its aggregates.
+struct GroupRow<'g> {
+    fields: &'g [Value],
+    aggregates: Vec<Value>,
+}
+
+impl<'g> Row for GroupRow<'g> {
+    fn column(&mut self, field: ELBRecordField) -> Result<Value, Unparsable> {
+        Ok(self.fields[field as usize].clone())
+    }
+
+    fn aggregate(&self, index: usize) -> Value {
+        self.aggregates[index].clone()
+    }
+}
+
+/// Parse a single field of a split record, with the same rules as `parse_record`.
+fn parse_column(fields: &Vec<&str>, field: ELBRecordField) -> Option<Value> {
+    fn text_or_null(text: &str) -> Value {
+        if text == ::UNDEFINED_CHAR {
+            Value::Null
+        } else {
+            Value::Text(text.to_owned())
+        }
+    }
+
+    fn or_null<T, F: FnOnce(T) -> Value>(parsed: Option<Option<T>>, f: F) -> Option<Value> {
+        parsed.map(|parsed| parsed.map_or(Value::Null, f))
+    }
+
+    let errors = &mut Vec::new();
+    let v1 = fields.len() == ::ELB_RECORD_V1_FIELD_COUNT;
+    match field {
+        ELBRecordField::Timestamp => {
+            fields.parse_field::<DateTime<UTC>>(field, errors).map(Value::Timestamp)
+        }
+        ELBRecordField::ELBName => Some(Value::Text(fields[field].to_owned())),
+        ELBRecordField::ClientAddress => {
+            fields.parse_field::<SocketAddrV4>(field, errors)
+                .map(|address| Value::Text(address.to_string()))
+        }
+        ELBRecordField::BackendAddress => {
+            or_null(fields.parse_optional_field::<SocketAddrV4>(field, errors),
+                    |address| Value::Text(address.to_string()))
+        }
+        ELBRecordField::RequestProcessingTime |
+        ELBRecordField::BackendProcessingTime |
+        ELBRecordField::ResponseProcessingTime => {
+            fields.parse_field::<f32>(field, errors)
+                .and_then(|_| fields[field].parse().ok())
+                .map(Value::Float)
+        }
+        ELBRecordField::ELBStatusCode => {
+            fields.parse_field::<u16>(field, errors).map(|code| Value::Integer(code as i64))
+        }
+        ELBRecordField::BackendStatusCode => {
+            or_null(fields.parse_optional_field::<u16>(field, errors),
+                    |code| Value::Integer(code as i64))
+        }
+        ELBRecordField::ReceivedBytes |
+        ELBRecordField::SentBytes => {
+            fields.parse_field::<u64>(field, errors).map(|bytes| Value::Integer(bytes as i64))
+        }
+        ELBRecordField::RequestMethod |
+        ELBRecordField::RequestURL => Some(text_or_null(fields[field])),
+        ELBRecordField::RequestHTTPVersion => {
+            Some(match fields[field] {
+                ::UNDEFINED_CHAR => Value::Null,
+                version => Value::Text(HttpVersion::from(version).as_str().to_owned()),
+            })
+        }
+        ELBRecordField::UserAgent |
+        ELBRecordField::SSLCipher |
+        ELBRecordField::SSLProtocol if v1 => Some(Value::Null),
+        ELBRecordField::UserAgent |
+        ELBRecordField::SSLCipher => Some(text_or_null(fields[field])),
+        ELBRecordField::SSLProtocol => {
+            Some(match fields[field] {
+                ::UNDEFINED_CHAR => Value::Null,
+                protocol => Value::Text(TlsProtocol::from(protocol).as_str().to_owned()),
+            })
+        }
+    }
+}
+
+fn eval_all<R: Row>(exprs: &[Expr], row: &mut R) -> Result<Vec<Value>, Unparsable> {
+    exprs.iter().map(|expr| eval(expr, row)).collect()
+}
+
+fn boolean(value: bool) -> Value {
+    Value::Integer(value as i64)
+}
+
+fn eval<R: Row>(expr: &Expr, row: &mut R) -> Result<Value, Unparsable> {
+    Ok(match *expr {
+        Expr::Literal(ref value) => value.clone(),
+        Expr::Column(field) => row.column(field)?,
+        Expr::AggregateRef(index) => row.aggregate(index),
+        // Removed by planning.
+        Expr::Name(_) |
+        Expr::Aggregate(..) => Value::Null,
+        Expr::Negate(ref expr) => {
+            match eval(expr, row)? {
+                Value::Integer(integer) => {
+                    integer.checked_neg().map_or(Value::Null, Value::Integer)
+                }
+                Value::Float(float) => Value::Float(-float),
+                _ => Value::Null,
+            }
+        }
+        Expr::Not(ref expr) => {
+            match eval(expr, row)? {
+                Value::Null => Value::Null,
+                value => boolean(!value.is_truthy()),
+            }
+        }
+        Expr::Binary(BinaryOp::And, ref left, ref right) => {
+            let left = eval(left, row)?;
+            if !left.is_null() && !left.is_truthy() {
+                return Ok(boolean(false));
+            }
+            match eval(right, row)? {
+                Value::Null => Value::Null,
+                ref right if !right.is_truthy() => boolean(false),
+                _ if left.is_null() => Value::Null,
+                _ => boolean(true),
+            }
+        }
+        Expr::Binary(BinaryOp::Or, ref left, ref right) => {
+            let left = eval(left, row)?;
+            if left.is_truthy() {
+                return Ok(boolean(true));
+            }
+            match eval(right, row)? {
+                ref right if right.is_truthy() => boolean(true),
+                Value::Null => Value::Null,
+                _ if left.is_null() => Value::Null,
+                _ => boolean(false),
+            }
+        }
+        Expr::Binary(op, ref left, ref right) => {
+            let left = eval(left, row)?;
+            let right = eval(right, row)?;
+            binary(op, &left, &right)
+        }
+        Expr::IsNull(ref expr, negated) => boolean(eval(expr, row)?.is_null() != negated),
+        Expr::Like(ref expr, ref pattern, negated) => {
+            match eval(expr, row)? {
+                Value::Null => Value::Null,
+                value => boolean(like(&value.to_string(), pattern) != negated),
+            }
+        }
+        Expr::In(ref expr, ref list, negated) => {
+            let value = eval(expr, row)?;
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+            let mut found = Some(false);
+            for item in list {
+                match value.compare(&eval(item, row)?) {
+                    Some(::std::cmp::Ordering::Equal) => {
+                        found = Some(true);
+                        break;
+                    }
+                    None => found = None,
+                    Some(_) => {}
+                }
+            }
+            found.map_or(Value::Null, |found| boolean(found != negated))
+        }
+        Expr::Between(ref expr, ref low, ref high, negated) => {
+            let value = eval(expr, row)?;
+            let low = eval(low, row)?;
+            let high = eval(high, row)?;
+            match (binary(BinaryOp::Ge, &value, &low), binary(BinaryOp::Le, &value, &high)) {
+                (Value::Null, _) | (_, Value::Null) => Value::Null,
+                (low, high) => boolean((low.is_truthy() && high.is_truthy()) != negated),
+            }
+        }
+    })
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    let comparison = |matches: &dyn Fn(::std::cmp::Ordering) -> bool| {
+        left.compare(right).map_or(Value::Null, |ordering| boolean(matches(ordering)))
+    };
+    match op {
+        BinaryOp::Eq => comparison(&|ordering| ordering == Equal),
+        BinaryOp::Ne => comparison(&|ordering| ordering != Equal),
+        BinaryOp::Lt => comparison(&|ordering| ordering == Less),
+        BinaryOp::Le => comparison(&|ordering| ordering != Greater),
+        BinaryOp::Gt => comparison(&|ordering| ordering == Greater),
+        BinaryOp::Ge => comparison(&|ordering| ordering != Less),
+        BinaryOp::And | BinaryOp::Or => Value::Null,
+        _ => arithmetic(op, left, right),
+    }
+}
+
+/// Integer arithmetic when both values are integers, falling back to floating point when it
+/// overflows.  Division by zero is `NULL`.
+fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    if let (&Value::Integer(left), &Value::Integer(right)) = (left, right) {
+        let result = match op {
+            BinaryOp::Add => left.checked_add(right),
+            BinaryOp::Sub => left.checked_sub(right),
+            BinaryOp::Mul => left.checked_mul(right),
+            _ if right == 0 => return Value::Null,
+            _ => left.checked_div(right),
+        };
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => {
+            match op {
+                BinaryOp::Add => Value::Float(left + right),
+                BinaryOp::Sub => Value::Float(left - right),
+                BinaryOp::Mul => Value::Float(left * right),
+                _ if right == 0.0 => Value::Null,
+                _ => Value::Float(left / right),
+            }
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Match `text` against a `LIKE` pattern, where `%` matches any run of characters and `_` any
+/// single character.  ASCII letters match regardless of case.
+fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    // The positions to return to when a match after the last `%` fails.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() &&
+           (pattern[p] == '_' || pattern[p].eq_ignore_ascii_case(&text[t])) {
+            t += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((percent, matched)) = backtrack {
+            p = percent + 1;
+            t = matched + 1;
+            backtrack = Some((percent, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+enum Accumulator {
+    Count(u64),
+    Sum(Option<Value>),
+    Avg(f64, u64),
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+impl Accumulator {
+    fn new(aggregate: Aggregate) -> Accumulator {
+        match aggregate {
+            Aggregate::Count => Accumulator::Count(0),
+            Aggregate::Sum => Accumulator::Sum(None),
+            Aggregate::Avg => Accumulator::Avg(0.0, 0),
+            Aggregate::Min => Accumulator::Min(None),
+            Aggregate::Max => Accumulator::Max(None),
+        }
+    }
+
+    /// Add an argument, where `None` stands for the row of a `count(*)`.  Nulls are ignored.
+    fn add(&mut self, arg: Option<Value>) {
+        let value = match arg {
+            None => {
+                if let Accumulator::Count(ref mut count) = *self {
+                    *count += 1;
+                }
+                return;
+            }
+            Some(Value::Null) => return,
+            Some(value) => value,
+        };
+        match *self {
+            Accumulator::Count(ref mut count) => *count += 1,
+            Accumulator::Sum(ref mut sum) => {
+                if value.as_f64().is_some() {
+                    *sum = Some(match sum.take() {
+                        Some(sum) => arithmetic(BinaryOp::Add, &sum, &value),
+                        None => value,
+                    });
+                }
+            }
+            Accumulator::Avg(ref mut sum, ref mut count) => {
+                if let Some(value) = value.as_f64() {
+                    *sum += value;
+                    *count += 1;
+                }
+            }
+            Accumulator::Min(ref mut min) => {
+                if min.as_ref().is_none_or(|min| {
+                    value.compare(min) == Some(::std::cmp::Ordering::Less)
+                }) {
+                    *min = Some(value);
+                }
+            }
+            Accumulator::Max(ref mut max) => {
+                if max.as_ref().is_none_or(|max| {
+                    value.compare(max) == Some(::std::cmp::Ordering::Greater)
+                }) {
+                    *max = Some(value);
+                }
+            }
+        }
+    }
+
+    fn value(&self) -> Value {
+        match *self {
+            Accumulator::Count(count) => Value::Integer(count as i64),
+            Accumulator::Sum(ref value) |
+            Accumulator::Min(ref value) |
+            Accumulator::Max(ref value) => value.clone().unwrap_or(Value::Null),
+            Accumulator::Avg(_, 0) => Value::Null,
+            Accumulator::Avg(sum, count) => Value::Float(sum / count as f64),
+        }
+    }
+}
+
+/// Returned when a query cannot be parsed or planned.
+#[derive(Debug, PartialEq, Clone)]
+pub enum QueryError {
+    /// The query is not valid SQL, or uses SQL that is not supported.  `position` is the byte
+    /// offset into the query at which the problem was found.
+    Syntax { position: usize, message: String },
+    /// The query reads from a table other than `logs`.
+    UnknownTable(String),
+    /// The query refers to a column that is neither a field nor the name of a result column.
+    UnknownColumn(String),
+    /// The query is well formed but cannot be run, e.g. because it uses an aggregate in WHERE.
+    Invalid(String),
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            QueryError::Syntax { position, ref message } => {
+                write!(f, "Syntax error at position {}: {}.", position, message)
+            }
+            QueryError::UnknownTable(ref table) => {
+                write!(f, "Unknown table {}; queries read from {}.", table, TABLE_NAME)
+            }
+            QueryError::UnknownColumn(ref column) => write!(f, "Unknown column {}.", column),
+            QueryError::Invalid(ref message) => write!(f, "Invalid query: {}.", message),
+        }
+    }
+}
+
+impl Error for QueryError {
+    fn description(&self) -> &str {
+        match *self {
+            QueryError::Syntax { .. } => "syntax error",
+            QueryError::UnknownTable(_) => "unknown table",
+            QueryError::UnknownColumn(_) => "unknown column",
+            QueryError::Invalid(_) => "invalid query",
+        }
+    }
+}
+
+#[cfg(test)]
+mod query_tests {
+    use super::{Query, QueryError, Value};
+
+    const TEST_LOG: &str = "\
+2015-08-15T23:43:05.302180Z elb-a 172.16.1.6:54814 172.16.1.5:9000 0.000039 0.145507 0.00003 \
+200 200 0 7582 \"GET http://some.domain.com:80/path0 HTTP/1.1\"
+2015-08-15T23:43:06.000000Z elb-a 172.16.1.7:54815 - -1 -1 -1 503 - 0 0 \
+\"GET http://some.domain.com:80/path1 HTTP/1.1\"
+2015-08-15T23:43:07.000000Z elb-b 172.16.1.6:54816 172.16.1.5:9000 0.000039 0.2 0.00003 \
+404 404 10 100 \"POST https://some.domain.com:443/login HTTP/1.1\" \"curl/7.38.0\" \
+ECDHE-RSA-AES128-GCM-SHA256 TLSv1.2
+
+not a record
+2015-08-15T23:43:08.000000Z elb-b 172.16.1.8:54817 172.16.1.5:9000 0.000039 0.1 0.00003 \
+200 200 0 50 \"GET http://some.domain.com:80/path2 HTTP/1.1\" \"-\" - -
+";
+
+    fn run(sql: &str) -> Vec<Vec<Value>> {
+        Query::parse(sql).unwrap().execute(TEST_LOG.as_bytes()).unwrap().rows
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Text(text.to_owned())
+    }
+
+    #[test]
+    fn selects_columns_of_matching_lines() {
+        assert_eq!(run("SELECT client_address, request_url FROM logs WHERE sent_bytes > 100"),
+                   vec![vec![text("172.16.1.6:54814"),
+                             text("http://some.domain.com:80/path0")]])
+    }
+
+    #[test]
+    fn counts_lines_by_status_code() {
+        assert_eq!(run("SELECT elb_status_code, count(*) FROM logs GROUP BY 1 ORDER BY 2 DESC, \
+                        1"),
+                   vec![vec![Value::Integer(200), Value::Integer(2)],
+                        vec![Value::Integer(404), Value::Integer(1)],
+                        vec![Value::Integer(503), Value::Integer(1)]])
+    }
+
+    #[test]
+    fn filters_on_timestamps() {
+        assert_eq!(run("SELECT count(*) FROM logs WHERE timestamp >= '2015-08-15T23:43:06' \
+                        AND timestamp < '2015-08-15T23:43:08Z'"),
+                   vec![vec![Value::Integer(2)]])
+    }
+
+    #[test]
+    fn treats_undefined_and_missing_fields_as_null() {
+        assert_eq!(run("SELECT count(*), count(backend_address), count(user_agent), \
+                        count(ssl_protocol) FROM logs"),
+                   vec![vec![Value::Integer(4),
+                             Value::Integer(3),
+                             Value::Integer(1),
+                             Value::Integer(1)]])
+    }
+
+    #[test]
+    fn computes_aggregates_per_group() {
+        assert_eq!(run("SELECT elb_name AS elb, sum(sent_bytes), min(backend_processing_time), \
+                        max(elb_status_code), avg(received_bytes) FROM logs GROUP BY elb \
+                        ORDER BY elb"),
+                   vec![vec![text("elb-a"),
+                             Value::Integer(7582),
+                             Value::Float(-1.0),
+                             Value::Integer(503),
+                             Value::Float(0.0)],
+                        vec![text("elb-b"),
+                             Value::Integer(150),
+                             Value::Float(0.1),
+                             Value::Integer(404),
+                             Value::Float(5.0)]])
+    }
+
+    #[test]
+    fn orders_by_expressions_that_are_not_selected() {
+        assert_eq!(run("SELECT request_url FROM logs ORDER BY sent_bytes + received_bytes \
+                        DESC LIMIT 2"),
+                   vec![vec![text("http://some.domain.com:80/path0")],
+                        vec![text("https://some.domain.com:443/login")]])
+    }
+
+    #[test]
+    fn supports_like_in_and_between() {
+        assert_eq!(run("SELECT count(*) FROM logs WHERE request_url LIKE '%/PATH_' AND \
+                        request_method IN ('GET', 'HEAD') AND elb_status_code NOT BETWEEN \
+                        500 AND 599"),
+                   vec![vec![Value::Integer(2)]])
+    }
+
+    #[test]
+    fn returns_a_single_row_for_aggregates_over_no_lines() {
+        assert_eq!(run("SELECT count(*), sum(sent_bytes) FROM logs WHERE elb_name = 'none'"),
+                   vec![vec![Value::Integer(0), Value::Null]])
+    }
+
+    #[test]
+    fn stops_reading_once_the_limit_is_reached() {
+        let result = Query::parse("SELECT elb_name FROM logs LIMIT 1")
+            .unwrap()
+            .execute(TEST_LOG.as_bytes())
+            .unwrap();
+
+        assert_eq!(result.rows, vec![vec![text("elb-a")]]);
+        assert_eq!(result.lines_read, 1)
+    }
+
+    #[test]
+    fn only_skips_lines_whose_needed_fields_cannot_be_parsed() {
+        let log = "2015-08-15T23:43:05.302180Z elb-a 172.16.1.6:54814 172.16.1.5:9000 \
+                   0.000039 0.145507 0.00003 200 200 0 not-a-number \
+                   \"GET http://some.domain.com:80/path0 HTTP/1.1\"";
+        let names = Query::parse("SELECT elb_name FROM logs").unwrap();
+        let bytes = Query::parse("SELECT sent_bytes FROM logs").unwrap();
+
+        assert_eq!(names.execute_lines(vec![log]).rows, vec![vec![text("elb-a")]]);
+        assert_eq!(bytes.execute_lines(vec![log]).lines_skipped, 1)
+    }
+
+    #[test]
+    fn counts_lines_that_are_not_records_as_skipped() {
+        let result = Query::parse("SELECT * FROM logs").unwrap().execute(TEST_LOG.as_bytes());
+
+        assert_eq!(result.map(|result| (result.rows.len(), result.lines_read, result.lines_skipped))
+                       .unwrap(),
+                   (4, 5, 1))
+    }
+
+    #[test]
+    fn counts_a_line_that_is_not_utf8_as_skipped() {
+        let mut log = b"not \xff a record\n".to_vec();
+        log.extend_from_slice(TEST_LOG.as_bytes());
+
+        let result = Query::parse("SELECT * FROM logs").unwrap().execute(&log[..]);
+
+        assert_eq!(result.map(|result| (result.rows.len(), result.lines_read, result.lines_skipped))
+                       .unwrap(),
+                   (4, 6, 2))
+    }
+
+    #[test]
+    fn names_columns_by_alias_or_expression() {
+        let query = Query::parse("SELECT count(*) AS requests, sum(sent_bytes) FROM logs")
+            .unwrap();
+
+        assert_eq!(query.columns(), ["requests", "sum(sent_bytes)"])
+    }
+
+    #[test]
+    fn rejects_unknown_columns_and_tables() {
+        assert_eq!(Query::parse("SELECT status FROM logs").unwrap_err(),
+                   QueryError::UnknownColumn("status".to_owned()));
+        assert_eq!(Query::parse("SELECT * FROM records").unwrap_err(),
+                   QueryError::UnknownTable("records".to_owned()))
+    }
+
+    #[test]
+    fn rejects_aggregates_in_where() {
+        assert!(Query::parse("SELECT * FROM logs WHERE count(*) > 1").is_err())
+    }
+}
@@ -0,0 +1,795 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use ELBRecordField;
+use super::QueryError;
+use super::value::Value;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Identifier(String),
+    QuotedIdentifier(String),
+    Number(String),
+    Text(String),
+    Comma,
+    LeftParen,
+    RightParen,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Token::Identifier(ref identifier) => write!(f, "{}", identifier),
+            Token::QuotedIdentifier(ref identifier) => write!(f, "\"{}\"", identifier),
+            Token::Number(ref number) => write!(f, "{}", number),
+            Token::Text(ref text) => write!(f, "'{}'", text),
+            Token::Comma => write!(f, ","),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Star => write!(f, "*"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Slash => write!(f, "/"),
+            Token::Eq => write!(f, "="),
+            Token::Ne => write!(f, "!="),
+            Token::Lt => write!(f, "<"),
+            Token::Le => write!(f, "<="),
+            Token::Gt => write!(f, ">"),
+            Token::Ge => write!(f, ">="),
+            Token::End => write!(f, "end of query"),
+        }
+    }
+}
+
+fn tokenize(sql: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars: Vec<(usize, char)> = sql.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (position, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            tokens.push((position, Token::Identifier(collect(&chars[start..i]))));
+            continue;
+        } else if c.is_ascii_digit() || (c == '.' && next_is_digit(&chars, i)) {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            tokens.push((position, Token::Number(collect(&chars[start..i]))));
+            continue;
+        } else if c == '\'' || c == '"' {
+            let (text, next) = quoted(&chars, i, c)?;
+            i = next;
+            let token = if c == '\'' {
+                Token::Text(text)
+            } else {
+                Token::QuotedIdentifier(text)
+            };
+            tokens.push((position, token));
+            continue;
+        } else {
+            let next = chars.get(i + 1).map(|&(_, next)| next);
+            match (c, next) {
+                ('!', Some('=')) | ('<', Some('>')) => {
+                    i += 1;
+                    Token::Ne
+                }
+                ('<', Some('=')) => {
+                    i += 1;
+                    Token::Le
+                }
+                ('>', Some('=')) => {
+                    i += 1;
+                    Token::Ge
+                }
+                ('=', _) => Token::Eq,
+                ('<', _) => Token::Lt,
+                ('>', _) => Token::Gt,
+                (',', _) => Token::Comma,
+                ('(', _) => Token::LeftParen,
+                (')', _) => Token::RightParen,
+                ('*', _) => Token::Star,
+                ('+', _) => Token::Plus,
+                ('-', _) => Token::Minus,
+                ('/', _) => Token::Slash,
+                (';', _) if chars[i + 1..].iter().all(|&(_, c)| c.is_whitespace()) => {
+                    break;
+                }
+                _ => return Err(syntax_error(position, format!("unexpected character {:?}", c))),
+            }
+        };
+        tokens.push((position, token));
+        i += 1;
+    }
+    tokens.push((sql.len(), Token::End));
+    Ok(tokens)
+}
+
+fn collect(chars: &[(usize, char)]) -> String {
+    chars.iter().map(|&(_, c)| c).collect()
+}
+
+fn next_is_digit(chars: &[(usize, char)], i: usize) -> bool {
+    chars.get(i + 1).is_some_and(|&(_, c)| c.is_ascii_digit())
+}
+
+/// Read a quoted string starting at `chars[start]`, where a doubled quote stands for itself.
+fn quoted(chars: &[(usize, char)],
+          start: usize,
+          quote: char)
+          -> Result<(String, usize), QueryError> {
+    let mut text = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => return Err(syntax_error(chars[start].0, "unterminated string".to_owned())),
+            Some(&(_, c)) if c == quote => {
+                if chars.get(i + 1).is_some_and(|&(_, next)| next == quote) {
+                    text.push(quote);
+                    i += 2;
+                } else {
+                    return Ok((text, i + 1));
+                }
+            }
+            Some(&(_, c)) => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn syntax_error(position: usize, message: String) -> QueryError {
+    QueryError::Syntax {
+        position,
+        message,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum BinaryOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn from_name(name: &str) -> Option<Aggregate> {
+        match &*name.to_ascii_lowercase() {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "avg" => Some(Aggregate::Avg),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Avg => "avg",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Expr {
+    Literal(Value),
+    Column(ELBRecordField),
+    /// A name that is not a field; only valid in `ORDER BY` and `GROUP BY` as a column alias.
+    Name(String),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    IsNull(Box<Expr>, bool),
+    Like(Box<Expr>, String, bool),
+    In(Box<Expr>, Vec<Expr>, bool),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+    /// `None` as the argument stands for `count(*)`.
+    Aggregate(Aggregate, Option<Box<Expr>>),
+    /// An aggregate replaced by the index of its accumulator during planning.
+    AggregateRef(usize),
+}
+
+impl Expr {
+    /// Visit this expression and all of its sub-expressions, stopping at aggregates when
+    /// `into_aggregates` is false.
+    pub(crate) fn visit<F: FnMut(&Expr)>(&self, into_aggregates: bool, f: &mut F) {
+        f(self);
+        match *self {
+            Expr::Negate(ref expr) |
+            Expr::Not(ref expr) |
+            Expr::IsNull(ref expr, _) |
+            Expr::Like(ref expr, _, _) => expr.visit(into_aggregates, f),
+            Expr::Binary(_, ref left, ref right) => {
+                left.visit(into_aggregates, f);
+                right.visit(into_aggregates, f);
+            }
+            Expr::In(ref expr, ref list, _) => {
+                expr.visit(into_aggregates, f);
+                for item in list {
+                    item.visit(into_aggregates, f);
+                }
+            }
+            Expr::Between(ref expr, ref low, ref high, _) => {
+                expr.visit(into_aggregates, f);
+                low.visit(into_aggregates, f);
+                high.visit(into_aggregates, f);
+            }
+            Expr::Aggregate(_, Some(ref arg)) if into_aggregates => arg.visit(into_aggregates, f),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn contains_aggregate(&self) -> bool {
+        let mut found = false;
+        self.visit(false,
+                   &mut |expr| if let Expr::Aggregate(..) = *expr {
+                       found = true
+                   });
+        found
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        fn not(negated: bool) -> &'static str {
+            if negated { "NOT " } else { "" }
+        }
+
+        match *self {
+            Expr::Literal(Value::Text(ref text)) => write!(f, "'{}'", text.replace('\'', "''")),
+            Expr::Literal(ref value) => write!(f, "{}", value),
+            Expr::Column(field) => write!(f, "{}", field.snake_case_name()),
+            Expr::Name(ref name) => write!(f, "{}", name),
+            Expr::Negate(ref expr) => write!(f, "-{}", expr),
+            Expr::Not(ref expr) => write!(f, "NOT {}", expr),
+            Expr::Binary(op, ref left, ref right) => {
+                write!(f, "{} {} {}", left, op.as_str(), right)
+            }
+            Expr::IsNull(ref expr, negated) => write!(f, "{} IS {}NULL", expr, not(negated)),
+            Expr::Like(ref expr, ref pattern, negated) => {
+                write!(f, "{} {}LIKE '{}'", expr, not(negated), pattern.replace('\'', "''"))
+            }
+            Expr::In(ref expr, ref list, negated) => {
+                let list: Vec<String> = list.iter().map(|item| item.to_string()).collect();
+                write!(f, "{} {}IN ({})", expr, not(negated), list.join(", "))
+            }
+            Expr::Between(ref expr, ref low, ref high, negated) => {
+                write!(f, "{} {}BETWEEN {} AND {}", expr, not(negated), low, high)
+            }
+            Expr::Aggregate(aggregate, None) => write!(f, "{}(*)", aggregate.as_str()),
+            Expr::Aggregate(aggregate, Some(ref arg)) => {
+                write!(f, "{}({})", aggregate.as_str(), arg)
+            }
+            Expr::AggregateRef(index) => write!(f, "#{}", index),
+        }
+    }
+}
+
+/// A `GROUP BY` or `ORDER BY` term, which may refer to a column of the result by its position.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Term {
+    Position(usize),
+    Expr(Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct SelectItem {
+    pub expr: Expr,
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Select {
+    /// Empty for `SELECT *`.
+    pub items: Vec<SelectItem>,
+    pub table: String,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Term>,
+    /// Terms paired with `true` for descending order.
+    pub order_by: Vec<(Term, bool)>,
+    pub limit: Option<u64>,
+}
+
+const RESERVED: &[&str] = &["select", "from", "where", "group", "order", "by", "limit", "and",
+                            "or", "not", "as", "asc", "desc", "is", "null", "like", "in",
+                            "between"];
+
+/// Parse the supported subset of `SELECT` statements.
+pub(crate) fn parse(sql: &str) -> Result<Select, QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(sql)?,
+        next: 0,
+    };
+    let select = parser.select()?;
+    match *parser.peek() {
+        Token::End => Ok(select),
+        _ => Err(parser.unexpected()),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].1.clone();
+        if token != Token::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    /// Undo the `advance` that returned `token`.
+    fn back(&mut self, token: &Token) {
+        if *token != Token::End {
+            self.next -= 1;
+        }
+    }
+
+    fn unexpected(&self) -> QueryError {
+        let (position, ref token) = self.tokens[self.next];
+        syntax_error(position, format!("unexpected {}", token))
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match *self.peek() {
+            Token::Identifier(ref identifier) => identifier.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            let (position, ref token) = self.tokens[self.next];
+            Err(syntax_error(position,
+                             format!("expected {} but found {}", keyword.to_uppercase(), token)))
+        }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), QueryError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            let (position, ref found) = self.tokens[self.next];
+            Err(syntax_error(position, format!("expected {} but found {}", token, found)))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, QueryError> {
+        match self.advance() {
+            Token::Identifier(ref identifier) if !is_reserved(identifier) => {
+                Ok(identifier.clone())
+            }
+            Token::QuotedIdentifier(identifier) => Ok(identifier),
+            token => {
+                self.back(&token);
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    fn select(&mut self) -> Result<Select, QueryError> {
+        self.expect_keyword("select")?;
+        let mut items = Vec::new();
+        if !self.eat(&Token::Star) {
+            loop {
+                let expr = self.expr()?;
+                let alias = if self.eat_keyword("as") {
+                    Some(self.name()?)
+                } else {
+                    None
+                };
+                items.push(SelectItem {
+                    expr,
+                    alias,
+                });
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect_keyword("from")?;
+        let table = self.name()?;
+        let filter = if self.eat_keyword("where") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut group_by = Vec::new();
+        if self.eat_keyword("group") {
+            self.expect_keyword("by")?;
+            loop {
+                group_by.push(self.term()?);
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let mut order_by = Vec::new();
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let term = self.term()?;
+                let descending = if self.eat_keyword("desc") {
+                    true
+                } else {
+                    self.eat_keyword("asc");
+                    false
+                };
+                order_by.push((term, descending));
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let limit = if self.eat_keyword("limit") {
+            match self.advance() {
+                Token::Number(ref number) if number.parse::<u64>().is_ok() => {
+                    Some(number.parse().unwrap())
+                }
+                token => {
+                    self.back(&token);
+                    return Err(self.unexpected());
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Select {
+            items,
+            table,
+            filter,
+            group_by,
+            order_by,
+            limit,
+        })
+    }
+
+    fn term(&mut self) -> Result<Term, QueryError> {
+        let position = self.tokens[self.next].0;
+        match self.expr()? {
+            Expr::Literal(Value::Integer(position_in_result)) if position_in_result > 0 => {
+                Ok(Term::Position(position_in_result as usize))
+            }
+            Expr::Literal(_) => {
+                Err(syntax_error(position, "expected a column position".to_owned()))
+            }
+            expr => Ok(Term::Expr(expr)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.and()?;
+        while self.eat_keyword("or") {
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.not()?;
+        while self.eat_keyword("and") {
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, QueryError> {
+        if self.eat_keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, QueryError> {
+        let left = self.additive()?;
+        let op = match *self.peek() {
+            Token::Eq => Some(BinaryOp::Eq),
+            Token::Ne => Some(BinaryOp::Ne),
+            Token::Lt => Some(BinaryOp::Lt),
+            Token::Le => Some(BinaryOp::Le),
+            Token::Gt => Some(BinaryOp::Gt),
+            Token::Ge => Some(BinaryOp::Ge),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.advance();
+            return Ok(Expr::Binary(op, Box::new(left), Box::new(self.additive()?)));
+        }
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expr::IsNull(Box::new(left), negated));
+        }
+        let negated = self.eat_keyword("not");
+        if self.eat_keyword("like") {
+            match self.advance() {
+                Token::Text(pattern) => Ok(Expr::Like(Box::new(left), pattern, negated)),
+                token => {
+                    self.back(&token);
+                    Err(self.unexpected())
+                }
+            }
+        } else if self.eat_keyword("in") {
+            self.expect(&Token::LeftParen)?;
+            let mut list = Vec::new();
+            loop {
+                list.push(self.additive()?);
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(&Token::RightParen)?;
+            Ok(Expr::In(Box::new(left), list, negated))
+        } else if self.eat_keyword("between") {
+            let low = self.additive()?;
+            self.expect_keyword("and")?;
+            let high = self.additive()?;
+            Ok(Expr::Between(Box::new(left), Box::new(low), Box::new(high), negated))
+        } else if negated {
+            Err(self.unexpected())
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Plus => BinaryOp::Add,
+                Token::Minus => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.advance();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Star => BinaryOp::Mul,
+                Token::Slash => BinaryOp::Div,
+                _ => return Ok(left),
+            };
+            self.advance();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self.eat(&Token::Minus) {
+            match self.unary()? {
+                Expr::Literal(Value::Integer(integer)) => {
+                    Ok(Expr::Literal(Value::Integer(-integer)))
+                }
+                Expr::Literal(Value::Float(float)) => Ok(Expr::Literal(Value::Float(-float))),
+                expr => Ok(Expr::Negate(Box::new(expr))),
+            }
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, QueryError> {
+        let position = self.tokens[self.next].0;
+        match self.advance() {
+            Token::Number(number) => {
+                number.parse::<i64>()
+                    .map(Value::Integer)
+                    .or_else(|_| number.parse::<f64>().map(Value::Float))
+                    .map(Expr::Literal)
+                    .map_err(|_| syntax_error(position, format!("invalid number {}", number)))
+            }
+            Token::Text(text) => Ok(Expr::Literal(Value::Text(text))),
+            Token::LeftParen => {
+                let expr = self.expr()?;
+                self.expect(&Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::Identifier(ref identifier) if identifier.eq_ignore_ascii_case("null") => {
+                Ok(Expr::Literal(Value::Null))
+            }
+            Token::Identifier(ref identifier) if *self.peek() == Token::LeftParen => {
+                self.function(position, identifier)
+            }
+            Token::Identifier(ref identifier) if !is_reserved(identifier) => Ok(column(identifier)),
+            Token::QuotedIdentifier(ref identifier) => Ok(column(identifier)),
+            token => {
+                self.back(&token);
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    fn function(&mut self, position: usize, name: &str) -> Result<Expr, QueryError> {
+        let aggregate = Aggregate::from_name(name)
+            .ok_or_else(|| syntax_error(position, format!("unknown function {}", name)))?;
+        self.expect(&Token::LeftParen)?;
+        let arg = if aggregate == Aggregate::Count && self.eat(&Token::Star) {
+            None
+        } else {
+            Some(Box::new(self.expr()?))
+        };
+        self.expect(&Token::RightParen)?;
+        Ok(Expr::Aggregate(aggregate, arg))
+    }
+}
+
+fn is_reserved(identifier: &str) -> bool {
+    RESERVED.iter().any(|keyword| identifier.eq_ignore_ascii_case(keyword))
+}
+
+fn column(name: &str) -> Expr {
+    ELBRecordField::ALL.iter()
+        .find(|field| field.snake_case_name().eq_ignore_ascii_case(name))
+        .map_or_else(|| Expr::Name(name.to_owned()), |field| Expr::Column(*field))
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use ELBRecordField;
+    use super::{Aggregate, BinaryOp, Expr, Term, parse};
+    use super::super::QueryError;
+    use super::super::value::Value;
+
+    #[test]
+    fn parses_a_query_with_every_clause() {
+        let select = parse("select elb_status_code as code, count(*) from logs \
+                            where timestamp >= '2015-08-15' group by 1 order by 2 desc limit 10")
+            .unwrap();
+
+        assert_eq!(select.items[0].expr, Expr::Column(ELBRecordField::ELBStatusCode));
+        assert_eq!(select.items[0].alias, Some("code".to_owned()));
+        assert_eq!(select.items[1].expr, Expr::Aggregate(Aggregate::Count, None));
+        assert_eq!(select.table, "logs");
+        assert_eq!(select.filter,
+                   Some(Expr::Binary(BinaryOp::Ge,
+                                     Box::new(Expr::Column(ELBRecordField::Timestamp)),
+                                     Box::new(Expr::Literal(Value::Text("2015-08-15"
+                                         .to_owned()))))));
+        assert_eq!(select.group_by, vec![Term::Position(1)]);
+        assert_eq!(select.order_by, vec![(Term::Position(2), true)]);
+        assert_eq!(select.limit, Some(10))
+    }
+
+    #[test]
+    fn binds_and_tighter_than_or() {
+        let select = parse("SELECT * FROM logs WHERE sent_bytes > 1 OR sent_bytes < 0 AND \
+                            received_bytes = 0")
+            .unwrap();
+
+        match select.filter {
+            Some(Expr::Binary(BinaryOp::Or, _, ref right)) => {
+                assert_eq!(right.to_string(), "sent_bytes < 0 AND received_bytes = 0")
+            }
+            ref filter => panic!("unexpected filter {:?}", filter),
+        }
+    }
+
+    #[test]
+    fn renders_expressions_as_sql() {
+        let select = parse("SELECT sum(sent_bytes + received_bytes) / count(*), \
+                            request_url NOT LIKE '%it''s%' FROM logs")
+            .unwrap();
+
+        assert_eq!(select.items[0].expr.to_string(),
+                   "sum(sent_bytes + received_bytes) / count(*)");
+        assert_eq!(select.items[1].expr.to_string(), "request_url NOT LIKE '%it''s%'")
+    }
+
+    #[test]
+    fn accepts_a_trailing_semicolon() {
+        assert!(parse("SELECT * FROM logs;").is_ok())
+    }
+
+    #[test]
+    fn reports_the_position_of_a_syntax_error() {
+        assert_eq!(parse("SELECT * FROM logs WHERE"),
+                   Err(QueryError::Syntax {
+                       position: 24,
+                       message: "unexpected end of query".to_owned(),
+                   }))
+    }
+
+    #[test]
+    fn rejects_unknown_functions() {
+        match parse("SELECT median(sent_bytes) FROM logs") {
+            Err(QueryError::Syntax { ref message, .. }) => {
+                assert_eq!(message, "unknown function median")
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
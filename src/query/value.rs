@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use chrono::{DateTime, UTC};
+
+use FieldValue;
+
+/// A value produced by a query.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Timestamp(DateTime<UTC>),
+}
+
+impl Value {
+    /// `true` only for values that are neither null, zero nor the empty string.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Integer(integer) => integer != 0,
+            Value::Float(float) => float != 0.0,
+            Value::Text(ref text) => !text.is_empty(),
+            Value::Timestamp(_) => true,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Integer(integer) => Some(integer as f64),
+            Value::Float(float) => Some(float),
+            _ => None,
+        }
+    }
+
+    /// Compare two values the way a `WHERE` clause does.  Returns `None` when either value is
+    /// null or the values cannot be compared.
+    ///
+    /// Numbers compare numerically regardless of their type and a timestamp compares with text
+    /// by parsing the text as an RFC 3339 timestamp, a timestamp without an offset (which is
+    /// taken to be UTC) or a date.
+    pub(crate) fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Integer(left), Value::Integer(right)) => Some(left.cmp(right)),
+            (Value::Text(left), Value::Text(right)) => Some(left.cmp(right)),
+            (Value::Timestamp(left), Value::Timestamp(right)) => Some(left.cmp(right)),
+            (Value::Timestamp(left), Value::Text(right)) => {
+                parse_timestamp(right).map(|right| left.cmp(&right))
+            }
+            (Value::Text(left), Value::Timestamp(right)) => {
+                parse_timestamp(left).map(|left| left.cmp(right))
+            }
+            (left, right) => {
+                match (left.as_f64(), right.as_f64()) {
+                    (Some(left), Some(right)) => left.partial_cmp(&right),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// A total order used to sort results: nulls first, then numbers, text and timestamps.
+    pub(crate) fn sort_cmp(&self, other: &Value) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match *value {
+                Value::Null => 0,
+                Value::Integer(_) | Value::Float(_) => 1,
+                Value::Text(_) => 2,
+                Value::Timestamp(_) => 3,
+            }
+        }
+
+        match rank(self).cmp(&rank(other)) {
+            Ordering::Equal => self.compare(other).unwrap_or(Ordering::Equal),
+            ordering => ordering,
+        }
+    }
+
+    pub(crate) fn group_key(&self) -> GroupKey {
+        match *self {
+            Value::Null => GroupKey::Null,
+            Value::Integer(integer) => GroupKey::Integer(integer),
+            Value::Float(float) => GroupKey::Float(float.to_bits()),
+            Value::Text(ref text) => GroupKey::Text(text.clone()),
+            Value::Timestamp(ref ts) => GroupKey::Timestamp(::epoch_micros(ts)),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(integer) => write!(f, "{}", integer),
+            Value::Float(float) => write!(f, "{}", float),
+            Value::Text(ref text) => write!(f, "{}", text),
+            Value::Timestamp(ref ts) => write!(f, "{}", FieldValue::Timestamp(*ts)),
+        }
+    }
+}
+
+/// A hashable form of a `Value` used to group rows.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub(crate) enum GroupKey {
+    Null,
+    Integer(i64),
+    Float(u64),
+    Text(String),
+    Timestamp(i64),
+}
+
+fn parse_timestamp(text: &str) -> Option<DateTime<UTC>> {
+    text.parse()
+        .or_else(|_| format!("{}Z", text).parse())
+        .or_else(|_| format!("{}:00Z", text).parse())
+        .or_else(|_| format!("{}T00:00:00Z", text).parse())
+        .ok()
+}
+
+#[cfg(test)]
+mod value_tests {
+    use std::cmp::Ordering;
+
+    use super::Value;
+
+    #[test]
+    fn compares_integers_and_floats_numerically() {
+        assert_eq!(Value::Integer(2).compare(&Value::Float(1.5)), Some(Ordering::Greater))
+    }
+
+    #[test]
+    fn compares_timestamps_with_partial_timestamps_in_text() {
+        let ts = Value::Timestamp("2015-08-15T14:05:00Z".parse().unwrap());
+
+        assert_eq!(ts.compare(&Value::Text("2015-08-15T14:02".to_owned())),
+                   Some(Ordering::Greater));
+        assert_eq!(ts.compare(&Value::Text("2015-08-16".to_owned())), Some(Ordering::Less))
+    }
+
+    #[test]
+    fn does_not_compare_nulls() {
+        assert_eq!(Value::Null.compare(&Value::Null), None)
+    }
+
+    #[test]
+    fn sorts_nulls_before_numbers_and_text() {
+        let mut values = vec![Value::Text("a".to_owned()), Value::Integer(1), Value::Null];
+
+        values.sort_by(|left, right| left.sort_cmp(right));
+
+        assert_eq!(values, vec![Value::Null, Value::Integer(1), Value::Text("a".to_owned())])
+    }
+}
@@ -0,0 +1,325 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use {ParsingResult, parse_record};
+
+/// Reads and parses records line by line while keeping track of how far it has read, so
+/// processing can later be resumed from the same place.
+///
+/// Empty lines are skipped but counted, so line numbers match the ones an editor shows.  Bytes
+/// that are not valid UTF-8 are replaced with U+FFFD, so such a line is returned as a record or
+/// a parsing error like any other, and offsets still count the bytes in the file.
+pub struct RecordReader<R> {
+    reader: R,
+    path: Option<PathBuf>,
+    offset: u64,
+    line_number: u64,
+    line: String,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    /// Read from the start of `reader`.
+    pub fn new(reader: R) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            path: None,
+            offset: 0,
+            line_number: 0,
+            line: String::new(),
+        }
+    }
+
+    /// Read the next record, or return `None` at the end of the input.
+    pub fn next_record(&mut self) -> io::Result<Option<ParsingResult<'_>>> {
+        loop {
+            let read = read_line_lossy(&mut self.reader, &mut self.line)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.offset += read as u64;
+            self.line_number += 1;
+            if !self.line.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+        Ok(Some(parse_record(self.line.trim_end_matches(['\r', '\n']))))
+    }
+
+    /// The number of bytes read so far, which is where the line after the last record returned
+    /// starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The number of lines read so far, which is the line number of the last record returned.
+    /// Lines are numbered from 1.
+    pub fn line_number(&self) -> u64 {
+        self.line_number
+    }
+}
+
+impl RecordReader<BufReader<File>> {
+    /// Read the file at `path` from the start.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<RecordReader<BufReader<File>>> {
+        let path = path.as_ref();
+        let mut reader = RecordReader::new(BufReader::new(File::open(path)?));
+        reader.path = Some(path.to_path_buf());
+        Ok(reader)
+    }
+
+    /// Reopen the file a position was taken from and continue reading right after the last
+    /// record returned before it was taken.
+    ///
+    /// Fails with `InvalidData` if the file has become shorter than the position or the position
+    /// is neither at the start of a line nor at the end of the file, both of which suggest the
+    /// file was replaced.  The end of the file is accepted because a last line without a line
+    /// ending ends there.
+    pub fn resume(position: &ReaderPosition) -> io::Result<RecordReader<BufReader<File>>> {
+        let mut file = File::open(&position.path)?;
+        let length = file.metadata()?.len();
+        if length < position.offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "the file is shorter than the position"));
+        }
+        if position.offset > 0 && position.offset < length {
+            let mut previous = [0];
+            file.seek(SeekFrom::Start(position.offset - 1))?;
+            file.read_exact(&mut previous)?;
+            if previous[0] != b'\n' {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "the position is not at the start of a line"));
+            }
+        } else {
+            file.seek(SeekFrom::Start(position.offset))?;
+        }
+        Ok(RecordReader {
+            reader: BufReader::new(file),
+            path: Some(position.path.clone()),
+            offset: position.offset,
+            line_number: position.line_number,
+            line: String::new(),
+        })
+    }
+
+    /// The position to resume from to continue after the last record returned.
+    pub fn position(&self) -> ReaderPosition {
+        ReaderPosition {
+            path: self.path.clone().unwrap_or_default(),
+            offset: self.offset,
+            line_number: self.line_number,
+        }
+    }
+}
+
+/// Replace the contents of `line` with the next line of `reader`, including its line ending,
+/// replacing invalid UTF-8 with U+FFFD.  Returns the number of bytes read, which is 0 at the end
+/// of the input.
+///
+/// Unlike `BufRead::read_line`, a line that is not valid UTF-8 does not fail after its bytes
+/// have been consumed, so callers can keep track of where they are in the input.
+pub(crate) fn read_line_lossy<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let mut bytes = mem::take(line).into_bytes();
+    bytes.clear();
+    let read = reader.read_until(b'\n', &mut bytes)?;
+    *line = String::from_utf8(bytes)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+    Ok(read)
+}
+
+/// Where a [`RecordReader`](struct.RecordReader.html) reading a file had got to.
+///
+/// A position is serialized by its `Display` implementation as the offset, the line number and
+/// the path separated by spaces, e.g. `5120 42 /var/log/elb/file.log`, and deserialized with
+/// `FromStr`.  The path comes last so it may contain spaces.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReaderPosition {
+    pub path: PathBuf,
+    /// The number of bytes before the next line to read.
+    pub offset: u64,
+    /// The number of lines before the next line to read.
+    pub line_number: u64,
+}
+
+impl Display for ReaderPosition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.offset, self.line_number, self.path.display())
+    }
+}
+
+impl FromStr for ReaderPosition {
+    type Err = ReaderPositionParseError;
+
+    fn from_str(s: &str) -> Result<ReaderPosition, ReaderPositionParseError> {
+        let mut parts = s.trim_end_matches(['\r', '\n']).splitn(3, ' ');
+        match (parts.next().and_then(|offset| offset.parse().ok()),
+               parts.next().and_then(|line_number| line_number.parse().ok()),
+               parts.next()) {
+            (Some(offset), Some(line_number), Some(path)) if !path.is_empty() => {
+                Ok(ReaderPosition {
+                    path: PathBuf::from(path),
+                    offset,
+                    line_number,
+                })
+            }
+            _ => Err(ReaderPositionParseError),
+        }
+    }
+}
+
+/// Returned when a serialized [`ReaderPosition`](struct.ReaderPosition.html) cannot be parsed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReaderPositionParseError;
+
+impl Display for ReaderPositionParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Expected an offset, a line number and a path separated by spaces.")
+    }
+}
+
+impl Error for ReaderPositionParseError {
+    fn description(&self) -> &str {
+        "invalid reader position"
+    }
+}
+
+#[cfg(test)]
+mod record_reader_tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{ReaderPosition, ReaderPositionParseError, RecordReader};
+
+    const TEST_LOG: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                            172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                            \"GET http://some.domain.com:80/path0 HTTP/1.1\"\r\n\
+                            \n\
+                            not a record\n\
+                            2015-08-15T23:43:06.000000Z elb-name 172.16.1.7:54815 - -1 -1 -1 \
+                            503 - 0 0 \"GET http://some.domain.com:80/path1 HTTP/1.1\"";
+
+    fn test_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("elp-reader-{}-{}.log", name, process::id()));
+        fs::write(&path, TEST_LOG).unwrap();
+        path
+    }
+
+    #[test]
+    fn tracks_the_offset_and_line_number_of_each_record() {
+        let mut reader = RecordReader::new(TEST_LOG.as_bytes());
+        let mut positions = Vec::new();
+        while let Some(parsed) = reader.next_record().unwrap().map(|result| result.is_ok()) {
+            positions.push((parsed, reader.offset(), reader.line_number()));
+        }
+
+        let first_line = TEST_LOG.find('\n').unwrap() as u64 + 1;
+        assert_eq!(positions,
+                   vec![(true, first_line, 1),
+                        (false, first_line + 1 + 13, 3),
+                        (true, TEST_LOG.len() as u64, 4)])
+    }
+
+    #[test]
+    fn keeps_track_of_the_position_past_a_line_that_is_not_utf8() {
+        let mut log = b"not \xff utf-8\n".to_vec();
+        log.extend_from_slice(TEST_LOG.as_bytes());
+        let mut reader = RecordReader::new(&log[..]);
+
+        let first_is_error = reader.next_record().unwrap().unwrap().is_err();
+        let position = (reader.offset(), reader.line_number());
+        let second_is_ok = reader.next_record().unwrap().unwrap().is_ok();
+
+        assert!(first_is_error && second_is_ok);
+        assert_eq!(position, (12, 1))
+    }
+
+    #[test]
+    fn resumes_after_the_last_record_read() {
+        let path = test_file("resume");
+        let position = {
+            let mut reader = RecordReader::open(&path).unwrap();
+            reader.next_record().unwrap();
+            reader.next_record().unwrap();
+            reader.position()
+        };
+        let serialized = position.to_string();
+
+        let mut resumed = RecordReader::resume(&serialized.parse().unwrap()).unwrap();
+        let elb_status_code = resumed.next_record().unwrap().unwrap().unwrap().elb_status_code;
+        let line_number = resumed.line_number();
+        let end = resumed.next_record().unwrap().is_none();
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!((elb_status_code, line_number, end), (503, 4, true))
+    }
+
+    #[test]
+    fn resumes_after_a_last_record_without_a_line_ending() {
+        let path = test_file("end");
+        let position = {
+            let mut reader = RecordReader::open(&path).unwrap();
+            while reader.next_record().unwrap().is_some() {}
+            reader.position()
+        };
+
+        let mut resumed = RecordReader::resume(&position).unwrap();
+        let end = resumed.next_record().unwrap().is_none();
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(position.offset, TEST_LOG.len() as u64);
+        assert!(end)
+    }
+
+    #[test]
+    fn refuses_to_resume_in_the_middle_of_a_line() {
+        let path = test_file("middle");
+
+        let result = RecordReader::resume(&ReaderPosition {
+            path: path.clone(),
+            offset: 10,
+            line_number: 0,
+        });
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData))
+    }
+
+    #[test]
+    fn refuses_to_resume_past_the_end_of_a_file() {
+        let path = test_file("past-end");
+
+        let result = RecordReader::resume(&ReaderPosition {
+            path: path.clone(),
+            offset: TEST_LOG.len() as u64 + 1,
+            line_number: 5,
+        });
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData))
+    }
+
+    #[test]
+    fn parses_positions_with_spaces_in_the_path() {
+        assert_eq!("5120 42 /var/log/my logs/file.log".parse(),
+                   Ok(ReaderPosition {
+                       path: PathBuf::from("/var/log/my logs/file.log"),
+                       offset: 5120,
+                       line_number: 42,
+                   }))
+    }
+
+    #[test]
+    fn rejects_malformed_positions() {
+        assert_eq!("5120 /var/log/file.log".parse::<ReaderPosition>(),
+                   Err(ReaderPositionParseError))
+    }
+}
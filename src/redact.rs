@@ -0,0 +1,300 @@
+use std::borrow::Cow;
+use std::io;
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use {ELBRecord, LogLineWriter, UNDEFINED_CHAR, percent_decode};
+
+/// How a [`Redactor`](struct.Redactor.html) rewrites client addresses.  Ports are kept.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressRedaction {
+    /// Leave addresses as they are.
+    Keep,
+    /// Replace each address with a pseudonym that shares as many leading bits with the
+    /// pseudonyms of other addresses as the addresses themselves do, so addresses in the same
+    /// subnet stay in the same, different, subnet.
+    PrefixPreserving,
+    /// Replace each address with one derived from its keyed hash, which hides how addresses are
+    /// related.
+    KeyedHash,
+}
+
+/// How a [`Redactor`](struct.Redactor.html) rewrites a query parameter.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParamRedaction {
+    /// Remove the parameter from the URL.
+    Strip,
+    /// Replace the parameter's value with its keyed hash, so equal values can still be matched.
+    Hash,
+}
+
+/// Removes personal information from records so logs can be handed to a third party.
+///
+/// Pseudonyms are derived with SipHash-2-4, a keyed pseudorandom function, so they are the same
+/// every time the same key is used but cannot be reversed or recomputed without it.  Keep the
+/// key secret, and use a new one when pseudonyms must not be linkable to earlier ones.
+///
+/// Redacted records are written with a [`LogLineWriter`](struct.LogLineWriter.html):
+///
+/// ```rust,no_run
+/// # let key = [0; 16];
+/// # let record = elp::parse_record("").unwrap();
+/// let redactor = elp::Redactor::new(key)
+///     .with_param("token", elp::ParamRedaction::Strip)
+///     .without_user_agent();
+/// let mut writer = elp::LogLineWriter::new(std::io::stdout());
+/// redactor.write_record(&record, &mut writer)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    key: [u8; 16],
+    address: AddressRedaction,
+    params: Vec<(String, ParamRedaction)>,
+    drop_user_agent: bool,
+}
+
+impl Redactor {
+    /// Redact client addresses with prefix-preserving pseudonyms derived using `key`.  URLs and
+    /// user agents are kept.
+    pub fn new(key: [u8; 16]) -> Redactor {
+        Redactor {
+            key,
+            address: AddressRedaction::PrefixPreserving,
+            params: Vec::new(),
+            drop_user_agent: false,
+        }
+    }
+
+    /// Rewrite client addresses using `address`.
+    pub fn with_address_redaction(mut self, address: AddressRedaction) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Rewrite every query parameter called `name` using `redaction`.  Names are compared after
+    /// percent-decoding.
+    pub fn with_param<S: Into<String>>(mut self, name: S, redaction: ParamRedaction) -> Self {
+        self.params.push((name.into(), redaction));
+        self
+    }
+
+    /// Replace user agents with `-`.
+    pub fn without_user_agent(mut self) -> Self {
+        self.drop_user_agent = true;
+        self
+    }
+
+    /// The redacted form of a client address.
+    pub fn redact_address(&self, address: SocketAddrV4) -> SocketAddrV4 {
+        let ip = u32::from(*address.ip());
+        let redacted = match self.address {
+            AddressRedaction::Keep => ip,
+            AddressRedaction::PrefixPreserving => {
+                let mut redacted = 0;
+                for bit in 0..32 {
+                    let prefix = ip.checked_shr(32 - bit).unwrap_or(0);
+                    let mut input = [0; 5];
+                    input[0] = bit as u8;
+                    input[1..].copy_from_slice(&prefix.to_be_bytes());
+                    let flip = (siphash24(&self.key, &input) & 1) as u32;
+                    redacted |= ((ip >> (31 - bit) & 1) ^ flip) << (31 - bit);
+                }
+                redacted
+            }
+            AddressRedaction::KeyedHash => siphash24(&self.key, &ip.to_be_bytes()) as u32,
+        };
+        SocketAddrV4::new(Ipv4Addr::from(redacted), address.port())
+    }
+
+    /// The redacted form of a request URL.  The URL is returned without allocating when none of
+    /// the configured query parameters appear in it.
+    pub fn redact_url<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        let (rest, fragment) = match url.find('#') {
+            Some(idx) => url.split_at(idx),
+            None => (url, ""),
+        };
+        let (base, query) = match rest.find('?') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => return Cow::Borrowed(url),
+        };
+
+        let mut changed = false;
+        let mut pairs = Vec::new();
+        for pair in query.split('&') {
+            let name = pair.split('=').next().unwrap_or("");
+            let redaction = self.params
+                .iter()
+                .find(|&(param, _)| *percent_decode(name) == **param)
+                .map(|&(_, redaction)| redaction);
+            match redaction {
+                Some(ParamRedaction::Strip) => changed = true,
+                Some(ParamRedaction::Hash) => {
+                    let value = pair.get(name.len() + 1..).unwrap_or("");
+                    pairs.push(Cow::Owned(format!("{}={:016x}",
+                                                  name,
+                                                  siphash24(&self.key, value.as_bytes()))));
+                    changed = true;
+                }
+                None => pairs.push(Cow::Borrowed(pair)),
+            }
+        }
+        if !changed {
+            return Cow::Borrowed(url);
+        }
+        let query = pairs.join("&");
+        Cow::Owned(if query.is_empty() {
+            format!("{}{}", base, fragment)
+        } else {
+            format!("{}?{}{}", base, query, fragment)
+        })
+    }
+
+    /// Write the redacted form of `record` to `writer`.
+    pub fn write_record<W: Write>(&self,
+                                  record: &ELBRecord,
+                                  writer: &mut LogLineWriter<W>)
+                                  -> io::Result<()> {
+        let request_url = self.redact_url(record.request_url);
+        writer.write_record(&ELBRecord {
+            client_address: self.redact_address(record.client_address),
+            request_url: &request_url,
+            user_agent: if self.drop_user_agent {
+                UNDEFINED_CHAR
+            } else {
+                record.user_agent
+            },
+            ..*record
+        })
+    }
+}
+
+/// SipHash-2-4 of `data` with a 128 bit `key`.
+fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut k0 = [0; 8];
+    let mut k1 = [0; 8];
+    k0.copy_from_slice(&key[..8]);
+    k1.copy_from_slice(&key[8..]);
+    let (k0, k1) = (u64::from_le_bytes(k0), u64::from_le_bytes(k1));
+    let mut v = [k0 ^ 0x736f_6d65_7073_6575,
+                 k1 ^ 0x646f_7261_6e64_6f6d,
+                 k0 ^ 0x6c79_6765_6e65_7261,
+                 k1 ^ 0x7465_6462_7974_6573];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        let m = u64::from_le_bytes(word);
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    }
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+    v[3] ^= m;
+    round(&mut v);
+    round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod redactor_tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use {LogLineWriter, parse_record};
+    use super::{AddressRedaction, ParamRedaction, Redactor, siphash24};
+
+    const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    fn address(ip: [u8; 4]) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::from(ip), 54814)
+    }
+
+    #[test]
+    fn matches_the_siphash_reference_output() {
+        let data: Vec<u8> = (0..15).collect();
+
+        assert_eq!(siphash24(&KEY, &data), 0xa129_ca61_49be_45e5)
+    }
+
+    #[test]
+    fn preserves_shared_prefixes_of_addresses() {
+        let redactor = Redactor::new(KEY);
+        let a = u32::from(*redactor.redact_address(address([10, 1, 2, 3])).ip());
+        let b = u32::from(*redactor.redact_address(address([10, 1, 2, 200])).ip());
+        let c = u32::from(*redactor.redact_address(address([10, 129, 0, 0])).ip());
+
+        assert_eq!((a ^ b).leading_zeros(), (0x0a01_0203u32 ^ 0x0a01_02c8).leading_zeros());
+        assert_eq!((a ^ c).leading_zeros(), 8);
+        assert!(a != 0x0a01_0203)
+    }
+
+    #[test]
+    fn hashes_addresses_with_the_key() {
+        let redactor = Redactor::new(KEY).with_address_redaction(AddressRedaction::KeyedHash);
+        let other = Redactor::new([1; 16]).with_address_redaction(AddressRedaction::KeyedHash);
+        let client = address([10, 1, 2, 3]);
+
+        assert_eq!(redactor.redact_address(client), redactor.redact_address(client));
+        assert!(redactor.redact_address(client) != other.redact_address(client));
+        assert_eq!(redactor.redact_address(client).port(), 54814)
+    }
+
+    #[test]
+    fn strips_and_hashes_configured_query_parameters() {
+        let redactor = Redactor::new(KEY)
+            .with_param("token", ParamRedaction::Strip)
+            .with_param("user id", ParamRedaction::Hash);
+
+        assert_eq!(redactor.redact_url("http://a.com:80/p?token=s3cr3t&page=2&user%20id=42#top"),
+                   format!("http://a.com:80/p?page=2&user%20id={:016x}#top",
+                           siphash24(&KEY, b"42")));
+        assert_eq!(redactor.redact_url("http://a.com:80/p?token=s3cr3t"),
+                   "http://a.com:80/p");
+        assert_eq!(redactor.redact_url("http://a.com:80/p?page=2"), "http://a.com:80/p?page=2")
+    }
+
+    #[test]
+    fn writes_the_redacted_line() {
+        let record = parse_record("2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                                   172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                                   \"GET http://a.com:80/p?token=s3cr3t HTTP/1.1\" \
+                                   \"curl/7.43.0\" - -")
+            .unwrap();
+        let redactor = Redactor::new(KEY)
+            .with_address_redaction(AddressRedaction::Keep)
+            .with_param("token", ParamRedaction::Strip)
+            .without_user_agent();
+        let mut writer = LogLineWriter::new(Vec::new());
+
+        redactor.write_record(&record, &mut writer).unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+                   "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 \
+                    0.000039 0.145507 0.00003 200 200 0 7582 \"GET http://a.com:80/p HTTP/1.1\" \
+                    \"-\" - -\n")
+    }
+}
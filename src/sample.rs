@@ -0,0 +1,228 @@
+use dedup::stable_hash;
+use {ELBRecord, epoch_micros};
+
+/// What decides whether a [`Sampler`](struct.Sampler.html) keeps a record.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SampleKey {
+    /// The client's IP address, ignoring the port, so all of a client's requests are kept or
+    /// left out together.
+    ClientIp,
+    /// The timestamp, client address and URL of the request, so each request is kept or left
+    /// out on its own.
+    Request,
+}
+
+/// Keeps a fixed fraction of records, chosen by hashing a key so the same records are kept every
+/// time the same logs are sampled.
+///
+/// The hash does not vary between runs or platforms.  Samplers with different seeds keep
+/// different records.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sampler {
+    rate: f64,
+    key: SampleKey,
+    seed: u64,
+}
+
+impl Sampler {
+    /// Keep about `rate` of the records, e.g. 0.01 for 1%, by client IP address.
+    pub fn new(rate: f64) -> Sampler {
+        Sampler {
+            rate,
+            key: SampleKey::ClientIp,
+            seed: 0,
+        }
+    }
+
+    /// Choose records by `key`, the client IP address by default.
+    pub fn with_key(mut self, key: SampleKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Mix `seed` into the hash, 0 by default.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Return `true` if `record` is in the sample.
+    pub fn sample(&self, record: &ELBRecord) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        if self.rate <= 0.0 {
+            return false;
+        }
+        let hash = match self.key {
+            SampleKey::ClientIp => stable_hash(&record.client_address.ip().octets(), self.seed),
+            SampleKey::Request => {
+                let request = format!("{} {} {}",
+                                      epoch_micros(&record.timestamp),
+                                      record.client_address,
+                                      record.request_url);
+                stable_hash(request.as_bytes(), self.seed)
+            }
+        };
+        hash < (self.rate * 18_446_744_073_709_551_616.0) as u64
+    }
+}
+
+/// Keeps a uniform random sample of exactly `capacity` items, or all of them if fewer are
+/// offered, without knowing in advance how many there will be.
+///
+/// Records borrow the line they were parsed from, so they are usually converted to something
+/// owned, e.g. the line itself or the fields of interest, before being offered.  The random
+/// numbers come from a generator seeded with [`with_seed`](#method.with_seed), so the same seed
+/// and input give the same sample.
+#[derive(Debug, Clone)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    items: Vec<T>,
+    offered: u64,
+    state: u64,
+}
+
+impl<T> Reservoir<T> {
+    /// Keep up to `capacity` items.
+    pub fn new(capacity: usize) -> Reservoir<T> {
+        Reservoir {
+            capacity,
+            items: Vec::with_capacity(capacity),
+            offered: 0,
+            state: 0,
+        }
+    }
+
+    /// Seed the random number generator, 0 by default.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.state = seed;
+        self
+    }
+
+    /// Offer `item` for the sample.
+    pub fn offer(&mut self, item: T) {
+        self.offered += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return;
+        }
+        let slot = self.next_random() % self.offered;
+        if slot < self.capacity as u64 {
+            self.items[slot as usize] = item;
+        }
+    }
+
+    /// The number of items offered so far.
+    pub fn offered(&self) -> u64 {
+        self.offered
+    }
+
+    /// The items in the sample, in no particular order.
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Return the items in the sample.
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+
+    /// SplitMix64.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod sampler_tests {
+    use parse_record;
+    use super::{Reservoir, SampleKey, Sampler};
+
+    fn record(client: u32, port: u32, second: u32) -> String {
+        format!("2015-08-15T23:43:{:02}.000000Z elb-name 10.{}.{}.{}:{} 172.16.1.5:9000 \
+                 0.000039 0.145507 0.00003 200 200 0 7582 \
+                 \"GET http://some.domain.com:80/ HTTP/1.1\"",
+                second,
+                client >> 16,
+                (client >> 8) & 0xff,
+                client & 0xff,
+                port)
+    }
+
+    fn sampled(sampler: &Sampler, lines: &[String]) -> Vec<bool> {
+        lines.iter().map(|line| sampler.sample(&parse_record(line).unwrap())).collect()
+    }
+
+    #[test]
+    fn keeps_about_the_requested_fraction() {
+        let lines: Vec<String> = (0..10_000).map(|client| record(client, 1000, 0)).collect();
+
+        let kept = sampled(&Sampler::new(0.1), &lines).iter().filter(|&&kept| kept).count();
+
+        assert!(kept > 900 && kept < 1100, "kept {}", kept)
+    }
+
+    #[test]
+    fn keeps_a_clients_requests_together() {
+        let lines: Vec<String> = (0..100)
+            .flat_map(|client| vec![record(client, 1000, 1), record(client, 2000, 2)])
+            .collect();
+
+        let kept = sampled(&Sampler::new(0.5), &lines);
+
+        assert!(kept.chunks(2).all(|pair| pair[0] == pair[1]));
+        assert!(kept.contains(&true) && kept.contains(&false))
+    }
+
+    #[test]
+    fn samples_requests_independently_by_request_key() {
+        let lines: Vec<String> = (0..100).map(|second| record(1, 1000, second % 60)).collect();
+        let sampler = Sampler::new(0.5).with_key(SampleKey::Request);
+
+        let kept = sampled(&sampler, &lines);
+
+        assert!(kept.contains(&true) && kept.contains(&false));
+        assert_eq!(kept, sampled(&sampler, &lines))
+    }
+
+    #[test]
+    fn keeps_different_records_with_different_seeds() {
+        let lines: Vec<String> = (0..100).map(|client| record(client, 1000, 0)).collect();
+
+        assert!(sampled(&Sampler::new(0.5), &lines) !=
+                sampled(&Sampler::new(0.5).with_seed(1), &lines))
+    }
+
+    #[test]
+    fn keeps_everything_until_the_reservoir_is_full() {
+        let mut reservoir = Reservoir::new(5);
+        for i in 0..3 {
+            reservoir.offer(i);
+        }
+
+        assert_eq!(reservoir.into_items(), vec![0, 1, 2])
+    }
+
+    #[test]
+    fn samples_exactly_the_reservoir_capacity_uniformly() {
+        let mut counts = [0; 10];
+        for seed in 0..2000 {
+            let mut reservoir = Reservoir::new(3).with_seed(seed);
+            for i in 0..10 {
+                reservoir.offer(i);
+            }
+            assert_eq!(reservoir.items().len(), 3);
+            for &i in reservoir.items() {
+                counts[i] += 1;
+            }
+        }
+
+        // Each item is kept with probability 3/10, so about 600 times.
+        assert!(counts.iter().all(|&count| count > 500 && count < 700), "{:?}", counts)
+    }
+}
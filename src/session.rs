@@ -0,0 +1,268 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use chrono::{DateTime, UTC};
+
+use {ELBRecord, epoch_micros};
+
+/// How a [`Sessionizer`](struct.Sessionizer.html) tells clients apart.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SessionKey {
+    /// The client's IP address, ignoring the port.
+    ClientIp,
+    /// The client's IP address and user agent, which separates clients behind the same NAT or
+    /// proxy when they use different browsers.
+    ClientIpAndUserAgent,
+}
+
+/// A summary of one client's session, returned by a [`Sessionizer`](struct.Sessionizer.html).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Session {
+    pub client_ip: Ipv4Addr,
+    /// `None` unless sessions are keyed by user agent.
+    pub user_agent: Option<String>,
+    /// The timestamp of the session's first request.
+    pub start: DateTime<UTC>,
+    /// The timestamp of the session's last request.
+    pub end: DateTime<UTC>,
+    pub requests: u64,
+    /// The number of different URL paths requested, ignoring query strings.
+    pub distinct_paths: usize,
+    /// The number of requests whose [`StatusClass`](enum.StatusClass.html) is an error.
+    pub errors: u64,
+    /// The bytes received from and sent to the client.
+    pub bytes: u64,
+}
+
+/// Groups records by client into sessions, which end when the client has made no request for
+/// longer than an inactivity gap.
+///
+/// Records are expected to be ordered by timestamp, e.g. by a [`RecordMerger`]
+/// (struct.RecordMerger.html).  A session is returned as soon as a record more than the gap
+/// after its last request is pushed, so only sessions that may still continue are kept in
+/// memory, along with the paths each of them has requested.
+///
+/// ```rust,no_run
+/// # let records: Vec<elp::ELBRecord> = Vec::new();
+/// let mut sessionizer = elp::Sessionizer::new(std::time::Duration::from_secs(30 * 60));
+/// for record in &records {
+///     for session in sessionizer.push(record) {
+///         println!("{} made {} requests", session.client_ip, session.requests);
+///     }
+/// }
+/// for session in sessionizer.finish() {
+///     println!("{} made {} requests", session.client_ip, session.requests);
+/// }
+/// ```
+pub struct Sessionizer {
+    gap: i64,
+    key: SessionKey,
+    open: HashMap<ClientKey, OpenSession>,
+    /// One entry per open session, holding a time it was last seen at.  Entries are not updated
+    /// as requests arrive, so an entry found to have expired is checked against its session.
+    expiries: BinaryHeap<Reverse<(i64, ClientKey)>>,
+    latest: Option<i64>,
+}
+
+type ClientKey = (Ipv4Addr, Option<String>);
+
+struct OpenSession {
+    session: Session,
+    /// The timestamp of the last request, in microseconds since the epoch.
+    last_seen: i64,
+    paths: HashSet<String>,
+}
+
+impl Sessionizer {
+    /// End sessions after `gap` without a request from the client, keying them by client IP
+    /// address.
+    pub fn new(gap: Duration) -> Sessionizer {
+        Sessionizer {
+            gap: gap.as_secs() as i64 * 1_000_000 + gap.subsec_micros() as i64,
+            key: SessionKey::ClientIp,
+            open: HashMap::new(),
+            expiries: BinaryHeap::new(),
+            latest: None,
+        }
+    }
+
+    /// Tell clients apart by `key`, the client IP address by default.
+    pub fn with_key(mut self, key: SessionKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Add `record` to its client's session and return the sessions that ended before it, in
+    /// the order of their last requests.
+    pub fn push(&mut self, record: &ELBRecord) -> Vec<Session> {
+        let timestamp = epoch_micros(&record.timestamp);
+        let now = self.latest.map_or(timestamp, |latest| latest.max(timestamp));
+        self.latest = Some(now);
+        let ended = self.expire(now);
+
+        let key = (*record.client_address.ip(),
+                   match self.key {
+                       SessionKey::ClientIp => None,
+                       SessionKey::ClientIpAndUserAgent => Some(record.user_agent.to_owned()),
+                   });
+        let path = record.url().map_or(record.request_url, |url| url.path);
+        let bytes = record.received_bytes + record.sent_bytes;
+        let error = record.status_class().is_error() as u64;
+        if let Some(open) = self.open.get_mut(&key) {
+            open.last_seen = open.last_seen.max(timestamp);
+            let session = &mut open.session;
+            session.start = session.start.min(record.timestamp);
+            session.end = session.end.max(record.timestamp);
+            session.requests += 1;
+            session.errors += error;
+            session.bytes += bytes;
+            if !open.paths.contains(path) {
+                open.paths.insert(path.to_owned());
+            }
+            return ended;
+        }
+
+        self.expiries.push(Reverse((timestamp, key.clone())));
+        let mut paths = HashSet::new();
+        paths.insert(path.to_owned());
+        self.open.insert(key.clone(),
+                         OpenSession {
+                             session: Session {
+                                 client_ip: key.0,
+                                 user_agent: key.1,
+                                 start: record.timestamp,
+                                 end: record.timestamp,
+                                 requests: 1,
+                                 distinct_paths: 0,
+                                 errors: error,
+                                 bytes,
+                             },
+                             last_seen: timestamp,
+                             paths,
+                         });
+        ended
+    }
+
+    /// The number of sessions that have not ended yet.
+    pub fn open_sessions(&self) -> usize {
+        self.open.len()
+    }
+
+    /// End every open session and return them in the order of their last requests.
+    pub fn finish(mut self) -> Vec<Session> {
+        let mut ended: Vec<_> = self.open.drain().map(|(_, open)| close(open)).collect();
+        ended.sort_by_key(|session| session.end);
+        ended
+    }
+
+    fn expire(&mut self, now: i64) -> Vec<Session> {
+        let mut ended = Vec::new();
+        while let Some(&Reverse((last_seen, _))) = self.expiries.peek() {
+            if now - last_seen <= self.gap {
+                break;
+            }
+            let Reverse((_, key)) = self.expiries.pop().unwrap();
+            let last_seen = self.open[&key].last_seen;
+            if now - last_seen > self.gap {
+                ended.push(close(self.open.remove(&key).unwrap()));
+            } else {
+                self.expiries.push(Reverse((last_seen, key)));
+            }
+        }
+        ended.sort_by_key(|session| session.end);
+        ended
+    }
+}
+
+fn close(open: OpenSession) -> Session {
+    Session { distinct_paths: open.paths.len(), ..open.session }
+}
+
+#[cfg(test)]
+mod sessionizer_tests {
+    use std::time::Duration;
+
+    use parse_record;
+    use super::{Session, SessionKey, Sessionizer};
+
+    fn line(time: &str, client: &str, status: u16, url: &str, user_agent: &str) -> String {
+        format!("2015-08-15T{}Z elb-name {}:54814 172.16.1.5:9000 0.000039 0.145507 0.00003 \
+                 {} {} 10 90 \"GET http://a.com:80{} HTTP/1.1\" \"{}\" - -",
+                time,
+                client,
+                status,
+                status,
+                url,
+                user_agent)
+    }
+
+    fn push_all(sessionizer: &mut Sessionizer, lines: &[String]) -> Vec<Session> {
+        let mut ended = Vec::new();
+        for line in lines {
+            ended.extend(sessionizer.push(&parse_record(line).unwrap()));
+        }
+        ended
+    }
+
+    #[test]
+    fn ends_a_session_after_the_inactivity_gap() {
+        let mut sessionizer = Sessionizer::new(Duration::from_secs(60));
+        let ended = push_all(&mut sessionizer,
+                             &[line("10:00:00.000000", "10.0.0.1", 200, "/a?x=1", "curl"),
+                               line("10:00:30.000000", "10.0.0.1", 404, "/a?x=2", "curl"),
+                               line("10:01:30.000000", "10.0.0.1", 200, "/b", "curl"),
+                               line("10:02:31.000000", "10.0.0.2", 200, "/a", "curl")]);
+
+        assert_eq!(ended.len(), 1);
+        let session = &ended[0];
+        assert_eq!(session.client_ip.to_string(), "10.0.0.1");
+        assert_eq!(session.user_agent, None);
+        assert_eq!(session.start.to_rfc3339(), "2015-08-15T10:00:00+00:00");
+        assert_eq!(session.end.to_rfc3339(), "2015-08-15T10:01:30+00:00");
+        assert_eq!((session.requests, session.distinct_paths, session.errors, session.bytes),
+                   (3, 2, 1, 300));
+        assert_eq!(sessionizer.open_sessions(), 1)
+    }
+
+    #[test]
+    fn starts_a_new_session_when_a_client_returns() {
+        let mut sessionizer = Sessionizer::new(Duration::from_secs(60));
+        let mut ended = push_all(&mut sessionizer,
+                                 &[line("10:00:00.000000", "10.0.0.1", 200, "/", "curl"),
+                                   line("10:05:00.000000", "10.0.0.1", 200, "/", "curl")]);
+        ended.extend(sessionizer.finish());
+
+        assert_eq!(ended.len(), 2);
+        assert_eq!(ended[0].end.to_rfc3339(), "2015-08-15T10:00:00+00:00");
+        assert_eq!(ended[1].start.to_rfc3339(), "2015-08-15T10:05:00+00:00")
+    }
+
+    #[test]
+    fn separates_user_agents_when_keyed_by_them() {
+        let mut sessionizer = Sessionizer::new(Duration::from_secs(60))
+            .with_key(SessionKey::ClientIpAndUserAgent);
+        push_all(&mut sessionizer,
+                 &[line("10:00:00.000000", "10.0.0.1", 200, "/", "curl"),
+                   line("10:00:01.000000", "10.0.0.1", 200, "/", "Mozilla/5.0")]);
+        let mut user_agents: Vec<_> =
+            sessionizer.finish().into_iter().map(|session| session.user_agent.unwrap()).collect();
+        user_agents.sort();
+
+        assert_eq!(user_agents, ["Mozilla/5.0", "curl"])
+    }
+
+    #[test]
+    fn returns_finished_sessions_in_the_order_of_their_last_requests() {
+        let mut sessionizer = Sessionizer::new(Duration::from_secs(60));
+        let ended = push_all(&mut sessionizer,
+                             &[line("10:00:00.000000", "10.0.0.1", 200, "/", "curl"),
+                               line("10:00:10.000000", "10.0.0.2", 200, "/", "curl"),
+                               line("10:00:20.000000", "10.0.0.1", 200, "/", "curl"),
+                               line("10:05:00.000000", "10.0.0.3", 200, "/", "curl")]);
+        let clients: Vec<_> = ended.iter().map(|session| session.client_ip.to_string()).collect();
+
+        assert_eq!(clients, ["10.0.0.2", "10.0.0.1"])
+    }
+}
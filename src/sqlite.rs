@@ -0,0 +1,347 @@
+//! Loading of ELB access logs into SQLite databases for ad-hoc querying.
+//!
+//! Parsed records are stored in a `records` table with a column per field.  Addresses are split
+//! into `*_ip` and `*_port` columns so clients can be looked up by IP alone, and timestamps are
+//! stored as the text found in the record (e.g. `2015-08-15T23:43:05.302180Z`), which sorts
+//! chronologically and is understood by SQLite's date and time functions.  Lines that could not
+//! be parsed are stored, with their errors, in a `parsing_errors` table.  Both tables record the
+//! source and line number each row came from.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use rusqlite::{Connection, Transaction};
+
+use reader::read_line_lossy;
+use {ELBRecord, ELBRecordField, ParsingErrors, parse_record};
+
+const CREATE_TABLES: &str = "
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    elb_name TEXT NOT NULL,
+    client_ip TEXT NOT NULL,
+    client_port INTEGER NOT NULL,
+    backend_ip TEXT,
+    backend_port INTEGER,
+    request_processing_time REAL NOT NULL,
+    backend_processing_time REAL NOT NULL,
+    response_processing_time REAL NOT NULL,
+    elb_status_code INTEGER NOT NULL,
+    backend_status_code INTEGER,
+    received_bytes INTEGER NOT NULL,
+    sent_bytes INTEGER NOT NULL,
+    request_method TEXT,
+    request_url TEXT,
+    request_http_version TEXT,
+    user_agent TEXT,
+    ssl_cipher TEXT,
+    ssl_protocol TEXT
+);
+CREATE TABLE IF NOT EXISTS parsing_errors (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    raw TEXT NOT NULL,
+    errors TEXT NOT NULL
+);";
+
+const CREATE_INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS records_timestamp ON records (timestamp);
+CREATE INDEX IF NOT EXISTS records_elb_status_code ON records (elb_status_code);
+CREATE INDEX IF NOT EXISTS records_client_ip ON records (client_ip);";
+
+const INSERT_RECORD: &str = "
+INSERT INTO records (source, line, timestamp, elb_name, client_ip, client_port, backend_ip,
+    backend_port, request_processing_time, backend_processing_time, response_processing_time,
+    elb_status_code, backend_status_code, received_bytes, sent_bytes, request_method,
+    request_url, request_http_version, user_agent, ssl_cipher, ssl_protocol)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+    ?20, ?21)";
+
+const INSERT_PARSING_ERRORS: &str = "
+INSERT INTO parsing_errors (source, line, raw, errors) VALUES (?1, ?2, ?3, ?4)";
+
+/// The number of rows added by an import.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ImportSummary {
+    pub records: u64,
+    pub parsing_errors: u64,
+}
+
+/// Imports ELB access logs into a SQLite database.
+///
+/// Each import runs in a single transaction so a failed import leaves the database as it was.
+/// Indexes slow down bulk inserts, so they are only created when [`create_indexes`]
+/// (#method.create_indexes) is called, typically once all files have been imported.
+pub struct SqliteExporter {
+    connection: Connection,
+}
+
+impl SqliteExporter {
+    /// Open, or create, the database at `path` and create the tables if they do not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteExporter, SqliteExportError> {
+        SqliteExporter::from_connection(Connection::open(path)?)
+    }
+
+    /// Use an existing connection, e.g. to an in-memory database, and create the tables if they
+    /// do not exist.
+    pub fn from_connection(connection: Connection) -> Result<SqliteExporter, SqliteExportError> {
+        connection.execute_batch(CREATE_TABLES)?;
+        Ok(SqliteExporter { connection })
+    }
+
+    /// Import every line of the file at `path`, using the path as the rows' source.
+    pub fn import_file<P: AsRef<Path>>(&mut self,
+                                       path: P)
+                                       -> Result<ImportSummary, SqliteExportError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        self.import(&path.to_string_lossy(), BufReader::new(file))
+    }
+
+    /// Import every line read from `reader`.  Line numbers start at 1.
+    pub fn import<R: BufRead>(&mut self,
+                              source: &str,
+                              mut reader: R)
+                              -> Result<ImportSummary, SqliteExportError> {
+        let transaction = self.connection.transaction()?;
+        let mut summary = ImportSummary::default();
+        let mut line = String::new();
+        let mut line_number: i64 = 0;
+        while read_line_lossy(&mut reader, &mut line)? > 0 {
+            line_number += 1;
+            {
+                let trimmed = line.trim_end_matches(['\r', '\n']);
+                if !trimmed.is_empty() {
+                    match parse_record(trimmed) {
+                        Ok(record) => {
+                            insert_record(&transaction, source, line_number, &record)?;
+                            summary.records += 1;
+                        }
+                        Err(errors) => {
+                            insert_parsing_errors(&transaction, source, line_number, &errors)?;
+                            summary.parsing_errors += 1;
+                        }
+                    }
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(summary)
+    }
+
+    /// Create the indexes on `timestamp`, `elb_status_code` and `client_ip` if they do not
+    /// exist.
+    pub fn create_indexes(&self) -> Result<(), SqliteExportError> {
+        self.connection.execute_batch(CREATE_INDEXES)?;
+        Ok(())
+    }
+
+    /// The underlying connection, e.g. to run queries against the imported data.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    pub fn into_connection(self) -> Connection {
+        self.connection
+    }
+}
+
+fn insert_record(transaction: &Transaction,
+                 source: &str,
+                 line_number: i64,
+                 record: &ELBRecord)
+                 -> Result<(), SqliteExportError> {
+    fn defined(value: &str) -> Option<&str> {
+        if value == ::UNDEFINED_CHAR { None } else { Some(value) }
+    }
+
+    let mut statement = transaction.prepare_cached(INSERT_RECORD)?;
+    statement.execute(rusqlite::params![source,
+                                        line_number,
+                                        record.field(ELBRecordField::Timestamp).to_string(),
+                                        record.elb_name,
+                                        record.client_address.ip().to_string(),
+                                        record.client_address.port(),
+                                        record.backend_address.map(|a| a.ip().to_string()),
+                                        record.backend_address.map(|a| a.port()),
+                                        record.request_processing_time,
+                                        record.backend_processing_time,
+                                        record.response_processing_time,
+                                        record.elb_status_code,
+                                        record.backend_status_code,
+                                        record.received_bytes as i64,
+                                        record.sent_bytes as i64,
+                                        record.request_method.map(|m| m.as_str()),
+                                        defined(record.request_url),
+                                        record.request_http_version.map(|v| v.as_str()),
+                                        defined(record.user_agent),
+                                        record.ssl_cipher.map(|c| c.name()),
+                                        record.ssl_protocol.map(|p| p.as_str())])?;
+    Ok(())
+}
+
+fn insert_parsing_errors(transaction: &Transaction,
+                         source: &str,
+                         line_number: i64,
+                         errors: &ParsingErrors)
+                         -> Result<(), SqliteExportError> {
+    let messages: Vec<String> = errors.errors.iter().map(|e| e.to_string()).collect();
+    let mut statement = transaction.prepare_cached(INSERT_PARSING_ERRORS)?;
+    statement.execute(rusqlite::params![source, line_number, errors.record, messages.join("\n")])?;
+    Ok(())
+}
+
+/// Returned when logs cannot be imported into a SQLite database.
+#[derive(Debug)]
+pub enum SqliteExportError {
+    /// A log file could not be read.
+    Io(io::Error),
+    /// SQLite rejected a statement.
+    Sqlite(rusqlite::Error),
+}
+
+impl Display for SqliteExportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            SqliteExportError::Io(ref e) => write!(f, "I/O error: {}", e),
+            SqliteExportError::Sqlite(ref e) => write!(f, "SQLite error: {}", e),
+        }
+    }
+}
+
+impl Error for SqliteExportError {
+    fn description(&self) -> &str {
+        match *self {
+            SqliteExportError::Io(_) => "I/O error",
+            SqliteExportError::Sqlite(_) => "SQLite error",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SqliteExportError::Io(ref e) => Some(e),
+            SqliteExportError::Sqlite(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for SqliteExportError {
+    fn from(e: io::Error) -> SqliteExportError {
+        SqliteExportError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for SqliteExportError {
+    fn from(e: rusqlite::Error) -> SqliteExportError {
+        SqliteExportError::Sqlite(e)
+    }
+}
+
+#[cfg(test)]
+mod sqlite_exporter_tests {
+    use rusqlite::Connection;
+
+    use super::{ImportSummary, SqliteExporter};
+
+    const TEST_LOG: &str = "2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 \
+                            172.16.1.5:9000 0.000039 0.145507 0.00003 200 200 0 7582 \
+                            \"GET http://some.domain.com:80/path0 HTTP/1.1\"\n\
+                            \n\
+                            2015-08-15T23:43:06.000000Z elb-name 172.16.1.7:54815 - -1 -1 -1 \
+                            503 - 0 0 \"GET http://some.domain.com:80/path1 HTTP/1.1\"\r\n\
+                            not a record\n";
+
+    fn import_test_log() -> (SqliteExporter, ImportSummary) {
+        let mut exporter =
+            SqliteExporter::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let summary = exporter.import("test.log", TEST_LOG.as_bytes()).unwrap();
+        (exporter, summary)
+    }
+
+    #[test]
+    fn imports_records_and_parsing_errors() {
+        let (_, summary) = import_test_log();
+
+        assert_eq!(summary,
+                   ImportSummary {
+                       records: 2,
+                       parsing_errors: 1,
+                   })
+    }
+
+    #[test]
+    fn stores_each_field_of_a_record() {
+        let (exporter, _) = import_test_log();
+
+        let row: (String, String, i64, Option<i64>, Option<String>) = exporter.connection()
+            .query_row("SELECT timestamp, client_ip, client_port, backend_status_code, \
+                        backend_ip FROM records WHERE line = 3",
+                       [],
+                       |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap();
+
+        assert_eq!(row,
+                   ("2015-08-15T23:43:06.000000Z".to_owned(),
+                    "172.16.1.7".to_owned(),
+                    54815,
+                    None,
+                    None))
+    }
+
+    #[test]
+    fn stores_the_raw_line_and_errors_of_unparsable_lines() {
+        let (exporter, _) = import_test_log();
+
+        let row: (i64, String, String) = exporter.connection()
+            .query_row("SELECT line, raw, errors FROM parsing_errors",
+                       [],
+                       |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+
+        assert_eq!(row, (4, "not a record".to_owned(), "Record is malformed.".to_owned()))
+    }
+
+    #[test]
+    fn records_a_line_that_is_not_utf8_as_a_parsing_error_and_carries_on() {
+        let mut exporter =
+            SqliteExporter::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let mut log = b"not \xff a record\n".to_vec();
+        log.extend_from_slice(TEST_LOG.as_bytes());
+
+        let summary = exporter.import("test.log", &log[..]).unwrap();
+        let raw: String = exporter.connection()
+            .query_row("SELECT raw FROM parsing_errors WHERE line = 1", [], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(summary,
+                   ImportSummary {
+                       records: 2,
+                       parsing_errors: 2,
+                   });
+        assert_eq!(raw, "not \u{fffd} a record")
+    }
+
+    #[test]
+    fn creates_the_indexes() {
+        let (exporter, _) = import_test_log();
+
+        exporter.create_indexes().unwrap();
+
+        let indexes: i64 = exporter.connection()
+            .query_row("SELECT count(*) FROM sqlite_master WHERE type = 'index' AND \
+                        tbl_name = 'records'",
+                       [],
+                       |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexes, 3)
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, UTC};
+
+use {ELBRecord, StatusClass};
+
+/// A datagram size that fits in the MTU of most networks without fragmentation.
+const DEFAULT_MAX_PACKET_SIZE: usize = 1432;
+
+/// How tags are attached to metrics.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StatsdFormat {
+    /// Plain StatsD has no tags, so their values are added to the metric name, e.g.
+    /// `elb.my-elb.success.requests:1|c`.
+    Plain,
+    /// DogStatsD tags, e.g. `elb.requests:1|c|#elb_name:my-elb,status_class:success`.
+    DogStatsd,
+}
+
+/// Aggregates `ELBRecord`s into StatsD metrics and sends them over UDP.
+///
+/// The following metrics are sent, tagged with the ELB's name as `elb_name` and, except for the
+/// gauge, the [`StatusClass`](enum.StatusClass.html) of the request as `status_class`:
+///
+/// * `requests`, `received_bytes` and `sent_bytes` - counters of the requests and bytes seen
+///   since the previous flush.
+/// * `request_processing_time`, `backend_processing_time` and `response_processing_time` -
+///   processing times in milliseconds, summarized in memory rather than sent as one timer sample
+///   per request, so replaying a day of logs does not send three lines per record.  Each is sent
+///   as `<name>.count` and `<name>.sum` counters, from which the mean can be derived across
+///   flushes, and `<name>.min` and `<name>.max` gauges of the extremes since the previous flush.
+///   The `-1` the ELB records when it could not send a request to a backend is left out.
+/// * `lag_seconds` - a gauge of the time between the newest record's timestamp and the flush.
+///
+/// Metric names are prefixed with `elb.` by default.  Metrics are aggregated in memory until
+/// [`flush`](#method.flush) is called or, when a flush interval is set, until a record arrives
+/// after the interval has elapsed.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    format: StatsdFormat,
+    prefix: String,
+    max_packet_size: usize,
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+    counters: BTreeMap<(&'static str, Tags), u64>,
+    timers: BTreeMap<(&'static str, Tags), TimerSummary>,
+    newest_timestamps: BTreeMap<String, DateTime<UTC>>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct Tags {
+    elb_name: String,
+    status_class: StatusClass,
+}
+
+/// The samples of a timer since the last flush, in milliseconds.
+#[derive(Debug, Clone, Copy)]
+struct TimerSummary {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TimerSummary {
+    fn new(sample: f64) -> TimerSummary {
+        TimerSummary {
+            count: 1,
+            sum: sample,
+            min: sample,
+            max: sample,
+        }
+    }
+
+    fn add(&mut self, sample: f64) {
+        self.count += 1;
+        self.sum += sample;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+    }
+}
+
+impl StatsdSink {
+    /// A sink sending DogStatsD metrics to `address`, e.g. `127.0.0.1:8125`, from an ephemeral
+    /// local port.
+    pub fn new<A: ToSocketAddrs>(address: A) -> io::Result<StatsdSink> {
+        let address = address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local_address: SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local_address)?;
+        socket.connect(address)?;
+        Ok(StatsdSink {
+            socket,
+            format: StatsdFormat::DogStatsd,
+            prefix: "elb.".to_owned(),
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            flush_interval: None,
+            last_flush: Instant::now(),
+            counters: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            newest_timestamps: BTreeMap::new(),
+        })
+    }
+
+    pub fn with_format(mut self, format: StatsdFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Prefix metric names with `prefix` and a `.`.  An empty prefix sends the names as they are.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}.", prefix.trim_end_matches('.'))
+        };
+        self
+    }
+
+    /// Send datagrams of at most `max_packet_size` bytes, 1432 by default.  Metrics are
+    /// separated by newlines; a single metric longer than this is sent on its own.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Flush from [`record`](#method.record) once `interval` has passed since the last flush.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// The address metrics are sent from.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Add a record to the metrics, flushing them if the flush interval has elapsed.
+    pub fn record(&mut self, record: &ELBRecord) -> io::Result<()> {
+        let tags = Tags {
+            elb_name: record.elb_name.to_owned(),
+            status_class: record.status_class(),
+        };
+        for &(name, value) in &[("requests", 1),
+                                ("received_bytes", record.received_bytes),
+                                ("sent_bytes", record.sent_bytes)] {
+            *self.counters.entry((name, tags.clone())).or_insert(0) += value;
+        }
+        for &(name, seconds) in &[("request_processing_time", record.request_processing_time),
+                                  ("backend_processing_time", record.backend_processing_time),
+                                  ("response_processing_time",
+                                   record.response_processing_time)] {
+            if seconds >= 0.0 {
+                let sample = seconds as f64 * 1000.0;
+                self.timers
+                    .entry((name, tags.clone()))
+                    .and_modify(|summary| summary.add(sample))
+                    .or_insert_with(|| TimerSummary::new(sample));
+            }
+        }
+        let newest = self.newest_timestamps
+            .entry(tags.elb_name)
+            .or_insert(record.timestamp);
+        if record.timestamp > *newest {
+            *newest = record.timestamp;
+        }
+
+        match self.flush_interval {
+            Some(interval) if self.last_flush.elapsed() >= interval => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send the metrics aggregated since the last flush and reset the counters and timers.
+    ///
+    /// UDP gives no guarantee of delivery.  If sending fails the metrics that were not sent are
+    /// dropped rather than sent again with the next flush.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        let mut lines = Vec::new();
+        for (&(name, ref tags), &count) in &self.counters {
+            lines.push(self.line(name, &count.to_string(), "c", Some(tags), &tags.elb_name));
+        }
+        for (&(name, ref tags), summary) in &self.timers {
+            for &(suffix, value, metric_type) in &[("count", summary.count as f64, "c"),
+                                                   ("sum", summary.sum, "c"),
+                                                   ("min", summary.min, "g"),
+                                                   ("max", summary.max, "g")] {
+                lines.push(self.line(&format!("{}.{}", name, suffix),
+                                     &milliseconds(value),
+                                     metric_type,
+                                     Some(tags),
+                                     &tags.elb_name));
+            }
+        }
+        let now = UTC::now();
+        for (elb_name, newest) in &self.newest_timestamps {
+            let lag = (now - *newest).num_milliseconds() as f64 / 1000.0;
+            lines.push(self.line("lag_seconds", &lag.to_string(), "g", None, elb_name));
+        }
+        self.counters.clear();
+        self.timers.clear();
+
+        let mut packet = String::new();
+        for line in lines {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > self.max_packet_size {
+                self.socket.send(packet.as_bytes())?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.socket.send(packet.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn line(&self,
+            name: &str,
+            value: &str,
+            metric_type: &str,
+            tags: Option<&Tags>,
+            elb_name: &str)
+            -> String {
+        match self.format {
+            StatsdFormat::DogStatsd => {
+                let mut line = format!("{}{}:{}|{}|#elb_name:{}",
+                                       self.prefix,
+                                       name,
+                                       value,
+                                       metric_type,
+                                       sanitize(elb_name));
+                if let Some(tags) = tags {
+                    line.push_str(",status_class:");
+                    line.push_str(tags.status_class.as_str());
+                }
+                line
+            }
+            StatsdFormat::Plain => {
+                let status_class = tags.map_or(String::new(),
+                                               |tags| format!("{}.", tags.status_class));
+                format!("{}{}.{}{}:{}|{}",
+                        self.prefix,
+                        sanitize(elb_name),
+                        status_class,
+                        name,
+                        value,
+                        metric_type)
+            }
+        }
+    }
+}
+
+/// Format a number of milliseconds to the microsecond resolution of the ELB's timings, without
+/// trailing zeros.
+fn milliseconds(value: f64) -> String {
+    let formatted = format!("{:.3}", value);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+/// Replace the characters that have a meaning in the StatsD line format.
+fn sanitize(value: &str) -> String {
+    value.chars()
+        .map(|c| match c {
+            ':' | '|' | '#' | ',' | '@' | '.' | '\n' | ' ' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod statsd_sink_tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use parse_record;
+    use super::{StatsdFormat, StatsdSink};
+
+    const TEST_RECORDS: &[&str] =
+        &["2015-08-15T23:43:05.302180Z elb-name 172.16.1.6:54814 172.16.1.5:9000 0.000039 \
+           0.145507 0.00003 200 200 0 7582 \"GET http://some.domain.com:80/path0 HTTP/1.1\"",
+          "2015-08-15T23:43:06.000000Z elb-name 172.16.1.7:54815 172.16.1.5:9000 0.000039 \
+           0.2 0.00003 200 200 10 100 \"GET http://some.domain.com:80/path1 HTTP/1.1\"",
+          "2015-08-15T23:43:07.000000Z elb-name 172.16.1.7:54815 - -1 -1 -1 503 - 0 0 \
+           \"- - -\""];
+
+    fn listener() -> UdpSocket {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        listener
+    }
+
+    fn receive(listener: &UdpSocket) -> String {
+        let mut buffer = [0; 65536];
+        let size = listener.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..size].to_vec()).unwrap()
+    }
+
+    fn record_all(sink: &mut StatsdSink) {
+        for line in TEST_RECORDS {
+            sink.record(&parse_record(line).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn sends_tagged_dogstatsd_metrics() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap()).unwrap();
+        record_all(&mut sink);
+
+        sink.flush().unwrap();
+
+        let packet = receive(&listener);
+        let lines: Vec<&str> = packet.lines().collect();
+        assert!(lines.contains(&"elb.requests:2|c|#elb_name:elb-name,status_class:success"));
+        assert!(lines.contains(&"elb.requests:1|c|#elb_name:elb-name,\
+                                 status_class:backend_unreachable"));
+        assert!(lines.contains(&"elb.sent_bytes:7682|c|#elb_name:elb-name,status_class:success"));
+        assert!(lines.contains(&"elb.backend_processing_time.count:2|c|#elb_name:elb-name,\
+                                 status_class:success"));
+        assert!(lines.contains(&"elb.backend_processing_time.sum:345.507|c|#elb_name:elb-name,\
+                                 status_class:success"));
+        assert!(lines.contains(&"elb.backend_processing_time.min:145.507|g|#elb_name:elb-name,\
+                                 status_class:success"));
+        assert!(lines.contains(&"elb.backend_processing_time.max:200|g|#elb_name:elb-name,\
+                                 status_class:success"));
+        assert!(lines.iter().any(|line| line.starts_with("elb.lag_seconds:") &&
+                                        line.ends_with("|g|#elb_name:elb-name")));
+        assert!(!lines.iter().any(|line| line.contains(":-1000|")))
+    }
+
+    #[test]
+    fn sends_one_summary_per_timer_however_many_records_there_are() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap()).unwrap();
+        for _ in 0..100 {
+            sink.record(&parse_record(TEST_RECORDS[0]).unwrap()).unwrap();
+        }
+
+        sink.flush().unwrap();
+
+        let packet = receive(&listener);
+        assert_eq!(packet.lines()
+                       .filter(|line| line.starts_with("elb.request_processing_time."))
+                       .count(),
+                   4);
+        assert!(packet.lines()
+            .any(|line| line.starts_with("elb.request_processing_time.count:100|c|")))
+    }
+
+    #[test]
+    fn adds_tags_to_names_of_plain_statsd_metrics() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap())
+            .unwrap()
+            .with_format(StatsdFormat::Plain)
+            .with_prefix("aws.elb");
+        sink.record(&parse_record(TEST_RECORDS[0]).unwrap()).unwrap();
+
+        sink.flush().unwrap();
+
+        let packet = receive(&listener);
+        assert!(packet.lines().any(|line| line == "aws.elb.elb-name.success.requests:1|c"))
+    }
+
+    #[test]
+    fn resets_counters_after_a_flush() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap()).unwrap();
+        record_all(&mut sink);
+        sink.flush().unwrap();
+        receive(&listener);
+
+        sink.flush().unwrap();
+
+        let packet = receive(&listener);
+        assert!(packet.lines().all(|line| line.starts_with("elb.lag_seconds:")))
+    }
+
+    #[test]
+    fn splits_metrics_into_packets_of_the_maximum_size() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap())
+            .unwrap()
+            .with_max_packet_size(200);
+        record_all(&mut sink);
+
+        sink.flush().unwrap();
+
+        let first = receive(&listener);
+        let second = receive(&listener);
+        assert!(first.len() <= 200 && first.contains('\n'));
+        assert!(second.len() <= 200)
+    }
+
+    #[test]
+    fn flushes_when_the_flush_interval_has_elapsed() {
+        let listener = listener();
+        let mut sink = StatsdSink::new(listener.local_addr().unwrap())
+            .unwrap()
+            .with_flush_interval(Duration::from_secs(0));
+
+        sink.record(&parse_record(TEST_RECORDS[0]).unwrap()).unwrap();
+
+        assert!(receive(&listener)
+            .contains("elb.requests:1|c|#elb_name:elb-name,status_class:success"))
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use ELBRecord;
+
+/// The outcome of a request, derived from the ELB and backend status codes.
+///
+/// Classic ELBs pass the backend's status code through to the client, so when the backend
+/// answered, `elb_status_code` and `backend_status_code` are the same and the backend's code
+/// decides the class.  When the backend did not answer, `backend_status_code` is `-` and the
+/// response was generated by the ELB itself.  See [Troubleshoot a Classic Load Balancer: HTTP
+/// errors](https://docs.aws.amazon.com/elasticloadbalancing/latest/classic/ts-elb-error-message.html)
+/// for the conditions behind each ELB-generated status code.
+///
+/// The rules, applied in order, are:
+///
+/// 1. The backend status code is `-`, the backend address is `-` and the ELB returned a 5xx:
+///    `BackendUnreachable`.  The ELB could not send the request to any registered instance,
+///    typically an HTTP 503 because there were no healthy instances or the surge queue was full.
+/// 2. The backend status code is `-` and the ELB returned a 4xx or 5xx: `ElbError`.  The ELB
+///    rejected the request (HTTP 400, 405, 408) or gave up on the instance it sent it to (HTTP
+///    502 for a closed connection or malformed response, HTTP 504 for an idle timeout).
+/// 3. The backend returned a 5xx: `BackendError`.
+/// 4. The backend (or, without a backend status code, the ELB) returned a 4xx: `ClientError`,
+///    a 3xx: `Redirect`, a 2xx: `Success` and a 1xx: `Informational`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum StatusClass {
+    Informational,
+    Success,
+    Redirect,
+    ClientError,
+    BackendError,
+    ElbError,
+    BackendUnreachable,
+}
+
+impl StatusClass {
+    /// Classify a record using the rules described above.
+    pub fn of(record: &ELBRecord) -> StatusClass {
+        StatusClass::from_codes(record.elb_status_code,
+                                record.backend_status_code,
+                                record.backend_address.is_some())
+    }
+
+    /// Classify a response from its parts.  `backend_contacted` is `false` when the record's
+    /// backend address is `-`.
+    pub fn from_codes(elb_status_code: u16,
+                      backend_status_code: Option<u16>,
+                      backend_contacted: bool)
+                      -> StatusClass {
+        match backend_status_code {
+            None if elb_status_code >= 500 && !backend_contacted => {
+                StatusClass::BackendUnreachable
+            }
+            None if elb_status_code >= 400 => StatusClass::ElbError,
+            Some(code) if code >= 500 => StatusClass::BackendError,
+            Some(code) => StatusClass::from_status_code(code),
+            None => StatusClass::from_status_code(elb_status_code),
+        }
+    }
+
+    fn from_status_code(code: u16) -> StatusClass {
+        match code {
+            0..=199 => StatusClass::Informational,
+            200..=299 => StatusClass::Success,
+            300..=399 => StatusClass::Redirect,
+            _ => StatusClass::ClientError,
+        }
+    }
+
+    /// `true` for every class other than `Informational`, `Success` and `Redirect`.
+    pub fn is_error(&self) -> bool {
+        *self >= StatusClass::ClientError
+    }
+
+    /// A snake_case identifier suitable for metric labels and column values.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StatusClass::Informational => "informational",
+            StatusClass::Success => "success",
+            StatusClass::Redirect => "redirect",
+            StatusClass::ClientError => "client_error",
+            StatusClass::BackendError => "backend_error",
+            StatusClass::ElbError => "elb_error",
+            StatusClass::BackendUnreachable => "backend_unreachable",
+        }
+    }
+}
+
+impl Display for StatusClass {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod status_class_tests {
+    use super::StatusClass;
+
+    #[test]
+    fn classifies_backend_responses_by_the_backend_status_code() {
+        assert_eq!(StatusClass::from_codes(200, Some(200), true), StatusClass::Success);
+        assert_eq!(StatusClass::from_codes(301, Some(301), true), StatusClass::Redirect);
+        assert_eq!(StatusClass::from_codes(404, Some(404), true), StatusClass::ClientError);
+        assert_eq!(StatusClass::from_codes(101, Some(101), true),
+                   StatusClass::Informational)
+    }
+
+    #[test]
+    fn classifies_a_backend_5xx_as_a_backend_error() {
+        assert_eq!(StatusClass::from_codes(500, Some(500), true),
+                   StatusClass::BackendError)
+    }
+
+    #[test]
+    fn classifies_an_elb_5xx_without_a_backend_status_as_an_elb_error() {
+        assert_eq!(StatusClass::from_codes(502, None, true), StatusClass::ElbError);
+        assert_eq!(StatusClass::from_codes(504, None, true), StatusClass::ElbError)
+    }
+
+    #[test]
+    fn classifies_an_elb_4xx_without_a_backend_status_as_an_elb_error() {
+        assert_eq!(StatusClass::from_codes(408, None, false), StatusClass::ElbError)
+    }
+
+    #[test]
+    fn classifies_an_elb_5xx_without_a_backend_as_backend_unreachable() {
+        assert_eq!(StatusClass::from_codes(503, None, false),
+                   StatusClass::BackendUnreachable)
+    }
+
+    #[test]
+    fn reports_only_error_classes_as_errors() {
+        assert!(!StatusClass::Redirect.is_error());
+        assert!(StatusClass::ClientError.is_error());
+        assert!(StatusClass::BackendUnreachable.is_error())
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/// The SSL/TLS protocol negotiated between the client and the ELB.
+///
+/// Protocols other than the ones below, e.g. ones AWS adds later, are kept, as they appear in the
+/// record, in the `Other` variant.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum TlsProtocol<'a> {
+    SslV3,
+    TlsV1,
+    TlsV1_1,
+    TlsV1_2,
+    TlsV1_3,
+    Other(&'a str),
+}
+
+impl<'a> TlsProtocol<'a> {
+    /// The protocol as it is written in an ELB access log record.
+    pub fn as_str(&self) -> &'a str {
+        match *self {
+            TlsProtocol::SslV3 => "SSLv3",
+            TlsProtocol::TlsV1 => "TLSv1",
+            TlsProtocol::TlsV1_1 => "TLSv1.1",
+            TlsProtocol::TlsV1_2 => "TLSv1.2",
+            TlsProtocol::TlsV1_3 => "TLSv1.3",
+            TlsProtocol::Other(protocol) => protocol,
+        }
+    }
+
+    /// `true` for the protocols deprecated by RFC 7568 and RFC 8996, that is, anything older
+    /// than TLS 1.2.  Unrecognized protocols are not reported as legacy.
+    pub fn is_legacy(&self) -> bool {
+        match *self {
+            TlsProtocol::SslV3 | TlsProtocol::TlsV1 | TlsProtocol::TlsV1_1 => true,
+            TlsProtocol::TlsV1_2 | TlsProtocol::TlsV1_3 | TlsProtocol::Other(_) => false,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for TlsProtocol<'a> {
+    fn from(protocol: &'a str) -> TlsProtocol<'a> {
+        match protocol {
+            "SSLv3" => TlsProtocol::SslV3,
+            "TLSv1" => TlsProtocol::TlsV1,
+            "TLSv1.1" => TlsProtocol::TlsV1_1,
+            "TLSv1.2" => TlsProtocol::TlsV1_2,
+            "TLSv1.3" => TlsProtocol::TlsV1_3,
+            _ => TlsProtocol::Other(protocol),
+        }
+    }
+}
+
+impl<'a> Display for TlsProtocol<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// How safe a cipher suite is considered to be.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum CipherStrength {
+    /// Broken or trivially breakable: RC4, single DES, export grade, NULL encryption, MD5 MACs
+    /// and anonymous key exchange.
+    Weak,
+    /// Not broken but phased out of current ELB security policies: 3DES and CBC mode suites.
+    Deprecated,
+    /// AEAD suites (AES-GCM, AES-CCM and ChaCha20-Poly1305).
+    Strong,
+}
+
+/// The cipher suite negotiated between the client and the ELB, identified by its OpenSSL name
+/// (e.g. `ECDHE-RSA-AES128-GCM-SHA256`) as it appears in the record.
+///
+/// The classification is derived from the name alone so cipher suites AWS adds later are still
+/// classified.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct CipherSuite<'a> {
+    name: &'a str,
+}
+
+impl<'a> CipherSuite<'a> {
+    /// The OpenSSL name of the cipher suite.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// `true` when the key exchange is ephemeral (ECDHE or DHE).  Every TLS 1.3 suite provides
+    /// forward secrecy.
+    pub fn has_forward_secrecy(&self) -> bool {
+        self.is_tls13() || self.name.starts_with("ECDHE-") || self.name.starts_with("DHE-") ||
+        self.name.starts_with("EDH-")
+    }
+
+    pub fn strength(&self) -> CipherStrength {
+        const WEAK_COMPONENTS: [&str; 6] = ["RC4", "NULL", "EXP", "MD5", "ADH", "AECDH"];
+        const AEAD_COMPONENTS: [&str; 3] = ["GCM", "CCM", "CHACHA20"];
+
+        let name = self.name;
+        let has_component =
+            |component: &str| name.split(['-', '_']).any(|part| part.starts_with(component));
+        let is_single_des = name.contains("DES-CBC-") || name.ends_with("DES-CBC");
+
+        if WEAK_COMPONENTS.iter().any(|c| has_component(c)) || is_single_des {
+            CipherStrength::Weak
+        } else if self.is_tls13() || AEAD_COMPONENTS.iter().any(|c| has_component(c)) {
+            CipherStrength::Strong
+        } else {
+            CipherStrength::Deprecated
+        }
+    }
+
+    /// Shorthand for `strength() == CipherStrength::Weak`.
+    pub fn is_weak(&self) -> bool {
+        self.strength() == CipherStrength::Weak
+    }
+
+    /// Shorthand for `strength() == CipherStrength::Deprecated`.
+    pub fn is_deprecated(&self) -> bool {
+        self.strength() == CipherStrength::Deprecated
+    }
+
+    fn is_tls13(&self) -> bool {
+        self.name.starts_with("TLS_")
+    }
+}
+
+impl<'a> From<&'a str> for CipherSuite<'a> {
+    fn from(name: &'a str) -> CipherSuite<'a> {
+        CipherSuite { name }
+    }
+}
+
+impl<'a> Display for CipherSuite<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tls_protocol_tests {
+    use super::TlsProtocol;
+
+    #[test]
+    fn returns_the_matching_variant_for_every_protocol_recorded_by_an_elb() {
+        assert_eq!(TlsProtocol::from("TLSv1"), TlsProtocol::TlsV1);
+        assert_eq!(TlsProtocol::from("TLSv1.1"), TlsProtocol::TlsV1_1);
+        assert_eq!(TlsProtocol::from("TLSv1.2"), TlsProtocol::TlsV1_2);
+        assert_eq!(TlsProtocol::from("TLSv1.3"), TlsProtocol::TlsV1_3)
+    }
+
+    #[test]
+    fn returns_other_for_an_unrecognized_protocol() {
+        assert_eq!(TlsProtocol::from("TLSv2"), TlsProtocol::Other("TLSv2"));
+        assert_eq!(TlsProtocol::Other("TLSv2").as_str(), "TLSv2")
+    }
+
+    #[test]
+    fn reports_protocols_older_than_tls_1_2_as_legacy() {
+        assert!(TlsProtocol::TlsV1_1.is_legacy());
+        assert!(!TlsProtocol::TlsV1_2.is_legacy());
+        assert!(!TlsProtocol::Other("TLSv2").is_legacy())
+    }
+}
+
+#[cfg(test)]
+mod cipher_suite_tests {
+    use super::CipherStrength;
+    use super::CipherSuite;
+
+    #[test]
+    fn classifies_ecdhe_gcm_suites_as_strong_with_forward_secrecy() {
+        let cipher = CipherSuite::from("ECDHE-RSA-AES128-GCM-SHA256");
+
+        assert_eq!((cipher.strength(), cipher.has_forward_secrecy()),
+                   (CipherStrength::Strong, true))
+    }
+
+    #[test]
+    fn classifies_tls13_suites_as_strong_with_forward_secrecy() {
+        let cipher = CipherSuite::from("TLS_AES_256_GCM_SHA384");
+
+        assert_eq!((cipher.strength(), cipher.has_forward_secrecy()),
+                   (CipherStrength::Strong, true))
+    }
+
+    #[test]
+    fn classifies_rsa_cbc_suites_as_deprecated_without_forward_secrecy() {
+        let cipher = CipherSuite::from("AES128-SHA");
+
+        assert_eq!((cipher.strength(), cipher.has_forward_secrecy()),
+                   (CipherStrength::Deprecated, false))
+    }
+
+    #[test]
+    fn classifies_3des_as_deprecated() {
+        assert!(CipherSuite::from("DES-CBC3-SHA").is_deprecated())
+    }
+
+    #[test]
+    fn classifies_rc4_single_des_and_export_suites_as_weak() {
+        assert!(CipherSuite::from("RC4-SHA").is_weak());
+        assert!(CipherSuite::from("EDH-RSA-DES-CBC-SHA").is_weak());
+        assert!(CipherSuite::from("EXP-RC4-MD5").is_weak())
+    }
+}
@@ -0,0 +1,447 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/// A structured view of the request URL recorded by the ELB.
+///
+/// Every component borrows from the record so parsing the URL does not allocate.  Use
+/// [`percent_decode`](fn.percent_decode.html), [`path_segments`](#method.path_segments) or
+/// [`query_params`](#method.query_params) when decoded values are needed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct RequestUrl<'a> {
+    /// `None` for origin-form URLs such as `/path?query`.
+    pub scheme: Option<&'a str>,
+    /// `None` for origin-form URLs.  IPv6 hosts keep their brackets.
+    pub host: Option<&'a str>,
+    pub port: Option<u16>,
+    /// The raw (still percent-encoded) path.  Empty when the URL has an authority but no path.
+    pub path: &'a str,
+    /// The raw query string without the leading `?`.
+    pub query: Option<&'a str>,
+    /// The raw fragment without the leading `#`.
+    pub fragment: Option<&'a str>,
+}
+
+impl<'a> RequestUrl<'a> {
+    /// Attempt to split a URL into its components.
+    ///
+    /// Both absolute URLs (`scheme://host[:port]/path`, the form ELBs record for HTTP
+    /// listeners) and origin-form URLs (`/path`) are accepted.
+    pub fn parse(url: &'a str) -> Result<RequestUrl<'a>, UrlParseError> {
+        let (scheme, host, port, rest) = match url.find("://") {
+            Some(scheme_end) => {
+                let scheme = &url[..scheme_end];
+                if scheme.is_empty() ||
+                   !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+                    return Err(UrlParseError::InvalidScheme);
+                }
+                let after_scheme = &url[scheme_end + 3..];
+                let authority_end = after_scheme.find(['/', '?', '#'])
+                    .unwrap_or(after_scheme.len());
+                let (host, port) = split_authority(&after_scheme[..authority_end])?;
+                (Some(scheme), Some(host), port, &after_scheme[authority_end..])
+            }
+            None if url.starts_with('/') => (None, None, None, url),
+            None => return Err(UrlParseError::RelativeUrl),
+        };
+
+        let (rest, fragment) = match rest.find('#') {
+            Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+            None => (rest, None),
+        };
+        let (path, query) = match rest.find('?') {
+            Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+            None => (rest, None),
+        };
+
+        Ok(RequestUrl {
+            scheme,
+            host,
+            port,
+            path,
+            query,
+            fragment,
+        })
+    }
+
+    /// An iterator over the percent-decoded, non-empty segments of the path.
+    pub fn path_segments(&self) -> PathSegments<'a> {
+        PathSegments { segments: self.path.split('/') }
+    }
+
+    /// An iterator over the percent-decoded name/value pairs of the query string.
+    ///
+    /// `+` is decoded as a space, as it is for `application/x-www-form-urlencoded` data.  A
+    /// parameter without `=` is returned with an empty value.
+    pub fn query_params(&self) -> QueryParams<'a> {
+        QueryParams { pairs: self.query.unwrap_or("").split('&') }
+    }
+
+    /// The decoded value of the first query parameter named `name`.
+    pub fn query_param(&self, name: &str) -> Option<Cow<'a, str>> {
+        self.query_params().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+fn split_authority(authority: &str) -> Result<(&str, Option<u16>), UrlParseError> {
+    // Credentials are never meaningful in an access log so they are skipped.
+    let authority = match authority.rfind('@') {
+        Some(idx) => &authority[idx + 1..],
+        None => authority,
+    };
+    let host_end = if authority.starts_with('[') {
+        match authority.find(']') {
+            Some(idx) => idx + 1,
+            None => return Err(UrlParseError::InvalidHost),
+        }
+    } else {
+        authority.find(':').unwrap_or(authority.len())
+    };
+    let host = &authority[..host_end];
+    if host.is_empty() {
+        return Err(UrlParseError::InvalidHost);
+    }
+
+    match &authority[host_end..] {
+        "" => Ok((host, None)),
+        port if port.starts_with(':') => {
+            match port[1..].parse() {
+                Ok(port) => Ok((host, Some(port))),
+                Err(_) => Err(UrlParseError::InvalidPort),
+            }
+        }
+        _ => Err(UrlParseError::InvalidHost),
+    }
+}
+
+/// Decode `%XX` escapes.
+///
+/// Malformed escapes are kept as they are and decoded bytes that are not valid UTF-8 are
+/// replaced with `U+FFFD`.  The input is returned without allocating when there is nothing to
+/// decode.
+pub fn percent_decode(encoded: &str) -> Cow<'_, str> {
+    decode(encoded, false)
+}
+
+fn decode(encoded: &str, plus_as_space: bool) -> Cow<'_, str> {
+    if !encoded.contains('%') && (!plus_as_space || !encoded.contains('+')) {
+        return Cow::Borrowed(encoded);
+    }
+
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'%' if idx + 2 < bytes.len() => {
+                match (hex_value(bytes[idx + 1]), hex_value(bytes[idx + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        idx += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        idx += 1;
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Iterator returned by [`RequestUrl::path_segments`](struct.RequestUrl.html#method.path_segments).
+pub struct PathSegments<'a> {
+    segments: ::std::str::Split<'a, char>,
+}
+
+impl<'a> Iterator for PathSegments<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        self.segments.by_ref().find(|segment| !segment.is_empty()).map(percent_decode)
+    }
+}
+
+/// Iterator returned by [`RequestUrl::query_params`](struct.RequestUrl.html#method.query_params).
+pub struct QueryParams<'a> {
+    pairs: ::std::str::Split<'a, char>,
+}
+
+impl<'a> Iterator for QueryParams<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<(Cow<'a, str>, Cow<'a, str>)> {
+        self.pairs.by_ref().find(|pair| !pair.is_empty()).map(|pair| {
+            match pair.find('=') {
+                Some(idx) => (decode(&pair[..idx], true), decode(&pair[idx + 1..], true)),
+                None => (decode(pair, true), Cow::Borrowed("")),
+            }
+        })
+    }
+}
+
+/// Returned when a request URL cannot be split into its components.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UrlParseError {
+    /// The URL is neither absolute nor starts with `/`.
+    RelativeUrl,
+    /// The scheme is empty or contains characters not allowed by RFC 3986.
+    InvalidScheme,
+    /// The host is empty or is followed by something other than a port.
+    InvalidHost,
+    /// The port is not a number between 0 and 65535.
+    InvalidPort,
+}
+
+impl Display for UrlParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            UrlParseError::RelativeUrl => write!(f, "URL is neither absolute nor origin-form."),
+            UrlParseError::InvalidScheme => write!(f, "URL scheme is invalid."),
+            UrlParseError::InvalidHost => write!(f, "URL host is invalid."),
+            UrlParseError::InvalidPort => write!(f, "URL port is invalid."),
+        }
+    }
+}
+
+impl Error for UrlParseError {
+    fn description(&self) -> &str {
+        match *self {
+            UrlParseError::RelativeUrl => "relative URL",
+            UrlParseError::InvalidScheme => "invalid URL scheme",
+            UrlParseError::InvalidHost => "invalid URL host",
+            UrlParseError::InvalidPort => "invalid URL port",
+        }
+    }
+}
+
+/// The kinds of path segments a [`PathTemplater`](struct.PathTemplater.html) can collapse.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentPattern {
+    /// Segments made up entirely of ASCII digits, e.g. `12345`.
+    Numeric,
+    /// Hyphenated UUIDs, e.g. `123e4567-e89b-12d3-a456-426655440000`.
+    Uuid,
+    /// Hexadecimal segments of at least `min_len` characters, e.g. object IDs or hashes.
+    Hex { min_len: usize },
+}
+
+impl SegmentPattern {
+    fn matches(&self, segment: &str) -> bool {
+        match *self {
+            SegmentPattern::Numeric => {
+                !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit())
+            }
+            SegmentPattern::Uuid => {
+                segment.len() == 36 &&
+                segment.char_indices().all(|(idx, c)| match idx {
+                    8 | 13 | 18 | 23 => c == '-',
+                    _ => c.is_ascii_hexdigit(),
+                })
+            }
+            SegmentPattern::Hex { min_len } => {
+                segment.len() >= min_len && segment.bytes().all(|b| b.is_ascii_hexdigit())
+            }
+        }
+    }
+}
+
+/// Rewrites request paths into endpoint templates so traffic can be grouped by endpoint.
+///
+/// Each path segment is checked against the rules in the order they were added and the first
+/// matching rule replaces the segment with its placeholder.  The default templater collapses
+/// numeric segments into `{id}` and UUIDs into `{uuid}`, so `/users/42/orders` becomes
+/// `/users/{id}/orders`.
+#[derive(Debug, Clone)]
+pub struct PathTemplater {
+    rules: Vec<(SegmentPattern, String)>,
+}
+
+impl PathTemplater {
+    /// A templater without any rules.
+    pub fn new() -> PathTemplater {
+        PathTemplater { rules: Vec::new() }
+    }
+
+    /// Add a rule replacing segments matching `pattern` with `placeholder`.
+    pub fn with_rule<S: Into<String>>(mut self, pattern: SegmentPattern, placeholder: S) -> Self {
+        self.rules.push((pattern, placeholder.into()));
+        self
+    }
+
+    /// The templated form of `path`.  The path is borrowed when no segment matched a rule.
+    pub fn template<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let needs_templating = path.split('/').any(|segment| self.placeholder(segment).is_some());
+        if !needs_templating {
+            return Cow::Borrowed(path);
+        }
+
+        let templated: Vec<&str> = path.split('/')
+            .map(|segment| self.placeholder(segment).unwrap_or(segment))
+            .collect();
+        Cow::Owned(templated.join("/"))
+    }
+
+    fn placeholder(&self, segment: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(segment))
+            .map(|(_, placeholder)| placeholder.as_str())
+    }
+}
+
+impl Default for PathTemplater {
+    fn default() -> PathTemplater {
+        PathTemplater::new()
+            .with_rule(SegmentPattern::Numeric, "{id}")
+            .with_rule(SegmentPattern::Uuid, "{uuid}")
+    }
+}
+
+#[cfg(test)]
+mod request_url_tests {
+    use super::RequestUrl;
+    use super::UrlParseError;
+
+    const TEST_URL: &str = "http://some.domain.com:80/path0/path1?param0=p0&param1=p1";
+
+    #[test]
+    fn returns_the_components_of_an_absolute_url() {
+        let url = RequestUrl::parse(TEST_URL).unwrap();
+
+        assert_eq!(url,
+                   RequestUrl {
+                       scheme: Some("http"),
+                       host: Some("some.domain.com"),
+                       port: Some(80),
+                       path: "/path0/path1",
+                       query: Some("param0=p0&param1=p1"),
+                       fragment: None,
+                   })
+    }
+
+    #[test]
+    fn returns_the_components_of_an_origin_form_url() {
+        let url = RequestUrl::parse("/path0?param0=p0#top").unwrap();
+
+        assert_eq!((url.scheme, url.host, url.path, url.query, url.fragment),
+                   (None, None, "/path0", Some("param0=p0"), Some("top")))
+    }
+
+    #[test]
+    fn returns_an_ipv6_host_with_its_brackets() {
+        let url = RequestUrl::parse("https://[2001:db8::1]:443/").unwrap();
+
+        assert_eq!((url.host, url.port), (Some("[2001:db8::1]"), Some(443)))
+    }
+
+    #[test]
+    fn returns_an_empty_path_when_the_url_has_none() {
+        let url = RequestUrl::parse("https://some.domain.com:443?param0=p0").unwrap();
+
+        assert_eq!((url.path, url.query), ("", Some("param0=p0")))
+    }
+
+    #[test]
+    fn returns_an_error_for_an_invalid_port() {
+        assert_eq!(RequestUrl::parse("http://some.domain.com:http/"),
+                   Err(UrlParseError::InvalidPort))
+    }
+
+    #[test]
+    fn returns_an_error_for_a_relative_url() {
+        assert_eq!(RequestUrl::parse("-"), Err(UrlParseError::RelativeUrl))
+    }
+
+    #[test]
+    fn returns_the_decoded_non_empty_path_segments() {
+        let url = RequestUrl::parse("http://some.domain.com/a%20b//c/").unwrap();
+
+        assert_eq!(url.path_segments().collect::<Vec<_>>(), vec!["a b", "c"])
+    }
+
+    #[test]
+    fn returns_the_decoded_query_params() {
+        let url = RequestUrl::parse("/search?q=elb+logs&tag=a%26b&flag").unwrap();
+
+        assert_eq!(url.query_params().collect::<Vec<_>>(),
+                   vec![("q".into(), "elb logs".into()),
+                        ("tag".into(), "a&b".into()),
+                        ("flag".into(), "".into())])
+    }
+
+    #[test]
+    fn returns_the_first_query_param_with_a_name() {
+        let url = RequestUrl::parse(TEST_URL).unwrap();
+
+        assert_eq!(url.query_param("param1").unwrap(), "p1")
+    }
+}
+
+#[cfg(test)]
+mod percent_decode_tests {
+    use super::percent_decode;
+    use std::borrow::Cow;
+
+    #[test]
+    fn borrows_the_input_when_there_is_nothing_to_decode() {
+        match percent_decode("/path0+path1") {
+            Cow::Borrowed(decoded) => assert_eq!(decoded, "/path0+path1"),
+            Cow::Owned(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn decodes_multi_byte_characters() {
+        assert_eq!(percent_decode("caf%C3%A9"), "café")
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%zz%4"), "100%zz%4")
+    }
+}
+
+#[cfg(test)]
+mod path_templater_tests {
+    use super::PathTemplater;
+    use super::SegmentPattern;
+
+    #[test]
+    fn collapses_numeric_ids_and_uuids_by_default() {
+        let templater = PathTemplater::default();
+
+        assert_eq!(templater.template("/users/42/orders/123e4567-e89b-12d3-a456-426655440000"),
+                   "/users/{id}/orders/{uuid}")
+    }
+
+    #[test]
+    fn leaves_paths_without_ids_unchanged() {
+        assert_eq!(PathTemplater::default().template("/path0/path1"), "/path0/path1")
+    }
+
+    #[test]
+    fn applies_the_first_matching_rule() {
+        let templater = PathTemplater::new()
+            .with_rule(SegmentPattern::Hex { min_len: 8 }, "{hash}")
+            .with_rule(SegmentPattern::Numeric, "{id}");
+
+        assert_eq!(templater.template("/blobs/12345678/1"), "/blobs/{hash}/{id}")
+    }
+}
@@ -0,0 +1,223 @@
+//! Classification of the user agents recorded in V2 ELB records.
+//!
+//! Classification is driven by a small rule set, embedded from `rules.txt`, that maps tokens
+//! found in a user agent to bot, browser, operating system and device families.  The embedded
+//! rules can be replaced without a new release of ELP by loading an updated rule file with
+//! [`UserAgentClassifier::from_rules`](struct.UserAgentClassifier.html#method.from_rules).
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/// The rules used by `UserAgentClassifier::default()`.
+pub const DEFAULT_RULES: &str = include_str!("rules.txt");
+
+/// The classification of a single user agent.
+///
+/// Each family is `None` when no rule of its category matched.  The families borrow from the
+/// classifier that produced them.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct UserAgentInfo<'r> {
+    /// The bot or crawler that sent the request.
+    pub bot: Option<&'r str>,
+    pub browser: Option<&'r str>,
+    pub os: Option<&'r str>,
+    pub device: Option<&'r str>,
+}
+
+impl<'r> UserAgentInfo<'r> {
+    /// `true` when the user agent matched a bot rule.
+    pub fn is_bot(&self) -> bool {
+        self.bot.is_some()
+    }
+}
+
+/// Classifies user agents using a rule set.
+#[derive(Debug, Clone)]
+pub struct UserAgentClassifier {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    category: Category,
+    family: String,
+    token: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Category {
+    Bot,
+    Browser,
+    Os,
+    Device,
+}
+
+impl UserAgentClassifier {
+    /// Build a classifier from rules in the format of the embedded `rules.txt`: one tab
+    /// separated `<category> <family> <token>` rule per line, where the category is one of
+    /// `bot`, `browser`, `os` or `device`.  Blank lines and lines starting with `#` are
+    /// ignored.
+    pub fn from_rules(rules: &str) -> Result<UserAgentClassifier, RuleParseError> {
+        let mut parsed_rules = Vec::new();
+        for (idx, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+            if fields.len() != 3 || fields[1].is_empty() || fields[2].is_empty() {
+                return Err(RuleParseError::MalformedRule { line: idx + 1 });
+            }
+            let category = match fields[0] {
+                "bot" => Category::Bot,
+                "browser" => Category::Browser,
+                "os" => Category::Os,
+                "device" => Category::Device,
+                _ => return Err(RuleParseError::UnknownCategory { line: idx + 1 }),
+            };
+            parsed_rules.push(Rule {
+                category,
+                family: fields[1].to_owned(),
+                token: fields[2].to_ascii_lowercase(),
+            });
+        }
+
+        Ok(UserAgentClassifier { rules: parsed_rules })
+    }
+
+    /// Classify a user agent.  The `-` recorded for V1 records and requests without a user
+    /// agent matches no rules.
+    pub fn classify(&self, user_agent: &str) -> UserAgentInfo<'_> {
+        let mut info = UserAgentInfo::default();
+        if user_agent == "-" {
+            return info;
+        }
+
+        let user_agent = user_agent.to_ascii_lowercase();
+        for rule in &self.rules {
+            let family = match rule.category {
+                Category::Bot => &mut info.bot,
+                Category::Browser => &mut info.browser,
+                Category::Os => &mut info.os,
+                Category::Device => &mut info.device,
+            };
+            if family.is_none() && user_agent.contains(rule.token.as_str()) {
+                *family = Some(rule.family.as_str());
+            }
+        }
+        info
+    }
+}
+
+impl Default for UserAgentClassifier {
+    fn default() -> UserAgentClassifier {
+        UserAgentClassifier::from_rules(DEFAULT_RULES).expect("the embedded rules are valid")
+    }
+}
+
+/// Returned when a rule set cannot be parsed.  Line numbers start at 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RuleParseError {
+    /// The line does not have exactly three non-empty, tab separated fields.
+    MalformedRule { line: usize },
+    /// The category is not one of `bot`, `browser`, `os` or `device`.
+    UnknownCategory { line: usize },
+}
+
+impl Display for RuleParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            RuleParseError::MalformedRule { line } => {
+                write!(f, "Rule on line {} is malformed.", line)
+            }
+            RuleParseError::UnknownCategory { line } => {
+                write!(f, "Rule on line {} has an unknown category.", line)
+            }
+        }
+    }
+}
+
+impl Error for RuleParseError {
+    fn description(&self) -> &str {
+        match *self {
+            RuleParseError::MalformedRule { .. } => "malformed rule",
+            RuleParseError::UnknownCategory { .. } => "unknown rule category",
+        }
+    }
+}
+
+#[cfg(test)]
+mod user_agent_classifier_tests {
+    use super::RuleParseError;
+    use super::UserAgentClassifier;
+    use super::UserAgentInfo;
+
+    #[test]
+    fn classifies_a_desktop_browser() {
+        let classifier = UserAgentClassifier::default();
+
+        let info = classifier.classify("Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+                                        AppleWebKit/537.36 (KHTML, like Gecko) \
+                                        Chrome/58.0.3029.110 Safari/537.36");
+
+        assert_eq!(info,
+                   UserAgentInfo {
+                       bot: None,
+                       browser: Some("Chrome"),
+                       os: Some("Windows"),
+                       device: Some("Desktop"),
+                   })
+    }
+
+    #[test]
+    fn classifies_a_mobile_browser() {
+        let classifier = UserAgentClassifier::default();
+
+        let info = classifier.classify("Mozilla/5.0 (iPhone; CPU iPhone OS 10_3 like Mac OS X) \
+                                        AppleWebKit/603.1.30 (KHTML, like Gecko) \
+                                        Version/10.0 Mobile/14E277 Safari/602.1");
+
+        assert_eq!((info.browser, info.os, info.device),
+                   (Some("Safari"), Some("iOS"), Some("Mobile")))
+    }
+
+    #[test]
+    fn flags_crawlers_as_bots() {
+        let classifier = UserAgentClassifier::default();
+
+        let info = classifier.classify("Mozilla/5.0 (compatible; Googlebot/2.1; \
+                                        +http://www.google.com/bot.html)");
+
+        assert_eq!((info.is_bot(), info.bot), (true, Some("Googlebot")))
+    }
+
+    #[test]
+    fn classifies_an_undefined_user_agent_as_nothing() {
+        assert_eq!(UserAgentClassifier::default().classify("-"), UserAgentInfo::default())
+    }
+
+    #[test]
+    fn uses_the_first_matching_rule_of_each_category() {
+        let classifier = UserAgentClassifier::from_rules("browser\tInternal\tmy-app\n\
+                                                          browser\tOther\tapp\n")
+            .unwrap();
+
+        assert_eq!(classifier.classify("MY-APP/1.0").browser, Some("Internal"))
+    }
+
+    #[test]
+    fn returns_an_error_referencing_the_line_of_a_malformed_rule() {
+        let rules = "# comment\n\nbrowser\tChrome\n";
+
+        assert_eq!(UserAgentClassifier::from_rules(rules).unwrap_err(),
+                   RuleParseError::MalformedRule { line: 3 })
+    }
+
+    #[test]
+    fn returns_an_error_for_an_unknown_category() {
+        assert_eq!(UserAgentClassifier::from_rules("robot\tR2\tbeep").unwrap_err(),
+                   RuleParseError::UnknownCategory { line: 1 })
+    }
+}